@@ -1,5 +1,4 @@
 use shared::*;
-use rand::Rng;
 use crate::behaviours;
 
 pub struct RegularEnemyAi {
@@ -32,9 +31,7 @@ impl Ai<RegularEnemyState> for RegularEnemyAi {
 
                 // maybe engage in an attack
                 if keep_dist_res == behaviours::KeepDistanceResult::InDistance {
-                    let mut rng = rand::thread_rng();
-
-                    let random = rng.gen::<f32>();
+                    let random = ai_data.random.next_f32();
 
 
                     if random > 0.99 {
@@ -50,7 +47,7 @@ impl Ai<RegularEnemyState> for RegularEnemyAi {
 
                 match attack_res {
                     behaviours::AttackResult::ClosingDistance => {},
-                    behaviours::AttackResult::Attacking => {
+                    behaviours::AttackResult::Attacking | behaviours::AttackResult::Exhausted => {
                         ai_data.current_behaviour = shared::Behaviour::KeepDistance;
                     },
 
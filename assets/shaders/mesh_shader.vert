@@ -2,8 +2,8 @@
 
 layout (location = 0) in vec3 Position;
 layout (location = 1) in vec3 Normal;
-layout (location = 2) in vec2 BoneWeights;
-layout (location = 3) in vec2 BoneIndices;
+layout (location = 2) in vec4 BoneWeights;
+layout (location = 3) in vec4 BoneIndices;
 layout (location = 4) in vec2 TexCord;
 
 out VS_OUTPUT {
@@ -13,7 +13,8 @@ out VS_OUTPUT {
 } OUT;
 
 
-uniform mat4 uBones[32];
+// 4 texels pr bone, one for each column
+uniform samplerBuffer uBones;
 
 uniform mat4 model;
 uniform mat4 view;
@@ -21,6 +22,12 @@ uniform mat4 projection;
 uniform sampler2D Texture;
 
 
+mat4 bone(int index) {
+  int i = index * 4;
+  return mat4(texelFetch(uBones, i), texelFetch(uBones, i + 1), texelFetch(uBones, i + 2), texelFetch(uBones, i + 3));
+}
+
+
 mat4 boneTransform() {
 
   if(int(BoneIndices.x) < 0)
@@ -30,9 +37,11 @@ mat4 boneTransform() {
   }
   mat4 ret;
 
-  // Weight1 * Bone1 + Weight2 * Bone2
-  ret = BoneWeights.x * uBones[int(BoneIndices.x)]
-       + BoneWeights.y * uBones[int(BoneIndices.y)];
+  // Weight1 * Bone1 + ... + Weight4 * Bone4, same as skinning::skin_matrix
+  ret = BoneWeights.x * bone(int(BoneIndices.x))
+       + BoneWeights.y * bone(int(BoneIndices.y))
+       + BoneWeights.z * bone(int(BoneIndices.z))
+       + BoneWeights.w * bone(int(BoneIndices.w));
 
   return ret;
 
@@ -2,7 +2,7 @@ use nalgebra as na;
 
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum CameraMode {
     Follow,
     Free
@@ -0,0 +1,232 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+
+pub const ASSET_ROOT_ENV: &str = "GAME_ASSET_ROOT";
+pub const CONFIG_FILE: &str = "game_config.ron";
+
+const DEFAULT_SCENE: &str = "scenes/default.ron";
+const DEFAULT_TICK_RATE: u32 = 60;
+const DEFAULT_MAX_TICKS_PER_FRAME: usize = 5;
+
+
+// The asset root is the folder containing assets/ and blender_models/, normally the repo root.
+// It is taken from, in order: --asset-root <path>, the GAME_ASSET_ROOT env var,
+// asset_root in game_config.ron (current dir or next to the executable), and lastly the current dir
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub asset_root: PathBuf,
+    pub scene: String,
+    // --headless <ticks> runs the simulation that many ticks without a window
+    pub headless_ticks: Option<usize>,
+    // simulation ticks pr second, and how many ticks a single frame may run to catch up
+    pub tick_rate: u32,
+    pub max_ticks_per_frame: usize,
+    // terrain seed, overrides the seed in the scene file. The scene file seed is used when not set
+    pub seed: Option<u32>,
+    // --record <file> writes the inputs of every tick to the file when the game quits
+    pub record: Option<PathBuf>,
+    // --replay <file> plays a recorded file instead of using the controls
+    pub replay: Option<PathBuf>,
+}
+
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    asset_root: Option<String>,
+    #[serde(default)]
+    scene: Option<String>,
+    #[serde(default)]
+    tick_rate: Option<u32>,
+    #[serde(default)]
+    max_ticks_per_frame: Option<usize>,
+    #[serde(default)]
+    seed: Option<u32>,
+}
+
+
+impl Config {
+
+    pub fn load() -> Config {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        let env_root = std::env::var(ASSET_ROOT_ENV).ok();
+
+        let file = load_config_file();
+
+        let default_root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+
+        let config = Config::from_sources(&args, env_root, file, default_root);
+
+        println!("Asset root {:?}", config.asset_root);
+        config
+    }
+
+    fn from_sources(args: &[String], env_root: Option<String>, file: ConfigFile, default_root: PathBuf) -> Config {
+
+        let asset_root = arg_value(args, "--asset-root")
+            .or(env_root)
+            .or(file.asset_root)
+            .map(PathBuf::from)
+            .unwrap_or(default_root);
+
+        let scene = arg_value(args, "--scene")
+            .or(file.scene)
+            .unwrap_or(DEFAULT_SCENE.to_string());
+
+        let headless_ticks = arg_value(args, "--headless")
+            .and_then(|ticks| ticks.parse().ok());
+
+        let tick_rate = arg_value(args, "--tick-rate")
+            .and_then(|rate| rate.parse().ok())
+            .or(file.tick_rate)
+            .filter(|rate| *rate > 0)
+            .unwrap_or(DEFAULT_TICK_RATE);
+
+        let max_ticks_per_frame = file.max_ticks_per_frame.unwrap_or(DEFAULT_MAX_TICKS_PER_FRAME);
+
+        let seed = arg_value(args, "--seed")
+            .and_then(|seed| seed.parse().ok())
+            .or(file.seed);
+
+        let record = arg_value(args, "--record").map(PathBuf::from);
+
+        let replay = arg_value(args, "--replay").map(PathBuf::from);
+
+        Config {
+            asset_root,
+            scene,
+            headless_ticks,
+            tick_rate,
+            max_ticks_per_frame,
+            seed,
+            record,
+            replay
+        }
+    }
+
+    // Default scene in the given asset root
+    pub fn from_asset_root(asset_root: PathBuf) -> Config {
+        Config {
+            asset_root,
+            scene: DEFAULT_SCENE.to_string(),
+            headless_ticks: None,
+            tick_rate: DEFAULT_TICK_RATE,
+            max_ticks_per_frame: DEFAULT_MAX_TICKS_PER_FRAME,
+            seed: None,
+            record: None,
+            replay: None
+        }
+    }
+
+    // assets/ with shaders, scenes and textures
+    pub fn assets_path(&self) -> PathBuf {
+        self.asset_root.join("assets")
+    }
+
+    // blender_models/ with the .glb files
+    pub fn models_path(&self) -> PathBuf {
+        self.asset_root.join("blender_models")
+    }
+}
+
+
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+
+fn load_config_file() -> ConfigFile {
+
+    let mut candidates = vec![PathBuf::from(CONFIG_FILE)];
+
+    if let Some(exe_dir) = std::env::current_exe().ok().as_ref().and_then(|p| p.parent()) {
+        candidates.push(exe_dir.join(CONFIG_FILE));
+    }
+
+    for path in candidates {
+        match parse_config_file(&path) {
+            Some(file) => return file,
+            None => {}
+        }
+    }
+
+    ConfigFile::default()
+}
+
+
+fn parse_config_file(path: &Path) -> Option<ConfigFile> {
+    let content = std::fs::read_to_string(path).ok()?;
+
+    match ron::de::from_str(&content) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            println!("Error parsing {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        a.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn arg_overrides_env_and_file() {
+        let file = ConfigFile { asset_root: Some("/file".to_string()), ..ConfigFile::default() };
+
+        let config = Config::from_sources(&args(&["--asset-root", "/arg"]), Some("/env".to_string()), file, PathBuf::from("/default"));
+
+        assert_eq!(config.asset_root, PathBuf::from("/arg"));
+        assert_eq!(config.scene, DEFAULT_SCENE);
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let file = ConfigFile { asset_root: Some("/file".to_string()), scene: Some("scenes/test.ron".to_string()), ..ConfigFile::default() };
+
+        let config = Config::from_sources(&args(&[]), Some("/env".to_string()), file, PathBuf::from("/default"));
+
+        assert_eq!(config.asset_root, PathBuf::from("/env"));
+        assert_eq!(config.scene, "scenes/test.ron");
+    }
+
+    #[test]
+    fn default_root() {
+        let config = Config::from_sources(&args(&["--scene", "scenes/other.ron"]), None, ConfigFile::default(), PathBuf::from("/default"));
+
+        assert_eq!(config.asset_root, PathBuf::from("/default"));
+        assert_eq!(config.models_path(), PathBuf::from("/default/blender_models"));
+        assert_eq!(config.scene, "scenes/other.ron");
+        assert_eq!(config.headless_ticks, None);
+    }
+
+    #[test]
+    fn headless_ticks() {
+        let config = Config::from_sources(&args(&["--headless", "600"]), None, ConfigFile::default(), PathBuf::from("/default"));
+
+        assert_eq!(config.headless_ticks, Some(600));
+    }
+
+    #[test]
+    fn tick_rate_and_seed() {
+        let file = ConfigFile { tick_rate: Some(30), seed: Some(7), ..ConfigFile::default() };
+
+        let config = Config::from_sources(&args(&["--tick-rate", "120"]), None, file, PathBuf::from("/default"));
+
+        assert_eq!(config.tick_rate, 120);
+        assert_eq!(config.max_ticks_per_frame, DEFAULT_MAX_TICKS_PER_FRAME);
+        assert_eq!(config.seed, Some(7));
+    }
+}
@@ -1,5 +1,6 @@
 use nalgebra as na;
 use sdl2;
+use serde::{Deserialize, Serialize};
 use crate::render_gl;
 use crate::camera;
 use crate::game;
@@ -32,6 +33,75 @@ pub struct Controls {
 
 }
 
+// The inputs the simulation cares about, without anything from sdl
+// Controls fills these from the keyboard, mouse and controller, headless runs can script them
+// and game::Replay records them
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Inputs {
+    pub movement_dir: na::Vector3::<f32>,
+    pub right_stick: Option<na::Vector3::<f32>>,
+    pub attack: bool,
+    pub roll: bool,
+    pub next_weapon: bool,
+    pub reset: bool,
+}
+
+
+impl Inputs {
+
+    pub fn none() -> Self {
+        Inputs {
+            movement_dir: na::Vector3::new(0.0, 0.0, 0.0),
+            right_stick: None,
+            attack: false,
+            roll: false,
+            next_weapon: false,
+            reset: false,
+        }
+    }
+
+    pub fn moving(x: f32, y: f32) -> Self {
+        Inputs {
+            movement_dir: na::Vector3::new(x, y, 0.0),
+            ..Inputs::none()
+        }
+    }
+
+    pub fn attack() -> Self {
+        Inputs {
+            attack: true,
+            ..Inputs::none()
+        }
+    }
+
+    pub fn next_weapon() -> Self {
+        Inputs {
+            next_weapon: true,
+            ..Inputs::none()
+        }
+    }
+
+    // Add the inputs from a frame. Sticks are always the latest, button presses are kept
+    // until a tick has used them, since a frame can run zero or several ticks
+    pub fn accumulate(&mut self, frame: &Inputs) {
+        self.movement_dir = frame.movement_dir;
+        self.right_stick = frame.right_stick;
+        self.attack |= frame.attack;
+        self.roll |= frame.roll;
+        self.next_weapon |= frame.next_weapon;
+        self.reset |= frame.reset;
+    }
+
+    // Call after a tick used the inputs
+    pub fn clear_buttons(&mut self) {
+        self.attack = false;
+        self.roll = false;
+        self.next_weapon = false;
+        self.reset = false;
+    }
+}
+
+
 #[derive(Debug)]
 pub enum Action {
     AddEnemy,
@@ -71,6 +141,17 @@ impl Controls {
         }
     }
 
+    pub fn inputs(&self) -> Inputs {
+        Inputs {
+            movement_dir: self.movement_dir,
+            right_stick: self.right_stick,
+            attack: self.attack,
+            roll: self.roll,
+            next_weapon: self.next_weapon,
+            reset: self.reset,
+        }
+    }
+
     pub fn handle_inputs(&mut self, ctx: &mut render_gl::context::Context, cameras: &mut game::Cameras ) -> Action  {
 
         self.reset = false;
@@ -21,7 +21,7 @@ impl Deltatime {
 
 
     pub fn time(&self) -> f32 {
-        (self.value.as_millis() as f32 )/ 1000.0 * self.time_speed
+        self.value.as_secs_f32() * self.time_speed
     }
 
     pub fn update(&mut self) {
@@ -30,3 +30,85 @@ impl Deltatime {
     }
 
 }
+
+
+
+// Fixed timestep accumulator. The simulation always steps with tick_delta,
+// real frame time is accumulated and turned into whole ticks.
+// What is left over is used to interpolate rendering between the last two ticks
+pub struct FixedStep {
+    tick_delta: f32,
+    max_ticks_per_frame: usize,
+    accumulator: f32,
+}
+
+impl FixedStep {
+    pub fn new(tick_rate: u32, max_ticks_per_frame: usize) -> Self {
+        Self {
+            tick_delta: 1.0 / tick_rate as f32,
+            max_ticks_per_frame,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn tick_delta(&self) -> f32 {
+        self.tick_delta
+    }
+
+    // Add the frame time and return how many ticks to run.
+    // At most max_ticks_per_frame, time beyond that is dropped, so a long stall
+    // fx in the debugger, does not make the game try to catch up forever
+    pub fn advance(&mut self, frame_time: f32) -> usize {
+        self.accumulator += frame_time;
+
+        let mut ticks = (self.accumulator / self.tick_delta) as usize;
+
+        if ticks > self.max_ticks_per_frame {
+            ticks = self.max_ticks_per_frame;
+            self.accumulator = ticks as f32 * self.tick_delta;
+        }
+
+        self.accumulator -= ticks as f32 * self.tick_delta;
+
+        ticks
+    }
+
+    // 0 is the previous tick, 1 is the current
+    pub fn alpha(&self) -> f32 {
+        f32::min(self.accumulator / self.tick_delta, 1.0)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn accumulates_partial_ticks() {
+        let mut step = FixedStep::new(60, 5);
+
+        assert_eq!(step.advance(0.01), 0);
+        assert_eq!(step.advance(0.01), 1);
+        assert!((step.alpha() - (0.02 - 1.0 / 60.0) * 60.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn multiple_ticks_in_one_frame() {
+        let mut step = FixedStep::new(100, 5);
+
+        assert_eq!(step.advance(0.035), 3);
+        assert!((step.alpha() - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn catch_up_is_capped() {
+        let mut step = FixedStep::new(60, 5);
+
+        assert_eq!(step.advance(10.0), 5);
+        assert_eq!(step.alpha(), 0.0);
+        assert_eq!(step.advance(0.0), 0);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::entity::*;
+use crate::physics;
 
 
 pub struct Entities {
@@ -6,23 +7,27 @@ pub struct Entities {
     pub default_weapon: Entity,
     pub weapons: EntitiesCollection,
     pub enemies: EntitiesCollection,
+    // loose objects like barrels and crates, moved by impulse collisions
+    pub props: EntitiesCollection,
 }
 
+// BTreeMap so entities are always updated in id order, which keeps the simulation deterministic
 pub struct EntitiesCollection {
     next_id: usize,
-    pub entities: std::collections::HashMap::<usize, Entity>,
+    pub entities: std::collections::BTreeMap::<usize, Entity>,
 }
 
 impl EntitiesCollection {
     pub fn new() -> Self {
         EntitiesCollection {
             next_id: 1,
-            entities: std::collections::HashMap::<usize, Entity>::new(),
+            entities: std::collections::BTreeMap::<usize, Entity>::new(),
         }
     }
 
     pub fn add(&mut self, mut entity: entity::Entity) -> usize {
         let id = self.next_id;
+        self.next_id += 1;
         entity.id = id;
         self.entities.insert(id, entity);
         id
@@ -37,12 +42,12 @@ impl EntitiesCollection {
     }
 
 
-    pub fn values_mut(&mut self) -> std::collections::hash_map::ValuesMut<'_, usize, Entity> {
+    pub fn values_mut(&mut self) -> std::collections::btree_map::ValuesMut<'_, usize, Entity> {
         self.entities.values_mut()
     }
 
 
-    pub fn values(&self) -> std::collections::hash_map::Values<'_, usize, Entity> {
+    pub fn values(&self) -> std::collections::btree_map::Values<'_, usize, Entity> {
         self.entities.values()
     }
 
@@ -58,12 +63,31 @@ impl Entities {
         Entities {
             enemies: EntitiesCollection::new(),
             weapons: EntitiesCollection::new(),
+            props: EntitiesCollection::new(),
             player: Entity::new(None, "Placeholder".to_string()),
             default_weapon: Entity::new(None, "default_weapon".to_string())
         }
     }
 
 
+    pub fn get(&self, key: physics::EntityKey) -> Option<&Entity> {
+        match key {
+            physics::EntityKey::Player => Some(&self.player),
+            physics::EntityKey::Enemy(id) => self.enemies.get(id),
+            physics::EntityKey::Prop(id) => self.props.get(id),
+        }
+    }
+
+
+    pub fn get_mut(&mut self, key: physics::EntityKey) -> Option<&mut Entity> {
+        match key {
+            physics::EntityKey::Player => Some(&mut self.player),
+            physics::EntityKey::Enemy(id) => self.enemies.get_mut(id),
+            physics::EntityKey::Prop(id) => self.props.get_mut(id),
+        }
+    }
+
+
     pub fn hitbox_entities(&self) -> Vec::<&Entity>{
 
         self.values()
@@ -80,6 +104,10 @@ impl Entities {
             res.push(e);
         }
 
+        for e in self.props.entities.values() {
+            res.push(e);
+        }
+
         res
 
 
@@ -4,6 +4,9 @@ use crate::physics;
 use crate::shared;
 
 
+// an attack started faster than this is a moving attack, see Entity::moving_attack
+const MOVING_ATTACK_SPEED: f32 = 0.5;
+
 
 #[derive(Clone)]
 pub struct Entity {
@@ -13,13 +16,44 @@ pub struct Entity {
     pub animation_player: Option<render_gl::AnimationPlayer>,
     pub bones: Vec::<na::Matrix4::<f32>>,
     pub skeleton: render_gl::Skeleton,
-    pub hitboxes: Vec::<physics::CollisionBox>,
+    pub hitboxes: Vec::<physics::CollisionShape>,
     pub weapon: Option<Box<Entity>>,
     pub is_hit: bool,
+    // the weapon hit the world, fx the ground, in the active frames of the current attack
+    pub weapon_hit_world: bool,
     pub ai: Option<shared::EntityAi>,
+    // physics at the start of the current tick, None until the first tick. Used to interpolate rendering
+    pub previous_physics: Option<shared::Physics>,
+    // counts the attacks started, so a swing can be told apart from the next one, fx to only hit once pr swing
+    pub attack_instance: usize,
+    // seconds left of the animation pause after a hit landed
+    pub hit_stop: f32,
+    // events of the base animation and of the animation graph state layer passed in the last animation update, and
+    // what they have switched on. Other layer events are not in here, see AnimationPlayer::layer_events
+    pub animation_events: Vec::<render_gl::AnimationEvent>,
+    pub animation_flags: AnimationFlags,
+    // picks the clip to play from the parameters, set by the state and physics before each animation update
+    pub animation_graph: Option<render_gl::AnimationStateMachine>,
+    pub animation_parameters: render_gl::AnimationParameters,
+    // velocity from props hitting the entity, added to its movement and slowed down each tick
+    pub knockback: na::Vector3::<f32>,
+    // the current attack started while moving. The graph plays it on the upper body and the entity keeps moving
+    pub moving_attack: bool,
     has_run: bool
 }
 
+
+// Switched on and off by animation events, and all off when the state changes
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct AnimationFlags {
+    // between hit_start and hit_end
+    pub hit_active: bool,
+    // between combo_window_open and combo_window_close
+    pub combo_window: bool,
+    // between invincible_start and invincible_end
+    pub invincible: bool,
+}
+
 impl Entity {
 
     pub fn new(animation_player: Option<render_gl::AnimationPlayer>, model_name: String) -> Self {
@@ -34,22 +68,74 @@ impl Entity {
                 legs: None,
             },
             weapon: None,
-            hitboxes: Vec::<physics::CollisionBox>::new(),
+            hitboxes: Vec::<physics::CollisionShape>::new(),
             is_hit: false,
+            weapon_hit_world: false,
             id: 0,
             ai: None,
+            previous_physics: None,
+            attack_instance: 0,
+            hit_stop: 0.0,
+            animation_events: Vec::new(),
+            animation_flags: AnimationFlags::default(),
+            animation_graph: None,
+            animation_parameters: render_gl::AnimationParameters::new(),
+            knockback: na::Vector3::new(0.0, 0.0, 0.0),
+            moving_attack: false,
             has_run: false,
         }
     }
 
+
+    pub fn store_previous_physics(&mut self) {
+        self.previous_physics = Some(self.base_entity.physics);
+
+        if let Some(weapon) = &mut self.weapon {
+            weapon.store_previous_physics();
+        }
+    }
+
+
+    // physics to render with, alpha is how far we are between the previous and current tick
+    pub fn render_physics(&self, alpha: f32) -> shared::Physics {
+        match &self.previous_physics {
+            Some(previous) => self.base_entity.physics.interpolate(previous, alpha),
+            None => self.base_entity.physics
+        }
+    }
+
     pub fn get_state(&self) -> shared::EntityState {
         self.base_entity.state
     }
 
 
     pub fn update_animations(&mut self, delta: f32) {
-        if let Some(animation_player) = &mut self.animation_player {
-            animation_player.update_skeleton(&mut self.skeleton, delta);
+        self.animation_events.clear();
+
+        if self.hit_stop > 0.0 {
+            self.hit_stop -= delta;
+        }
+        else if let Some(animation_player) = &mut self.animation_player {
+
+            if let Some(graph) = &mut self.animation_graph {
+                set_animation_parameters(&mut self.animation_parameters, &self.base_entity);
+                graph.update(&self.animation_parameters, animation_player, &self.skeleton);
+                self.animation_parameters.clear_triggers();
+            }
+
+            self.animation_events = animation_player.update_skeleton(&mut self.skeleton, delta);
+
+            // the state layer plays what the entity does, fx a moving attack, so its events count
+            if let Some(layer) = self.animation_graph.as_ref().and_then(|graph| graph.state_layer()) {
+                let layer_events = animation_player.layer_events().iter()
+                    .filter(|(name, _)| name == layer)
+                    .map(|(_, event)| event.clone());
+                self.animation_events.extend(layer_events);
+            }
+        }
+
+        for event in &self.animation_events {
+            self.animation_flags.apply(&event.name);
         }
 
         render_gl::inverse_kinematics::update_ik(&mut self.skeleton, &self.base_entity.physics, delta);
@@ -71,25 +157,97 @@ impl Entity {
     }
 
 
+    // added on top of whatever plays, for models with a hit_reaction animation
+    pub fn play_hit_reaction(&mut self) {
+        if let Some(animation_player) = &mut self.animation_player {
+            if animation_player.animations.contains("hit_reaction") {
+                let mask = render_gl::JointMask::all(&self.skeleton);
+                animation_player.play_layer(render_gl::AnimationLayer::new("hit_reaction", "hit_reaction", render_gl::LayerMode::Additive, mask));
+            }
+        }
+    }
+
+
+    // the attack or roll clip has played through, also when the animation graph plays it on a layer
+    pub fn action_animation_done(&self) -> bool {
+
+        let animation_player = self.animation_player.as_ref().unwrap();
+
+        match self.animation_graph.as_ref().and_then(|graph| graph.state_layer()) {
+            Some(layer) => animation_player.layer(layer).is_none(),
+            None => animation_player.has_repeated,
+        }
+    }
+
+
+    // the event happened in the last animation update
+    pub fn animation_event(&self, name: &str) -> bool {
+        self.animation_events.iter().any(|event| event.name == name)
+    }
+
+
     fn update_state(&mut self, state: shared::EntityState) {
 
         self.base_entity.state = state;
+        self.animation_flags = AnimationFlags::default();
+        self.moving_attack = false;
+
+        // the animation graph sees these in the next animation update
+        match state {
+            shared::EntityState::Attack(info) => {
+                self.attack_instance += 1;
+                self.moving_attack = self.base_entity.physics.velocity.xy().magnitude() > MOVING_ATTACK_SPEED;
+                self.animation_parameters.set_trigger("attack_requested");
+                self.animation_parameters.set_number("combo_index", info.combo_num as f32);
+                self.animation_parameters.set_clip("attack_clip", &self.base_entity.weapon_stats().attack_animation(info.combo_num));
+            },
+            shared::EntityState::Roll => self.animation_parameters.set_trigger("roll_requested"),
+            _ => {}
+        };
 
-        if let Some(animation_player) = &mut self.animation_player {
+        self.animation_parameters.set_bool("moving_attack", self.moving_attack);
+    }
+}
 
-            match state {
-                shared::EntityState::Moving => animation_player.set_current(render_gl::Animation::Walk, &self.skeleton),
-                shared::EntityState::Attack(info) => {
-                    if info.combo_num == 1 {
-                        animation_player.set_current(render_gl::Animation::AttackFollow, &self.skeleton)
-                    }
-                    else {
-                        animation_player.set_current(render_gl::Animation::Attack, &self.skeleton)
-                    }
-                },
-                shared::EntityState::Idle => animation_player.set_current(render_gl::Animation::Idle, &self.skeleton),
-                shared::EntityState::Roll => animation_player.set_current(render_gl::Animation::Roll, &self.skeleton),
-            };
-        };
+
+// The parameters that come from the state and physics, the triggers are set when the state changes
+fn set_animation_parameters(parameters: &mut render_gl::AnimationParameters, base_entity: &shared::BaseEntity) {
+
+    let physics = &base_entity.physics;
+
+    let velocity = physics.velocity.xy();
+    let forward = physics.facing_dir.xy();
+    let right = na::Vector2::new(forward.y, -forward.x);
+
+    parameters.set_number("speed", velocity.magnitude());
+    // relative to where the entity faces, for strafing
+    parameters.set_number("forward_speed", velocity.dot(&forward));
+    parameters.set_number("side_speed", velocity.dot(&right));
+    parameters.set_bool("grounded", physics.grounded);
+
+    let (attacking, rolling) = match base_entity.state {
+        shared::EntityState::Attack(_) => (true, false),
+        shared::EntityState::Roll => (false, true),
+        _ => (false, false),
+    };
+
+    parameters.set_bool("attacking", attacking);
+    parameters.set_bool("rolling", rolling);
+}
+
+
+impl AnimationFlags {
+
+    // unknown events, fx footstep, do not switch anything
+    pub fn apply(&mut self, event: &str) {
+        match event {
+            "hit_start" => self.hit_active = true,
+            "hit_end" => self.hit_active = false,
+            "combo_window_open" => self.combo_window = true,
+            "combo_window_close" => self.combo_window = false,
+            "invincible_start" => self.invincible = true,
+            "invincible_end" => self.invincible = false,
+            _ => {}
+        }
     }
 }
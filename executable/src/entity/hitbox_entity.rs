@@ -1,11 +1,10 @@
-use crate::physics::CollisionBox;
+use crate::physics::{CollisionShape, ShapeKind};
 use crate::entity::*;
 
-pub fn add_hitbox_to_entity(entity: &mut Entity,  hitboxes:  &Vec::<(String,Vec<na::Vector3::<f32>>)>) {
+pub fn add_hitbox_to_entity(entity: &mut Entity,  hitboxes:  &Vec::<(String, Vec<na::Vector3::<f32>>, ShapeKind)>) {
 
     for hitbox_kv in hitboxes {
-        let mut hb = CollisionBox::from_mesh_data(&hitbox_kv.1);
-        hb.name = hitbox_kv.0.clone();
+        let hb = CollisionShape::from_mesh_data(hitbox_kv.2, &hitbox_kv.1, &hitbox_kv.0);
         entity.hitboxes.push(hb);
     }
 }
@@ -3,7 +3,7 @@ mod model;
 pub use self::model::{Model};
 
 mod entity;
-pub use self::entity::{Entity};
+pub use self::entity::{Entity, AnimationFlags};
 
 mod entities;
 pub use self::entities::{Entities, EntitiesCollection};
@@ -12,7 +12,8 @@ pub struct LoadedAis {
 
 pub struct AiPlugin<T> {
     pub ai: Box<dyn shared::Ai<T>>,
-    pub lib: libloading::Library,
+    // None when the ai is linked into the executable, see builtin_ais
+    pub lib: Option<libloading::Library>,
 }
 
 
@@ -24,6 +25,12 @@ impl<T> AiPlugin<T> {
 }
 
 
+// file name of the ai crate library on this platform, fx libai.so, ai.dll or libai.dylib
+pub fn lib_file_name() -> String {
+    format!("{}ai{}", std::env::consts::DLL_PREFIX, std::env::consts::DLL_SUFFIX)
+}
+
+
 pub fn load_ais(res: &Resources) -> LoadedAis {
     LoadedAis {
         regular_enemy: load_regular_enemy_ai(res)
@@ -34,19 +41,31 @@ pub fn load_ais(res: &Resources) -> LoadedAis {
 fn load_regular_enemy_ai(res: &Resources) -> AiPlugin<shared::RegularEnemyState> {
     // make a copy of dll so we can still build it
 
-    let lib = res.copy_and_load_lib("ai.dll");
+    let lib = res.copy_and_load_lib(&lib_file_name());
 
     let regular_enemy_ai: libloading::Symbol<extern "Rust" fn() ->  Box<dyn shared::Ai<shared::RegularEnemyState>>> = unsafe { lib.get(b"regular_enemy_ai") }
     .expect("load symbol");
 
     AiPlugin {
         ai: regular_enemy_ai(),
-        lib: lib
+        lib: Some(lib)
     }
 
 }
 
 
+// The ais linked into the executable, no hot reload. Used by headless runs and tests
+// where there might not be a copy of the dll next to the executable
+pub fn builtin_ais() -> LoadedAis {
+    LoadedAis {
+        regular_enemy: AiPlugin {
+            ai: ::ai::regular_enemy_ai(),
+            lib: None
+        }
+    }
+}
+
+
 pub fn run_ais(scene: &mut game::Scene) {
 
     let ais = match &scene.loaded_ais {
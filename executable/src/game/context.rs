@@ -3,6 +3,7 @@ use crate::render_gl;
 
 
 
+use crate::config::Config;
 use crate::controls;
 use crate::deltatime;
 use crate::game;
@@ -14,18 +15,23 @@ pub struct Context {
 
     pub controls: controls::Controls,
     pub scene: game::Scene,
+    pub renderer: game::SceneRenderer,
     pub render_context: render_gl::context::Context,
 
     delta_time: deltatime::Deltatime,
 
+    pub fixed_step: deltatime::FixedStep,
+
     pub res_dll: Resources,
+
+    pub res_models: Resources,
 }
 
 impl Context {
 
-    pub fn new() -> Result<Context, failure::Error> {
+    pub fn new(config: &Config) -> Result<Context, failure::Error> {
 
-        let ctx = empty()?;
+        let ctx = empty(config)?;
 
         Ok(ctx)
     }
@@ -55,12 +61,12 @@ impl Context {
 
 
 
-fn empty() -> Result<Context, failure::Error> {
+fn empty(config: &Config) -> Result<Context, failure::Error> {
 
     let width = 900;
     let height = 700;
 
-    let render_context = render_gl::context::setup(width, height)?;
+    let render_context = render_gl::context::setup(width, height, &config.assets_path())?;
 
     let background_color_buffer = render_gl::ColorBuffer::from_color(na::Vector3::new(0.3, 0.3, 0.5));
 
@@ -69,19 +75,30 @@ fn empty() -> Result<Context, failure::Error> {
 
     let res_dll = Resources::from_relative_exe_path(Path::new("")).unwrap();
 
+    let res_models = Resources::from_path(&config.models_path());
+
     let event_pump = render_context.sdl.event_pump().unwrap();
     let controls = controls::Controls::new(event_pump);
 
-    let scene = game::Scene::new(&render_context, &res_dll).unwrap();
+    let loaded_ais = Some(game::ai::load_ais(&res_dll));
+
+    let scene = game::Scene::new(&render_context.res, &res_models, &config.scene, config.seed, loaded_ais).unwrap();
+
+    let renderer = game::SceneRenderer::new(&render_context)?;
 
     let delta_time = deltatime::Deltatime::new();
 
+    let fixed_step = deltatime::FixedStep::new(config.tick_rate, config.max_ticks_per_frame);
+
 
     Ok(Context {
         scene,
+        renderer,
         controls,
         render_context,
         delta_time,
-        res_dll
+        fixed_step,
+        res_dll,
+        res_models,
     })
 }
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::controls::Inputs;
+use crate::game;
+use crate::physics;
+use crate::resources::Resources;
+
+
+// Runs the game simulation without sdl or opengl. Inputs are given for each tick
+// instead of coming from Controls, so runs can be scripted fx in tests
+pub struct Headless {
+    pub scene: game::Scene,
+    pub tick: usize,
+    pub delta: f32,
+}
+
+
+impl Headless {
+
+    pub fn new(config: &Config) -> Result<Headless, failure::Error> {
+
+        let res = Resources::from_path(&config.assets_path());
+
+        let res_models = Resources::from_path(&config.models_path());
+
+        let scene = game::Scene::new(&res, &res_models, &config.scene, config.seed, Some(game::ai::builtin_ais()))?;
+
+        Ok(Headless {
+            scene,
+            tick: 0,
+            // same fixed delta as the window loop
+            delta: 1.0 / config.tick_rate as f32,
+        })
+    }
+
+
+    pub fn step(&mut self, inputs: &Inputs) -> Vec<physics::EntityCollision> {
+        let collisions = game::simulation::step(&mut self.scene, inputs, self.delta);
+        self.tick += 1;
+        collisions
+    }
+
+
+    // Run one tick for each inputs in the script
+    pub fn run(&mut self, script: &[Inputs]) {
+        for inputs in script {
+            self.step(inputs);
+        }
+    }
+
+
+    pub fn run_idle(&mut self, ticks: usize) {
+        for _ in 0..ticks {
+            self.step(&Inputs::none());
+        }
+    }
+}
+
+
+// Entry for --headless <ticks>, runs the replay if there is one and then ticks more without input
+pub fn run(config: &Config, ticks: usize, replay: Option<game::Replay>) -> Result<(), failure::Error> {
+
+    let mut headless = Headless::new(config)?;
+
+    if let Some(replay) = replay {
+        headless.run(&replay.tick_inputs());
+    }
+
+    headless.run_idle(ticks);
+
+    let player = &headless.scene.entities.player.base_entity;
+    println!("Ran {} ticks", headless.tick);
+    println!("player pos={:?} grounded={} state={:?}", player.physics.pos, player.physics.grounded, player.state);
+
+    for enemy in headless.scene.entities.enemies.values() {
+        println!("enemy {} pos={:?} state={:?}", enemy.id, enemy.base_entity.physics.pos, enemy.get_state());
+    }
+
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::camera;
+    use crate::types::*;
+
+    fn headless() -> Headless {
+        // the asset root is the repo root, one up from executable/
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        let config = Config {
+            seed: Some(1234),
+            ..Config::from_asset_root(root)
+        };
+
+        Headless::new(&config).unwrap()
+    }
+
+
+    fn entity_states(scene: &game::Scene) -> Vec<(V3, na::UnitQuaternion::<f32>, V3, shared::EntityState)> {
+        scene.entities.values().iter()
+            .map(|e| {
+                let physics = e.base_entity.physics;
+                (physics.pos, physics.rotation, physics.velocity, e.get_state())
+            })
+            .collect()
+    }
+
+
+    fn ground_height(scene: &game::Scene, x: f32, y: f32) -> Option<f32> {
+        let point = V3::new(x, y, 0.0);
+        let triangles = scene.world_triangles_near(x, y, 0.0);
+        triangles.iter()
+            .map(|t| t.project_point_z_axis(&point))
+            .zip(triangles.iter())
+            .find(|(p, t)| t.inside(p))
+            .map(|(p, _)| p.z)
+    }
+
+
+    fn horizontal_distance(a: V3, b: V3) -> f32 {
+        (a.xy() - b.xy()).magnitude()
+    }
+
+
+    #[test]
+    fn player_lands_on_terrain() {
+        let mut sim = headless();
+
+        sim.scene.entities.player.base_entity.physics.pos = V3::new(2.0, 3.0, 10.0);
+
+        sim.run_idle(300);
+
+        let physics = sim.scene.entities.player.base_entity.physics;
+        assert!(physics.grounded);
+
+        let ground = ground_height(&sim.scene, physics.pos.x, physics.pos.y).unwrap();
+        assert!((physics.pos.z - ground).abs() < 1.0, "player z {} ground {}", physics.pos.z, ground);
+    }
+
+
+    #[test]
+    fn enemy_ai_approaches_player() {
+        let mut sim = headless();
+
+        sim.scene.entities.player.base_entity.physics.pos = V3::new(0.0, 0.0, 5.0);
+
+        let start = V3::new(10.0, 0.0, 5.0);
+        for enemy in sim.scene.entities.enemies.values_mut() {
+            enemy.base_entity.physics.pos = start;
+        }
+
+        sim.run_idle(120);
+
+        let player_pos = sim.scene.entities.player.base_entity.physics.pos;
+        for enemy in sim.scene.entities.enemies.values() {
+            let dist = horizontal_distance(enemy.base_entity.physics.pos, player_pos);
+            assert!(dist < 8.0, "enemy still {} away", dist);
+        }
+    }
+
+
+    #[test]
+    fn same_inputs_give_same_states() {
+        let mut script = Vec::new();
+        script.extend(std::iter::repeat(Inputs::none()).take(60));
+        script.push(Inputs::next_weapon());
+        script.extend(std::iter::repeat(Inputs::moving(0.0, 1.0)).take(90));
+        script.push(Inputs::attack());
+        script.extend(std::iter::repeat(Inputs::moving(1.0, 0.0)).take(90));
+
+        let mut first = headless();
+        first.run(&script);
+
+        let mut second = headless();
+        second.run(&script);
+
+        assert_eq!(entity_states(&first.scene), entity_states(&second.scene));
+    }
+
+
+    #[test]
+    fn replay_reproduces_run() {
+        let mut original = headless();
+        let mut replay = game::Replay::new(&original.scene, 60);
+
+        for tick in 0..240 {
+            let inputs = match tick % 80 {
+                0 => Inputs::attack(),
+                t if t < 40 => Inputs::moving(0.0, 1.0),
+                _ => Inputs::moving(-1.0, 0.5),
+            };
+            replay.record(&inputs);
+            original.step(&inputs);
+        }
+
+        let mut replayed = headless();
+        assert_eq!(replayed.scene.terrain_seed().unwrap(), replay.terrain_seed);
+
+        let mut player = game::ReplayPlayer::new(replay);
+        while let Some(inputs) = player.next() {
+            replayed.step(&inputs);
+        }
+
+        assert_eq!(entity_states(&original.scene), entity_states(&replayed.scene));
+    }
+
+
+    #[test]
+    fn replay_with_camera_turned() {
+        let mut original = headless();
+        let mut replay = game::Replay::new(&original.scene, 60);
+
+        let start_rotation = original.scene.cameras.follow().z_rotation();
+
+        for tick in 0..240 {
+            // walk while turning the follow camera with the stick
+            let inputs = Inputs {
+                right_stick: if tick < 60 { Some(V3::new(1.0, 0.0, 0.0)) } else { None },
+                ..Inputs::moving(0.0, 1.0)
+            };
+
+            // looking around with the free camera is not in the inputs, and should not change the run
+            if tick == 120 {
+                original.scene.cameras.mode = camera::CameraMode::Free;
+            }
+            if tick >= 120 {
+                original.scene.camera_mut().update_movement(5.0, 2.0);
+            }
+
+            replay.record(&inputs);
+            original.step(&inputs);
+        }
+
+        assert!((original.scene.cameras.follow().z_rotation() - start_rotation).abs() > 0.1);
+
+        let mut replayed = headless();
+        let mut player = game::ReplayPlayer::new(replay);
+        while let Some(inputs) = player.next() {
+            replayed.step(&inputs);
+        }
+
+        assert_eq!(entity_states(&original.scene), entity_states(&replayed.scene));
+    }
+
+
+    #[test]
+    fn player_attack_hits_enemy() {
+        let mut sim = headless();
+
+        // settle on the ground before placing the enemy
+        sim.run_idle(120);
+
+        let player_pos = sim.scene.entities.player.base_entity.physics.pos;
+        for enemy in sim.scene.entities.enemies.values_mut() {
+            enemy.ai = None;
+            enemy.base_entity.physics.pos = player_pos + V3::new(1.2, 0.0, 0.0);
+        }
+
+        sim.step(&Inputs::next_weapon());
+        assert!(sim.scene.entities.player.weapon.is_some());
+
+        let health_before: Vec::<f32> = sim.scene.entities.enemies.values().map(|e| e.base_entity.health.health()).collect();
+
+        sim.step(&Inputs::attack());
+
+        let mut hit = false;
+        let mut events = Vec::new();
+        for _ in 0..60 {
+            sim.step(&Inputs::none());
+            hit |= sim.scene.entities.enemies.values().any(|e| e.is_hit);
+            events.extend(sim.scene.hit_events.iter().cloned());
+        }
+
+        assert!(hit);
+
+        // the weapon is inside the enemy for many ticks, but the swing only hits once
+        let enemies = sim.scene.entities.enemies.count();
+        assert_eq!(events.len(), enemies, "{:#?}", events);
+        assert!(events.iter().all(|e| e.attacker == physics::EntityKey::Player && e.damage > 0.0));
+
+        let health_after: Vec::<f32> = sim.scene.entities.enemies.values().map(|e| e.base_entity.health.health()).collect();
+        for (before, after) in health_before.iter().zip(health_after.iter()) {
+            assert!(after < before, "health {} -> {}", before, after);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::entity;
+use crate::game;
+use crate::physics::{self, EntityKey, Part, ProxyKey};
+use crate::types::*;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HitEvent {
+    pub attacker: EntityKey,
+    pub target: EntityKey,
+    // model name of the attackers weapon
+    pub weapon: String,
+    // name of the targets hitbox that was hit
+    pub hitbox: String,
+    pub point: V3,
+    pub damage: f32,
+}
+
+
+// The targets each attacker has hit in its current swing, so a swing only hits a target once
+// even when the weapon stays inside the target for many ticks
+#[derive(Debug, Clone)]
+pub struct HitRegistry {
+    swings: BTreeMap::<EntityKey, Swing>,
+}
+
+
+#[derive(Debug, Clone)]
+struct Swing {
+    attack_instance: usize,
+    targets: BTreeSet::<EntityKey>,
+}
+
+
+impl HitRegistry {
+
+    pub fn new() -> HitRegistry {
+        HitRegistry {
+            swings: BTreeMap::new(),
+        }
+    }
+
+
+    // True the first time attack_instance of attacker hits target. A new attack instance starts a new swing
+    pub fn register(&mut self, attacker: EntityKey, attack_instance: usize, target: EntityKey) -> bool {
+
+        let swing = self.swings.entry(attacker).or_insert_with(|| Swing { attack_instance, targets: BTreeSet::new() });
+
+        if swing.attack_instance != attack_instance {
+            swing.attack_instance = attack_instance;
+            swing.targets.clear();
+        }
+
+        swing.targets.insert(target)
+    }
+}
+
+
+// Has a weapon and is between the hit_start and hit_end events of an attack
+pub fn weapon_active(entity: &entity::Entity) -> bool {
+
+    if entity.weapon.is_none() {
+        return false;
+    }
+
+    match entity.get_state() {
+        shared::EntityState::Attack(_) => entity.animation_flags.hit_active,
+        _ => false
+    }
+}
+
+
+// Find the weapon hits of this tick, apply their damage and store them in scene.hit_events.
+// Uses the weapon hitboxes from the last broadphase update, so update it after moving the weapons
+pub fn update_hits(scene: &mut game::Scene) {
+
+    let (events, touching) = find_hits(&scene.broadphase, &scene.entities, &mut scene.hits);
+
+    // is_hit is while a weapon is inside, the events only on the first touch of a swing
+    scene.entities.player.is_hit = false;
+    for enemy in scene.entities.enemies.values_mut() {
+        enemy.is_hit = false;
+    }
+
+    for key in touching {
+        if let Some(target) = scene.entities.get_mut(key) {
+            target.is_hit = true;
+        }
+    }
+
+    // a character killed or respawned by an earlier hit this tick is out of the fight, it neither takes nor gives the rest
+    let mut killed = Vec::<EntityKey>::new();
+    let mut applied = Vec::new();
+
+    for event in events {
+        if killed.contains(&event.target) || killed.contains(&event.attacker) {
+            continue;
+        }
+
+        if apply_hit(scene, &event) {
+            killed.push(event.target);
+        }
+
+        applied.push(event);
+    }
+
+    scene.hit_events = applied;
+}
+
+
+fn find_hits(broadphase: &physics::Broadphase, entities: &entity::Entities, hits: &mut HitRegistry) -> (Vec::<HitEvent>, Vec::<EntityKey>) {
+
+    let mut events = Vec::new();
+    let mut touching = Vec::new();
+
+    for (attacker_key, target_key) in broadphase.weapon_pairs() {
+
+        // props are not damaged and enemies do not hit each other
+        if !target_key.is_character() || (attacker_key != EntityKey::Player && target_key != EntityKey::Player) {
+            continue;
+        }
+
+        let attacker = match entities.get(attacker_key) {
+            Some(attacker) => attacker,
+            None => continue,
+        };
+
+        if !weapon_active(attacker) {
+            continue;
+        }
+
+        // fx in the middle of a roll
+        if entities.get(target_key).map(|target| target.animation_flags.invincible).unwrap_or(true) {
+            continue;
+        }
+
+        let weapon = ProxyKey { entity: attacker_key, part: Part::Weapon };
+        let body = ProxyKey { entity: target_key, part: Part::Body };
+
+        let contact = match broadphase.contact(weapon, body) {
+            Some(contact) => contact,
+            None => continue,
+        };
+
+        touching.push(target_key);
+
+        if !hits.register(attacker_key, attacker.attack_instance, target_key) {
+            continue;
+        }
+
+        let stats = attacker.base_entity.weapon_stats();
+
+        let hitbox = broadphase.hitboxes(body).get(contact.hitbox_b)
+            .map(|hitbox| hitbox.name().to_string())
+            .unwrap_or_default();
+
+        events.push(HitEvent {
+            attacker: attacker_key,
+            target: target_key,
+            weapon: attacker.weapon.as_ref().map(|w| w.model_name.clone()).unwrap_or_default(),
+            hitbox,
+            point: contact.point,
+            damage: stats.damage,
+        });
+    }
+
+    (events, touching)
+}
+
+
+// Returns true when the hit killed the target
+fn apply_hit(scene: &mut game::Scene, event: &HitEvent) -> bool {
+
+    // both animations pause a moment to give the hit weight
+    let hit_stop = match scene.entities.get_mut(event.attacker) {
+        Some(attacker) => {
+            attacker.hit_stop = attacker.base_entity.weapon_stats().hit_stop;
+            attacker.hit_stop
+        },
+        None => 0.0,
+    };
+
+    let dead = match scene.entities.get_mut(event.target) {
+        Some(target) => {
+            target.hit_stop = hit_stop;
+            target.play_hit_reaction();
+            target.base_entity.health.damage(event.damage)
+        },
+        None => return false,
+    };
+
+    if dead {
+        kill(scene, event.target);
+    }
+
+    dead
+}
+
+
+fn kill(scene: &mut game::Scene, key: EntityKey) {
+    match key {
+        EntityKey::Player => {
+            respawn_player(scene);
+        },
+        EntityKey::Enemy(id) => {
+            scene.entities.enemies.entities.remove(&id);
+        },
+        EntityKey::Prop(_) => {}
+    }
+}
+
+
+// Back at the start of the scene with full health
+fn respawn_player(scene: &mut game::Scene) {
+
+    let start = scene.description.player.position;
+    let player = &mut scene.entities.player;
+
+    player.base_entity.physics = shared::Physics::new();
+    player.base_entity.physics.pos = V3::new(start[0], start[1], start[2]);
+    player.base_entity.health.restore();
+    player.base_entity.stamina.restore();
+    player.base_entity.queued_action = Some(shared::EntityState::Idle);
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn swing_registers_each_target_once() {
+        let mut hits = HitRegistry::new();
+
+        assert!(hits.register(EntityKey::Player, 1, EntityKey::Enemy(0)));
+        assert!(!hits.register(EntityKey::Player, 1, EntityKey::Enemy(0)));
+
+        // other targets in the same swing still count
+        assert!(hits.register(EntityKey::Player, 1, EntityKey::Enemy(1)));
+
+        // and other attackers have their own swings
+        assert!(hits.register(EntityKey::Enemy(0), 1, EntityKey::Player));
+    }
+
+
+    #[test]
+    fn next_swing_hits_again() {
+        let mut hits = HitRegistry::new();
+
+        assert!(hits.register(EntityKey::Player, 1, EntityKey::Enemy(0)));
+        assert!(hits.register(EntityKey::Player, 2, EntityKey::Enemy(0)));
+        assert!(!hits.register(EntityKey::Player, 2, EntityKey::Enemy(0)));
+    }
+}
@@ -2,9 +2,28 @@ mod context;
 pub use self::context::{Context};
 
 mod scene;
-pub use self::scene::{Scene, Cameras};
+pub use self::scene::{Scene, SceneMesh, Cameras};
+
+mod scene_renderer;
+pub use self::scene_renderer::{SceneRenderer};
+
+mod terrain;
+pub use self::terrain::{Terrain, TerrainChanges, ChunkCollision};
+
+mod scene_description;
+pub use self::scene_description::{SceneDescription, TerrainDescription, ModelDescription, PlayerDescription, EnemySpawn, PropSpawn, AiDescription};
+
+mod hits;
+pub use self::hits::{HitEvent, HitRegistry};
 
 mod update;
 pub use self::update::{update_game_state};
 
+pub mod simulation;
+
+pub mod headless;
+
+mod replay;
+pub use self::replay::{Replay, ReplayPlayer};
+
 pub mod ai;
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::controls::Inputs;
+use crate::game;
+
+
+// The inputs of every simulation tick, and what is needed to start the same game again.
+// Since the simulation is deterministic, feeding the inputs back in reproduces the run exactly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub scene: String,
+    pub terrain_seed: u32,
+    pub tick_rate: u32,
+    // run length encoded, (number of ticks, inputs)
+    pub inputs: Vec<(usize, Inputs)>,
+}
+
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Io error for replay {:?}", path)]
+    Io { path: PathBuf, inner: std::io::Error },
+    #[fail(display = "Can not parse the replay {:?}: {}", path, inner)]
+    ReplayParseFailed { path: PathBuf, inner: ron::Error },
+    #[fail(display = "Can not write the replay: {}", inner)]
+    ReplayWriteFailed { inner: ron::Error },
+}
+
+
+impl Replay {
+
+    pub fn new(scene: &game::Scene, tick_rate: u32) -> Replay {
+        Replay {
+            scene: scene.scene_name.clone(),
+            terrain_seed: scene.terrain_seed().unwrap_or(0),
+            tick_rate,
+            inputs: Vec::new()
+        }
+    }
+
+
+    // Add the inputs for the next tick
+    pub fn record(&mut self, inputs: &Inputs) {
+        match self.inputs.last_mut() {
+            Some((count, last)) if last == inputs => {
+                *count += 1;
+            },
+            _ => {
+                self.inputs.push((1, *inputs));
+            }
+        };
+    }
+
+
+    pub fn tick_count(&self) -> usize {
+        self.inputs.iter().map(|(count, _)| count).sum()
+    }
+
+
+    // One inputs pr tick
+    pub fn tick_inputs(&self) -> Vec<Inputs> {
+        self.inputs.iter()
+            .flat_map(|(count, inputs)| std::iter::repeat(*inputs).take(*count))
+            .collect()
+    }
+
+
+    // Start the game the replay was recorded in, same scene, terrain and tick rate
+    pub fn apply_to(&self, config: &mut Config) {
+        config.scene = self.scene.clone();
+        config.seed = Some(self.terrain_seed);
+        config.tick_rate = self.tick_rate;
+    }
+
+
+    pub fn load(path: &Path) -> Result<Replay, Error> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| Error::Io {
+                path: path.into(),
+                inner: e
+            })?;
+
+        ron::de::from_str(&data)
+            .map_err(|e| Error::ReplayParseFailed {
+                path: path.into(),
+                inner: e
+            })
+    }
+
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let data = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|e| Error::ReplayWriteFailed {
+                inner: e
+            })?;
+
+        std::fs::write(path, data)
+            .map_err(|e| Error::Io {
+                path: path.into(),
+                inner: e
+            })
+    }
+}
+
+
+// Feeds the recorded inputs back one tick at a time
+pub struct ReplayPlayer {
+    replay: Replay,
+    run_index: usize,
+    run_tick: usize,
+}
+
+
+impl ReplayPlayer {
+
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        ReplayPlayer {
+            replay,
+            run_index: 0,
+            run_tick: 0
+        }
+    }
+
+
+    // Inputs for the next tick, None when the replay is done
+    pub fn next(&mut self) -> Option<Inputs> {
+        let (count, inputs) = *self.replay.inputs.get(self.run_index)?;
+
+        self.run_tick += 1;
+        if self.run_tick >= count {
+            self.run_index += 1;
+            self.run_tick = 0;
+        }
+
+        Some(inputs)
+    }
+
+
+    pub fn is_finished(&self) -> bool {
+        self.run_index >= self.replay.inputs.len()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn replay(inputs: &[Inputs]) -> Replay {
+        let mut replay = Replay {
+            scene: "scenes/default.ron".to_string(),
+            terrain_seed: 42,
+            tick_rate: 60,
+            inputs: Vec::new()
+        };
+
+        for i in inputs {
+            replay.record(i);
+        }
+
+        replay
+    }
+
+
+    #[test]
+    fn record_run_length() {
+        let script = vec![Inputs::none(), Inputs::none(), Inputs::attack(), Inputs::moving(1.0, 0.0), Inputs::moving(1.0, 0.0)];
+
+        let replay = replay(&script);
+
+        assert_eq!(replay.inputs.len(), 3);
+        assert_eq!(replay.tick_count(), 5);
+        assert_eq!(replay.tick_inputs(), script);
+    }
+
+
+    #[test]
+    fn player_returns_recorded_ticks() {
+        let script = vec![Inputs::next_weapon(), Inputs::moving(0.0, 1.0), Inputs::moving(0.0, 1.0), Inputs::attack()];
+
+        let mut player = ReplayPlayer::new(replay(&script));
+
+        let mut played = Vec::new();
+        while let Some(inputs) = player.next() {
+            played.push(inputs);
+        }
+
+        assert!(player.is_finished());
+        assert_eq!(played, script);
+    }
+
+
+    #[test]
+    fn ron_round_trip() {
+        let replay = replay(&[Inputs::none(), Inputs::moving(0.5, -1.0), Inputs::attack()]);
+
+        let data = ron::ser::to_string(&replay).unwrap();
+        let loaded: Replay = ron::de::from_str(&data).unwrap();
+
+        assert_eq!(loaded, replay);
+    }
+}
@@ -5,16 +5,23 @@ use quadtree as qt;
 
 
 use crate::physics;
-use crate::cube;
 use crate::entity;
 use crate::render_gl;
 use crate::camera;
 
 use crate::action_system;
 use crate::game::ai;
+use crate::game::Terrain;
+use crate::game::{HitEvent, HitRegistry};
+use crate::game::scene_description::*;
 use crate::resources::Resources;
-use crate::text_render;
-use crate::types::*;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+// revisions are unique across scenes, so a renderer never mistakes a mesh from a reloaded scene for the old one
+static NEXT_MESH_REVISION: AtomicUsize = AtomicUsize::new(1);
 
 pub struct Cameras {
     free_camera: camera::FreeCamera,
@@ -32,9 +39,31 @@ impl Cameras {
                 &self.follow_camera,
         }
     }
+
+
+    // Only moved by the simulation, from the player and the right stick in the inputs. Movement is relative to it,
+    // so replays move the same whichever camera is looked through
+    pub fn follow(&self) -> &dyn camera::Camera {
+        &self.follow_camera
+    }
+
+
+    pub fn follow_mut(&mut self) -> &mut dyn camera::Camera {
+        &mut self.follow_camera
+    }
 }
 
 
+// Mesh data for a model, the SceneRenderer uploads it to the gpu when the revision changes
+pub struct SceneMesh {
+    pub data: render_gl::GltfMesh,
+    pub skinned: bool,
+    pub revision: usize,
+}
+
+
+// All the simulation state, nothing in here needs sdl or a gl context
+// so it can be run headless. Rendering is done by game::SceneRenderer
 pub struct Scene {
 
     // CAMERAS
@@ -42,462 +71,513 @@ pub struct Scene {
 
     pub entities: entity::Entities,
 
-    // MEHES AND SHADERS
-    pub cube_shader: render_gl::Shader,
-    pub mesh_shader: render_gl::Shader,
-    pub world_shader: render_gl::Shader,
-    pub hitbox_shader: render_gl::Shader,
-    pub text_shader: render_gl::Shader,
-
-    // World
+    // World, static geometry like a heightmap and level meshes. Streamed terrain has its own triangles in terrain
     pub world_triangles: Vec::<physics::Triangle>,
     pub world_triangles_tree: qt::QuadTree::<usize>,
 
+    // static world meshes that are rendered with render_world
+    pub world_models: Vec::<String>,
+
     // make this a struct that can keep track of it, with usize ids, but not as a vec index
     // but something where we can add and remove from
-    pub models: std::collections::HashMap<String, entity::Model>,
+    pub meshes: std::collections::HashMap<String, SceneMesh>,
 
-    pub animations: std::collections::HashMap<String, render_gl::PlayerAnimations>,
+    pub animations: std::collections::HashMap<String, render_gl::AnimationSet>,
 
     pub actions: action_system::ActionsImpl,
 
-    pub render_hitboxes: bool,
-
     pub loaded_ais: Option<ai::LoadedAis>,
 
-}
-
-impl Scene {
+    pub scene_name: String,
+    pub description: SceneDescription,
 
-    pub fn new(render_context: &render_gl::context::Context, res_dll: &Resources) -> Result<Scene, failure::Error> {
+    // terrain chunks, streamed in around the player. None when the terrain is not generated
+    pub terrain: Option<Terrain>,
 
-        let mut scene = empty(render_context, res_dll)?;
+    // entity and weapon hitboxes in world space, updated by physics and weapon updates
+    pub broadphase: physics::Broadphase,
 
-        println!("Setup world");
-        scene.setup_world(&render_context.gl)?;
-
-        println!("Setup player");
-        scene.setup_player(&render_context.gl)?;
+    // slopes, steps and gravity for how characters move in the world
+    pub character_controller: physics::CharacterController,
 
-        println!("Setup weapon");
-        scene.load_weapon(&render_context.gl)?;
+    // which targets each swing has hit, and the hits of the last tick
+    pub hits: HitRegistry,
+    pub hit_events: Vec::<HitEvent>,
 
-        println!("Setup enemy");
-        scene.setup_enemy(&render_context.gl)?;
-
-        Ok(scene)
-    }
+}
 
-    fn setup_enemy(&mut self, gl: &gl::Gl) -> Result<(), failure::Error>  {
+impl Scene {
 
-        let enemy_glb_path = "E:/repos/Game-in-rust/blender_models/enemy1.glb";
+    // res is the assets folder, where the scene file and actions are loaded from
+    // terrain_seed overrides the seed in the scene file
+    pub fn new(res: &Resources, res_models: &Resources, scene_name: &str, terrain_seed: Option<u32>, loaded_ais: Option<ai::LoadedAis>) -> Result<Scene, failure::Error> {
 
-        let (skeleton, index_map) = render_gl::Skeleton::from_gltf(&enemy_glb_path)?;
+        let description = SceneDescription::load(res, scene_name)?;
 
-        let animations = load_animations(&enemy_glb_path, &skeleton, None).unwrap();
+        let mut scene = empty(res, scene_name, description.clone(), loaded_ais)?;
 
-        self.animations.insert("enemy1".to_string(), animations.clone());
+        println!("Setup world");
+        scene.setup_world(res, res_models, &description, terrain_seed)?;
 
-        let animation_player = render_gl::AnimationPlayer::new(render_gl::Animation::Idle, animations);
-        let gltf_meshes = render_gl::meshes_from_gltf(&enemy_glb_path, gl, &index_map)?;
+        println!("Setup player");
+        scene.setup_player(res, res_models, &description.player)?;
 
-        let mut bones = Vec::new();
-        let joint_count = skeleton.joints.len();
-        for _ in 0..joint_count {
-            bones.push(na::Matrix4::identity());
+        println!("Setup weapons");
+        for weapon in &description.weapons {
+            scene.load_weapon(res, res_models, weapon)?;
         }
 
-        // MODELS
-        let model_name = "enemy";
-        self.add_skinned_model(gl, model_name, &gltf_meshes);
-
-        let mut enemy = entity::Entity::new(Some(animation_player), model_name.to_string());
-        self.setup_hitboxes(gl, &mut enemy, &gltf_meshes);
+        println!("Setup enemies");
+        scene.setup_enemies(res, res_models, &description.enemies)?;
 
-        enemy.skeleton = skeleton;
-        enemy.bones = bones;
+        println!("Setup props");
+        scene.setup_props(res_models, &description.props)?;
 
-        enemy.base_entity.physics.pos.x += 5.0;
+        scene.update_terrain();
 
-        enemy.base_entity.queued_action = Some(shared::EntityState::Idle);
-        enemy.next_action();
+        Ok(scene)
+    }
 
-        enemy.ai = Some(shared::EntityAi::regular_enemy(4.0));
+    fn setup_enemies(&mut self, res: &Resources, res_models: &Resources, spawns: &[EnemySpawn]) -> Result<(), failure::Error>  {
 
-        let _id = self.entities.enemies.add(enemy);
+        // load each model once, and clone it for every spawn using it
+        let mut loaded = std::collections::HashMap::<String, entity::Entity>::new();
 
+        for (i, spawn) in spawns.iter().enumerate() {
 
-        Ok(())
-    }
+            if !loaded.contains_key(&spawn.model.name) {
+                let enemy = self.load_skinned_entity(res_models, &spawn.model)?;
+                loaded.insert(spawn.model.name.clone(), enemy);
+            }
 
+            let mut enemy = loaded[&spawn.model.name].clone();
 
-    fn load_weapon(&mut self, gl: &gl::Gl) ->  Result<(), failure::Error>  {
-        let glb_path = "E:/repos/Game-in-rust/blender_models/sword.glb";
+            enemy.animation_graph = Some(render_gl::AnimationStateMachine::new(render_gl::AnimationGraph::load(res, &spawn.animation_graph)?));
 
-        let (skeleton, index_map) = render_gl::Skeleton::from_gltf(&glb_path)?;
-        let base_animations = Some(&self.entities.player.animation_player.as_ref().unwrap().animations);
-        let animations = load_animations(&glb_path, &skeleton, base_animations).unwrap();
+            enemy.base_entity.physics.pos = na::Vector3::new(spawn.position[0], spawn.position[1], spawn.position[2]);
 
-        let gltf_meshes = render_gl::meshes_from_gltf(&glb_path, gl, &index_map)?;
-        let model_name = "sword";
+            let z_rot = spawn.rotation.to_radians();
+            enemy.base_entity.physics.rotation = na::UnitQuaternion::from_euler_angles(0.0, 0.0, z_rot);
+            enemy.base_entity.physics.facing_dir = na::Vector3::new(z_rot.cos(), z_rot.sin(), 0.0);
 
-        self.add_model(gl, model_name, &gltf_meshes.meshes[model_name]);
+            enemy.base_entity.queued_action = Some(shared::EntityState::Idle);
+            enemy.next_action();
 
-        let mut weapon = entity::Entity::new(None, model_name.to_string());
-        self.setup_hitboxes(gl, &mut weapon, &gltf_meshes);
+            // seed from the spawn index, so the same scene gives the same ai choices
+            enemy.ai = spawn.ai.entity_ai(i as u64);
 
-        self.animations.insert(model_name.to_string(), animations);
-        self.entities.weapons.add(weapon);
+            let _id = self.entities.enemies.add(enemy);
+        }
 
         Ok(())
     }
 
 
-    fn setup_world(&mut self, gl: &gl::Gl) -> Result<(), failure::Error>  {
+    fn setup_props(&mut self, res_models: &Resources, spawns: &[PropSpawn]) -> Result<(), failure::Error>  {
 
-        let model_name = "world";
+        // load each model once, and clone it for every spawn using it
+        let mut loaded = std::collections::HashMap::<String, entity::Entity>::new();
 
-        let generated = render_gl::perlin_field();
+        for spawn in spawns {
 
-        //let generated = render_gl::triangle();
+            if !loaded.contains_key(&spawn.model.name) {
+                let prop = self.load_static_entity(res_models, &spawn.model)?;
+                loaded.insert(spawn.model.name.clone(), prop);
+            }
 
-        self.world_triangles = generated.triangles();
+            let mut prop = loaded[&spawn.model.name].clone();
 
+            let physics = &mut prop.base_entity.physics;
+            physics.pos = na::Vector3::new(spawn.position[0], spawn.position[1], spawn.position[2]);
+            physics.rotation = na::UnitQuaternion::from_euler_angles(0.0, 0.0, spawn.rotation.to_radians());
+            physics.inverse_mass = spawn.inverse_mass();
+            physics.restitution = spawn.restitution;
+            physics.friction = spawn.friction;
 
-        println!("Inserting triangles");
-        for i in 0..self.world_triangles.len() {
-            self.world_triangles_tree.insert(i, qt::QuadRect::from(self.world_triangles[i]));
+            self.entities.props.add(prop);
         }
 
-        self.add_model(gl, model_name, &generated);
-
         Ok(())
     }
 
-    fn setup_player(&mut self, gl: &gl::Gl) -> Result<(), failure::Error>  {
-
-        let player_glb_path = "E:/repos/Game-in-rust/blender_models/player.glb";
 
-        let (skeleton, index_map) = render_gl::Skeleton::from_gltf(&player_glb_path)?;
+    // Model without animations, with its hitboxes
+    fn load_static_entity(&mut self, res_models: &Resources, model_desc: &ModelDescription) -> Result<entity::Entity, failure::Error> {
+        let glb_path = res_models.path(&model_desc.path);
 
-        let animations = load_animations(&player_glb_path, &skeleton, None).unwrap();
+        let (_, index_map) = render_gl::Skeleton::from_gltf(&glb_path)?;
+        let gltf_meshes = render_gl::meshes_from_gltf(&glb_path, &index_map)?;
+        let model_name = &model_desc.name;
 
-        self.animations.insert("player".to_string(), animations.clone());
+        self.add_model(model_name, &gltf_meshes.meshes[model_name]);
 
-        let animation_player = render_gl::AnimationPlayer::new(render_gl::Animation::Idle, animations);
-        let gltf_meshes = render_gl::meshes_from_gltf(&player_glb_path, gl, &index_map)?;
+        let mut entity = entity::Entity::new(None, model_name.to_string());
+        self.setup_hitboxes(&mut entity, &gltf_meshes);
 
-        let mut bones = Vec::new();
-        let joint_count = skeleton.joints.len();
-        for _ in 0..joint_count {
-            bones.push(na::Matrix4::identity());
-        }
-
-        // MODELS
-        let model_name = "player";
-        self.add_skinned_model(gl, model_name, &gltf_meshes);
+        Ok(entity)
+    }
 
-        let mut player = entity::Entity::new(Some(animation_player), model_name.to_string());
 
-        player.base_entity.physics.pos.x = 0.0;
-        player.base_entity.physics.pos.y = 0.0;
+    fn load_weapon(&mut self, res: &Resources, res_models: &Resources, weapon_desc: &ModelDescription) ->  Result<(), failure::Error>  {
+        let glb_path = res_models.path(&weapon_desc.path);
 
-        self.setup_hitboxes(gl, &mut player, &gltf_meshes);
+        let (skeleton, index_map) = render_gl::Skeleton::from_gltf(&glb_path)?;
+        let base_animations = Some(&self.entities.player.animation_player.as_ref().unwrap().animations);
+        let mut animations = load_animations(&glb_path, &skeleton, base_animations).unwrap();
 
-        player.skeleton = skeleton;
-        player.bones = bones;
+        let gltf_meshes = render_gl::meshes_from_gltf(&glb_path, &index_map)?;
+        let model_name = &weapon_desc.name;
 
+        self.add_model(model_name, &gltf_meshes.meshes[model_name]);
 
-        for hitbox in &player.hitboxes {
-            println!("{:?}",  hitbox.name);
-        }
+        let mut weapon = entity::Entity::new(None, model_name.to_string());
+        self.setup_hitboxes(&mut weapon, &gltf_meshes);
 
-        self.entities.player = player;
+        // given to whoever picks up the weapon
+        let stats = load_weapon_stats(res, model_name)?;
+        add_attack_events(&mut animations, &stats);
+        weapon.base_entity.weapon = Some(stats);
 
+        self.animations.insert(model_name.to_string(), animations);
+        self.entities.weapons.add(weapon);
 
         Ok(())
     }
 
 
+    // Build the terrain and the static level geometry, replacing what was there
+    fn setup_world(&mut self, res: &Resources, res_models: &Resources, description: &SceneDescription, seed: Option<u32>) -> Result<(), failure::Error>  {
 
-    fn setup_hitboxes(&mut self, gl: &gl::Gl, entity: &mut entity::Entity, gltf_meshes: &render_gl::GltfMeshes) {
-
-        let hitboxes = gltf_meshes.hitboxes(&entity.model_name);
-
-        let _entity = entity::add_hitbox_to_entity(entity, &hitboxes);
+        self.clear_world();
 
-        for hb_kv in &hitboxes {
-            self.add_model(gl, &hb_kv.0, &gltf_meshes.meshes[&hb_kv.0]);
-        }
-    }
+        match &description.terrain {
+            TerrainDescription::Perlin(params) => {
+                let mut params = params.clone();
+                if let Some(seed) = seed {
+                    params.seed = seed;
+                }
 
+                self.regenerate_terrain(&params);
+            },
+            TerrainDescription::Heightmap(params) => {
+                let image = res.load_image_luma16(&params.path)?;
 
-    fn add_skinned_model(&mut self, gl: &gl::Gl, name: &str, gltf_meshes: &render_gl::GltfMeshes) {
-        let model_mesh = render_gl::SkinnedMesh::new(gl, &gltf_meshes.meshes[name]);
+                let mesh = render_gl::heightmap_field(&image, params);
+                self.add_world_mesh("heightmap", &mesh, render_gl::MeshUsage::RenderAndCollision);
+            }
+        };
 
-        let model = entity::Model::skinned_model(model_mesh);
+        for level in &description.levels {
+            self.load_level(res_models, level)?;
+        }
 
-        self.models.insert(name.to_string(), model);
+        self.build_world_tree();
 
+        Ok(())
     }
 
 
-    fn add_model(&mut self, gl: &gl::Gl, name: &str, gltf_mesh: &render_gl::GltfMesh) {
-
-        println!("Adding model {:?}", name);
-
-        let model_mesh = render_gl::Mesh::new(gl, gltf_mesh);
-
-
-        let model = entity::Model::mesh(model_mesh);
+    // Replace the generated terrain with one generated from params, dropping all the old chunks.
+    // The renderer uploads the new chunk meshes on the next render
+    pub fn regenerate_terrain(&mut self, params: &render_gl::TerrainParams) {
 
-        self.models.insert(name.to_string(), model);
-    }
-
-    pub fn camera(&self) -> &dyn camera::Camera {
-        match self.cameras.mode {
-            camera::CameraMode::Free =>
-                &self.cameras.free_camera,
-            camera::CameraMode::Follow =>
-                &self.cameras.follow_camera,
+        if let Some(terrain) = &self.terrain {
+            for name in terrain.mesh_names() {
+                self.meshes.remove(&name);
+            }
         }
-    }
 
+        self.terrain = Some(Terrain::new(params.clone()));
 
-    pub fn camera_mut(&mut self) -> &mut dyn camera::Camera {
-        match self.cameras.mode {
-            camera::CameraMode::Free =>
-                &mut self.cameras.free_camera,
-            camera::CameraMode::Follow =>
-                &mut self.cameras.follow_camera,
-        }
+        self.update_terrain();
     }
 
 
-    pub fn update_animations(&mut self, delta: f32) {
+    // Drop the generated terrain and all the static world geometry
+    fn clear_world(&mut self) {
 
-        self.entities.player.update_animations(delta);
+        if let Some(terrain) = self.terrain.take() {
+            for name in terrain.mesh_names() {
+                self.meshes.remove(&name);
+            }
+        }
 
-        /*
-        if self.entities.player.base_entity.physics.falling {
-        println!("falling={} - player Pos={:?}",
-        self.entities.player.base_entity.physics.falling,
-        self.entities.player.base_entity.physics.pos,
-    );
-    }
-         */
-        for enemy in self.entities.enemies.values_mut() {
-            enemy.update_animations(delta);
+        for name in &self.world_models {
+            self.meshes.remove(name);
         }
 
+        self.world_models = Vec::new();
+        self.world_triangles = Vec::new();
+        self.build_world_tree();
     }
 
 
-    //TODO store low_poly_texture id and char maps in render context
-    pub fn render(&mut self, render_context: &mut render_gl::context::Context, low_poly_texture_id: u32, charMap: &std::collections::HashMap<u32, text_render::Character>, bmq: &text_render::BitmapQuad) {
+    // Static meshes from a level .glb, fx walls, ramps and bridges
+    fn load_level(&mut self, res_models: &Resources, path: &str) -> Result<(), failure::Error> {
 
-        // Render meshes
-        self.render_meshes(render_context, low_poly_texture_id);
+        let level_meshes = render_gl::level_meshes_from_gltf(&res_models.path(path))?;
 
-        // Render text
-        //self.render_text(render_context, charMap, bmq);
+        for level_mesh in &level_meshes {
+            // prefix with the file, so level meshes never replace an entity model with the same name
+            let name = format!("{}:{}", path, level_mesh.name);
+            self.add_world_mesh(&name, &level_mesh.mesh, level_mesh.usage);
+        }
 
+        Ok(())
     }
 
 
-    fn render_text(&mut self, render_context: &mut render_gl::context::Context, charMap: &std::collections::HashMap<u32, text_render::Character>, bmq: &text_render::BitmapQuad) {
+    // Add a static mesh to the world, for rendering and/or collision.
+    // The triangles are not in the quadtree before build_world_tree is called
+    fn add_world_mesh(&mut self, name: &str, mesh: &render_gl::GltfMesh, usage: render_gl::MeshUsage) {
 
-        //TODO get height and width from not hard coded
-        let projection = na::Matrix4::new_orthographic(0.0, 700.0, 0.0, 800.0, -1.0, 1.0);
-        self.text_shader.set_projection(&render_context.gl, projection);
+        if usage.collision() {
+            self.world_triangles.extend(mesh.triangles());
+        }
 
-        render_gl::render_text(
-            &render_context.gl,
-            charMap,
-            &mut self.text_shader,
-            &bmq.vao,
-            &bmq.vbo,
-            &V3::new(0.0, 0.0, 1.0));
+        if usage.render() {
+            self.add_model(name, mesh);
+            self.world_models.push(name.to_string());
+        }
     }
 
-    fn render_meshes(&mut self, render_context: &mut render_gl::context::Context, low_poly_texture_id: u32) {
-        let gl = &render_context.gl;
-        // RENDER SCENE WITH CUBE SHADER
-        self.cube_shader.set_used();
-
 
-        let light_pos = na::Vector3::new(0.0, 0.0, 10.0);
+    // Put all the world triangles into a new quadtree, with bounds that fit them
+    fn build_world_tree(&mut self) {
 
-        // CAN BE MOVED OUTSIDE THE LOOP
-        self.cube_shader.set_vec3(gl, "lightPos", light_pos);
-        self.cube_shader.set_vec3(gl, "lightColor", na::Vector3::new(1.0, 1.0, 1.0));
-
-        self.cube_shader.set_projection_and_view(gl, self.camera().projection(), self.camera().view());
+        // a bit of margin, and at least the old -100..100
+        let mut half_size = 100;
+        for t in &self.world_triangles {
+            for v in &[t.v0, t.v1, t.v2] {
+                half_size = i32::max(half_size, f32::max(v.x.abs(), v.y.abs()).ceil() as i32 + 1);
+            }
+        }
 
-        // RENDER WITH MESH SHADER
+        self.world_triangles_tree = qt::QuadTree::new(qt::QuadRect::new(qt::QuadPoint {x: -half_size, y: -half_size}, qt::QuadPoint{ x: half_size, y: half_size}));
 
-        // Set low_poly_texture as active
-        render_gl::texture::set_texture(&render_context.gl, low_poly_texture_id);
+        println!("Inserting triangles");
+        for i in 0..self.world_triangles.len() {
+            self.world_triangles_tree.insert(i, qt::Aabb::from(self.world_triangles[i]));
+        }
+    }
 
 
-        self.mesh_shader.set_used();
-        self.mesh_shader.set_vec3(gl, "lightPos", light_pos);
-        self.mesh_shader.set_vec3(gl, "lightColor", na::Vector3::new(1.0, 1.0, 1.0));
-        self.mesh_shader.set_vec3(gl, "viewPos", self.camera().pos());
+    // Stream terrain chunks in and out around the player, and keep collision loaded under every entity and prop
+    pub fn update_terrain(&mut self) {
 
-        self.mesh_shader.set_projection_and_view(gl, self.camera().projection(), self.camera().view());
+        let view_pos = self.entities.player.base_entity.physics.pos;
 
+        let mut entity_positions = vec![view_pos];
+        for enemy in self.entities.enemies.values() {
+            entity_positions.push(enemy.base_entity.physics.pos);
+        }
+        for prop in self.entities.props.values() {
+            entity_positions.push(prop.base_entity.physics.pos);
+        }
 
-        for entity in self.entities.values() {
-            let model = &self.models[&entity.model_name];
-            render_gl::render_entity(&entity, model, gl, &self.mesh_shader);
+        let changes = match &mut self.terrain {
+            Some(terrain) => terrain.update(view_pos, &entity_positions),
+            None => return
+        };
 
-            match &entity.weapon {
-                Some(w) => {
-                    let model = &self.models[&w.model_name];
-                    render_gl::render_entity(&w, model, gl, &self.mesh_shader);
-                },
-                None => {}
-            };
+        for name in &changes.removed {
+            self.meshes.remove(name);
+        }
 
+        for (name, mesh) in changes.added {
+            self.set_mesh(&name, mesh, false);
         }
+    }
 
 
-        let model = &self.models["world"];
-        render_gl::render_world(model, gl, &self.mesh_shader);
+    // World and terrain triangles within radius of world x y, looking down along z
+    pub fn world_triangles_near(&self, x: f32, y: f32, radius: f32) -> Vec::<physics::Triangle> {
 
+        let mut triangles: Vec::<physics::Triangle> = self.world_triangles_tree.query_circle(na::Vector2::new(x, y), radius)
+            .iter()
+            .map(|i| self.world_triangles[**i])
+            .collect();
 
-        if self.render_hitboxes {
-            self.render_hitboxes(render_context);
+        if let Some(terrain) = &self.terrain {
+            triangles.extend(terrain.triangles_near(x, y, radius));
         }
 
-        self.render_ik_targets(render_context);
+        triangles
     }
 
+    fn setup_player(&mut self, res: &Resources, res_models: &Resources, player_desc: &PlayerDescription) -> Result<(), failure::Error>  {
 
-    fn render_ik_targets(&mut self, render_context: &mut render_gl::context::Context) {
+        let mut player = self.load_skinned_entity(res_models, &player_desc.model)?;
 
-        let skeleton = &self.entities.player.skeleton;
-        let gl = &render_context.gl;
+        player.animation_graph = Some(render_gl::AnimationStateMachine::new(render_gl::AnimationGraph::load(res, &player_desc.animation_graph)?));
 
-        let ik_legs = match skeleton.legs {
-            None =>  {return;},
-            Some(ref legs) => legs
-        };
+        player.base_entity.physics.pos = na::Vector3::new(player_desc.position[0], player_desc.position[1], player_desc.position[2]);
 
-        let clr = na::Vector3::new(1.0, 0.0, 0.0);
-        let _cube_model = cube::Cube::new(clr, gl);
+        self.entities.player = player;
 
-        let mut scale_mat = na::Matrix4::identity();
+        Ok(())
+    }
 
 
-        scale_mat = scale_mat * 0.2;
-        scale_mat[15] = 1.0;
+    // Load a skinned and animated entity, like the player or an enemy, from a glb file
+    fn load_skinned_entity(&mut self, res_models: &Resources, model_desc: &ModelDescription) -> Result<entity::Entity, failure::Error> {
 
-        self.cube_shader.set_used();
-        let proj = self.camera().projection();
-        let view = self.camera().view();
-        self.cube_shader.set_projection_and_view(gl, proj, view);
+        let glb_path = res_models.path(&model_desc.path);
 
-        // maybe do this and translation to the ik.target.translation and rotation
-        // so render is just ik.target.translation for tran, and . rotation for rot
-        // As it is now we might forget go get it in correct spot at some point
-        // on the other hand no, we need to store base target anyways
+        let (skeleton, index_map) = render_gl::Skeleton::from_gltf(&glb_path)?;
 
-        // Current next target, and relative target for left leg
-        self.render_pos(na::Vector3::new(1.0, 1.0, 1.0), render_context, &ik_legs.left_leg.current_target());
+        let animations = load_animations(&glb_path, &skeleton, None).unwrap();
 
-        match ik_legs.next_targets() {
-            (Some(left_target), Some(right_target)) => {
-                self.render_pos(na::Vector3::new(0.0, 0.0, 0.0), render_context, &left_target);
-                self.render_pos(na::Vector3::new(0.0, 0.0, 0.0), render_context, &right_target);
-            },
-            (None, Some(right_target)) => {
-                self.render_pos(na::Vector3::new(0.0, 0.0, 0.0), render_context, &right_target);
+        self.animations.insert(model_desc.name.clone(), animations.clone());
 
-            },
-            (Some(left_target), None) => {
-                self.render_pos(na::Vector3::new(0.0, 0.0, 0.0), render_context, &left_target);
-            },
-            _ => {}
-        };
+        let animation_player = render_gl::AnimationPlayer::new("idle", animations);
+        let gltf_meshes = render_gl::meshes_from_gltf(&glb_path, &index_map)?;
 
+        let mut bones = Vec::new();
+        let joint_count = skeleton.joints.len();
+        for _ in 0..joint_count {
+            bones.push(na::Matrix4::identity());
+        }
+
+        // MODELS
+        let model_name = &model_desc.name;
+        self.add_skinned_model(model_name, &gltf_meshes);
 
-        //self.render_pos(na::Vector3::new(0.0, 0.0, 1.0), render_context, &(ik_legs.relative_target + self.entities.player.base_entity.physics.pos));
+        let mut entity = entity::Entity::new(Some(animation_player), model_name.to_string());
+        self.setup_hitboxes(&mut entity, &gltf_meshes);
 
+        entity.skeleton = skeleton;
+        entity.bones = bones;
+
+        Ok(entity)
+    }
 
-        // skeleton joints positions left leg
-        self.render_pos(na::Vector3::new(0.0, 1.0, 0.0), render_context, &(ik_legs.left_leg.joint_pos(1, &skeleton.joints) + self.entities.player.base_entity.physics.pos));
 
-        self.render_pos(na::Vector3::new(0.0, 1.0, 0.0), render_context, &(ik_legs.left_leg.joint_pos(2, &skeleton.joints) + self.entities.player.base_entity.physics.pos));
 
+    fn setup_hitboxes(&mut self, entity: &mut entity::Entity, gltf_meshes: &render_gl::GltfMeshes) {
 
+        let hitboxes = gltf_meshes.hitboxes(&entity.model_name);
 
-        //self.render_pos(render_context, &ik_legs.target.translation);
+        let _entity = entity::add_hitbox_to_entity(entity, &hitboxes);
 
+        for hb_kv in &hitboxes {
+            self.add_model(&hb_kv.0, &gltf_meshes.meshes[&hb_kv.0]);
+        }
     }
 
-    fn render_pos(&self, clr: na::Vector3::<f32>, render_context: &mut render_gl::context::Context, pos: &na::Vector3::<f32>) {
 
-        let gl = &render_context.gl;
+    fn add_skinned_model(&mut self, name: &str, gltf_meshes: &render_gl::GltfMeshes) {
+        self.set_mesh(name, gltf_meshes.meshes[name].clone(), true);
+    }
 
-        let cube_model = cube::Cube::new(clr, gl);
 
-        let mut scale_mat = na::Matrix4::identity();
+    fn add_model(&mut self, name: &str, gltf_mesh: &render_gl::GltfMesh) {
 
+        println!("Adding model {:?}", name);
 
-        scale_mat = scale_mat * 0.1;
-        scale_mat[15] = 1.0;
+        self.set_mesh(name, gltf_mesh.clone(), false);
+    }
 
-        self.cube_shader.set_used();
-        let proj = self.camera().projection();
-        let view = self.camera().view();
-        self.cube_shader.set_projection_and_view(gl, proj, view);
 
-        let trans_mat_world = na::Matrix4::new_translation(&pos);
+    // Add or replace the mesh data for a model
+    pub fn set_mesh(&mut self, name: &str, data: render_gl::GltfMesh, skinned: bool) {
 
+        let revision = NEXT_MESH_REVISION.fetch_add(1, Ordering::Relaxed);
 
-        cube_model.render(gl, &self.cube_shader, trans_mat_world * scale_mat);
+        self.meshes.insert(name.to_string(), SceneMesh {
+            data,
+            skinned,
+            revision
+        });
+    }
 
+    pub fn camera(&self) -> &dyn camera::Camera {
+        match self.cameras.mode {
+            camera::CameraMode::Free =>
+                &self.cameras.free_camera,
+            camera::CameraMode::Follow =>
+                &self.cameras.follow_camera,
+        }
     }
 
-    fn render_hitboxes(&mut self, render_context: &mut render_gl::context::Context) {
-        let mut switched = false;
-        if !render_context.wire_frame {
-            switched = true;
-            render_context.switch_mode();
+
+    pub fn camera_mut(&mut self) -> &mut dyn camera::Camera {
+        match self.cameras.mode {
+            camera::CameraMode::Free =>
+                &mut self.cameras.free_camera,
+            camera::CameraMode::Follow =>
+                &mut self.cameras.follow_camera,
         }
+    }
 
 
-        //RENDER HITBOXES
+    pub fn update_animations(&mut self, delta: f32) {
 
-        let gl = &render_context.gl;
+        self.entities.player.update_animations(delta);
 
-        self.hitbox_shader.set_used();
-        self.hitbox_shader.set_projection_and_view(gl, self.camera().projection(), self.camera().view());
+        for enemy in self.entities.enemies.values_mut() {
+            enemy.update_animations(delta);
+        }
 
+    }
 
 
-        for entity in self.entities.hitbox_entities() {
-            match entity.is_hit {
-                true => self.hitbox_shader.set_vec3(gl, "color", na::Vector3::new(1.0, 0.0, 0.0)),
-                false => self.hitbox_shader.set_vec3(gl, "color", na::Vector3::new(1.0, 1.0, 1.0))
-            };
+    // Seed of the generated terrain, None when the terrain is not generated
+    pub fn terrain_seed(&self) -> Option<u32> {
+        self.terrain.as_ref().map(|terrain| terrain.params.seed)
+    }
 
-            for hitbox in &entity.hitboxes {
-                let col_box = hitbox.make_transformed(entity.base_entity.physics.pos, entity.base_entity.physics.rotation);
 
-                let clr = na::Vector3::new(1.0, 1.0, 0.0);
-                let cube_model = cube::Cube::from_collision_box(col_box, clr, gl);
+    // Reload the scene file, and rebuild the scene if it changed on disk
+    pub fn reload_description(&mut self, res: &Resources, res_models: &Resources) {
 
-                cube_model.render(gl, &self.hitbox_shader, na::Matrix4::identity());
+        let description = match SceneDescription::load(res, &self.scene_name) {
+            Ok(desc) => desc,
+            Err(err) => {
+                println!("Error loading {}: {}", self.scene_name, err);
+                return;
             }
+        };
+
+        if description == self.description {
+            return;
         }
 
-        if switched {
-            render_context.switch_mode();
+        // keep the current seed, it might be from --seed, unless the seed in the file changed
+        let seed = match (&description.terrain, &self.description.terrain) {
+            (TerrainDescription::Perlin(new), TerrainDescription::Perlin(old)) if new.seed == old.seed => self.terrain_seed(),
+            _ => None
+        };
+
+        // only the terrain or levels changed, rebuild the world and keep entities where they are
+        let mut same_besides_world = description.clone();
+        same_besides_world.terrain = self.description.terrain.clone();
+        same_besides_world.levels = self.description.levels.clone();
+
+        if same_besides_world == self.description {
+            println!("Rebuilding world");
+            match self.setup_world(res, res_models, &description, seed) {
+                Ok(_) => {
+                    self.update_terrain();
+                    self.description = description;
+                },
+                Err(err) => println!("Error building world: {}", err),
+            };
+            return;
         }
 
+        match Scene::new(res, res_models, &self.scene_name, seed, None) {
+            Ok(mut new_scene) => {
+                println!("Reloaded {}", self.scene_name);
+                new_scene.cameras.mode = self.cameras.mode;
+                // keep the already loaded ais
+                new_scene.loaded_ais = self.loaded_ais.take();
+                *self = new_scene;
+            },
+            Err(err) => {
+                println!("Error building {}: {}", self.scene_name, err);
+            }
+        };
     }
 
     pub fn reload_ais(&mut self, res_dll: &Resources) {
@@ -510,32 +590,12 @@ impl Scene {
 
     }
 
-    pub fn reload_shaders(&mut self, render_context: &render_gl::context::Context) {
-
-        let shaders = vec![("world_shader", &mut self.world_shader),
-                           ("mesh_shader", &mut self.mesh_shader),
-                           ("hitbox_shader", &mut self.hitbox_shader)];
-
-        for (name, shader) in shaders {
-            match render_gl::Shader::new(name, &render_context.res, &render_context.gl) {
-                Ok(new_shader) => {
-
-                    println!("Reloaded {}", name);
-                    *shader = new_shader;
-                },
-                Err(err) => {
-                    println!("Error loading {}: {}", name, err);
-                }
-            };
-        }
-    }
-
 }
 
 
 
 
-fn empty(render_context: &render_gl::context::Context, res_dll: &Resources) -> Result<Scene, failure::Error> {
+fn empty(res: &Resources, scene_name: &str, description: SceneDescription, loaded_ais: Option<ai::LoadedAis>) -> Result<Scene, failure::Error> {
 
     let width = 700;
     let height = 800;
@@ -545,21 +605,7 @@ fn empty(render_context: &render_gl::context::Context, res_dll: &Resources) -> R
     let free_camera = camera::FreeCamera::new();
 
 
-    let cube_shader = render_gl::Shader::new("light_color_shader", &render_context.res, &render_context.gl)?;
-
-    let mesh_shader = render_gl::Shader::new("mesh_shader", &render_context.res, &render_context.gl)?;
-
-    let world_shader = render_gl::Shader::new("world_shader", &render_context.res, &render_context.gl)?;
-
-    let hitbox_shader = render_gl::Shader::new("hitbox_shader", &render_context.res, &render_context.gl)?;
-
-    let text_shader = render_gl::Shader::new("text_shader", &render_context.res, &render_context.gl)?;
-
-    let actions = action_system::load_player_actions(&render_context.res)?;
-
-
-    //TODO make single function to reload all ais
-    let loaded_ais = Some(ai::load_ais(res_dll));
+    let actions = action_system::load_player_actions(res)?;
 
 
     let cameras = Cameras {
@@ -570,25 +616,53 @@ fn empty(render_context: &render_gl::context::Context, res_dll: &Resources) -> R
 
 
     Ok(Scene {
-        mesh_shader,
-        world_shader,
         actions,
-        cube_shader,
-        hitbox_shader,
         entities,
-        text_shader,
         cameras,
-        models: std::collections::HashMap::new(),
+        meshes: std::collections::HashMap::new(),
         animations: std::collections::HashMap::new(),
         loaded_ais,
-        render_hitboxes: false,
+        scene_name: scene_name.to_string(),
+        description,
+        terrain: None,
+        broadphase: physics::Broadphase::new(),
+        character_controller: physics::CharacterController::default(),
+        hits: HitRegistry::new(),
+        hit_events: Vec::new(),
         world_triangles: Vec::new(),
+        world_models: Vec::new(),
         world_triangles_tree: qt::QuadTree::new(qt::QuadRect::new(qt::QuadPoint {x: -100, y: -100}, qt::QuadPoint{ x: 100, y: 100})),
 
     })
 }
 
-fn load_animations(file_path: &str, skeleton: &render_gl::Skeleton, base_animations: Option<&render_gl::PlayerAnimations>) -> Option<render_gl::PlayerAnimations>{
+// Attack animations without events get hit and combo events from the frames in the weapon stats.
+// When attacks share an animation the first one sets its events
+fn add_attack_events(animations: &mut render_gl::AnimationSet, stats: &shared::WeaponStats) {
+
+    let event = |name: &str, frame: usize| render_gl::AnimationEvent { name: name.to_string(), frame };
+
+    for (combo_num, attack) in stats.attacks.iter().enumerate() {
+        let animation = match animations.get_mut(&stats.attack_animation(combo_num)) {
+            Some(animation) => animation,
+            None => continue,
+        };
+
+        if !animation.events.is_empty() {
+            continue;
+        }
+
+        animation.set_events(vec![
+            event("hit_start", attack.hit_start_frame),
+            event("hit_end", attack.hit_end_frame),
+            event("combo_window_open", attack.combo_start_frame),
+            event("combo_window_close", attack.combo_end_frame),
+        ]);
+    }
+}
+
+
+fn load_animations(file_path: &Path, skeleton: &render_gl::Skeleton, base_animations: Option<&render_gl::AnimationSet>) -> Option<render_gl::AnimationSet>{
 
     let animations = match render_gl::load_animations(file_path, &skeleton, base_animations) {
         Ok(key_frames) => key_frames,
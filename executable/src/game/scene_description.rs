@@ -0,0 +1,301 @@
+use serde::Deserialize;
+
+use crate::render_gl::{TerrainParams, HeightmapParams};
+use crate::resources::{self, Resources};
+
+
+// Describes what a scene is made of, loaded from a .ron file in assets/scenes/
+// Scene::new builds the actual entities and models from this
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SceneDescription {
+    pub terrain: TerrainDescription,
+    // static level .glb files in blender_models/, see render_gl::level_meshes_from_gltf
+    #[serde(default)]
+    pub levels: Vec<String>,
+    pub player: PlayerDescription,
+    #[serde(default)]
+    pub weapons: Vec<ModelDescription>,
+    #[serde(default)]
+    pub enemies: Vec<EnemySpawn>,
+    #[serde(default)]
+    pub props: Vec<PropSpawn>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum TerrainDescription {
+    // missing params use TerrainParams::default()
+    Perlin(TerrainParams),
+    // a grayscale image in the assets folder, fx Heightmap((path: "heightmaps/hills.png", size: 200.0))
+    Heightmap(HeightmapParams),
+}
+
+
+// name is the name of the mesh node in the .glb file, hitboxes are found from this name
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ModelDescription {
+    pub name: String,
+    pub path: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PlayerDescription {
+    pub model: ModelDescription,
+    #[serde(default)]
+    pub position: [f32; 3],
+    // in assets/, picks the clips to play, see render_gl::AnimationGraph
+    #[serde(default = "default_animation_graph")]
+    pub animation_graph: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EnemySpawn {
+    pub model: ModelDescription,
+    #[serde(default)]
+    pub position: [f32; 3],
+    // rotation around z in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub ai: AiDescription,
+    #[serde(default = "default_animation_graph")]
+    pub animation_graph: String,
+}
+
+
+fn default_animation_graph() -> String {
+    "animation_graphs/character.ron".to_string()
+}
+
+
+// A loose object like a barrel or a crate, pushed around by characters and falling with gravity
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PropSpawn {
+    pub model: ModelDescription,
+    #[serde(default)]
+    pub position: [f32; 3],
+    // rotation around z in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    // 0 is a prop that never moves
+    #[serde(default = "default_prop_mass")]
+    pub mass: f32,
+    #[serde(default = "default_prop_restitution")]
+    pub restitution: f32,
+    #[serde(default = "default_prop_friction")]
+    pub friction: f32,
+}
+
+
+fn default_prop_mass() -> f32 {
+    20.0
+}
+
+fn default_prop_restitution() -> f32 {
+    0.2
+}
+
+fn default_prop_friction() -> f32 {
+    0.6
+}
+
+
+impl PropSpawn {
+
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass <= 0.0 {
+            return 0.0;
+        }
+
+        1.0 / self.mass
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum AiDescription {
+    None,
+    RegularEnemy { distance: f32 },
+}
+
+impl Default for AiDescription {
+    fn default() -> Self {
+        AiDescription::None
+    }
+}
+
+
+impl AiDescription {
+
+    // seed for the ai's random choices
+    pub fn entity_ai(&self, seed: u64) -> Option<shared::EntityAi> {
+        match self {
+            AiDescription::None => None,
+            AiDescription::RegularEnemy { distance } => Some(shared::EntityAi::regular_enemy(*distance, seed)),
+        }
+    }
+}
+
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to load resource {}", name)]
+    ResourceLoad { name: String, inner: resources::Error },
+    #[fail(display = "Can not parse the scene {}: {}", name, inner)]
+    SceneParseFailed { name: String, inner: ron::de::Error },
+    #[fail(display = "Can not parse the weapon {}: {}", name, inner)]
+    WeaponParseFailed { name: String, inner: ron::de::Error },
+}
+
+
+// Stats of the weapon from weapons/<name>.ron in the assets folder, or the default stats when there is no file
+pub fn load_weapon_stats(res: &Resources, name: &str) -> Result<shared::WeaponStats, Error> {
+
+    let file_name = format!("weapons/{}.ron", name);
+
+    if !res.path(&file_name).exists() {
+        println!("No {}, using default weapon stats", file_name);
+        return Ok(shared::WeaponStats::default());
+    }
+
+    let weapon_data = res.load_string(&file_name)
+        .map_err(|e| Error::ResourceLoad {
+            name: file_name.clone(),
+            inner: e
+        })?;
+
+    ron::de::from_str(&weapon_data)
+        .map_err(|e| Error::WeaponParseFailed {
+            name: file_name,
+            inner: e
+        })
+}
+
+
+impl SceneDescription {
+
+    pub fn load(res: &Resources, name: &str) -> Result<SceneDescription, Error> {
+
+        let scene_data = res.load_string(name)
+            .map_err(|e| Error::ResourceLoad {
+                name: name.into(),
+                inner: e
+            })?;
+
+        ron::de::from_str(&scene_data)
+            .map_err(|e| Error::SceneParseFailed {
+                name: name.into(),
+                inner: e
+            })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_scene() {
+
+        let data = r#"
+(
+    terrain: Perlin((seed: 3, octaves: 4, domain_warp: Some((strength: 0.5, scale: 2.0)))),
+    levels: ["levels/castle.glb"],
+    player: (
+        model: (name: "player", path: "player.glb"),
+    ),
+    weapons: [
+        (name: "sword", path: "sword.glb"),
+    ],
+    enemies: [
+        (
+            model: (name: "enemy", path: "enemy1.glb"),
+            position: (5.0, 0.0, 0.0),
+            rotation: 90.0,
+            ai: RegularEnemy(distance: 4.0),
+        ),
+        (
+            model: (name: "enemy", path: "enemy1.glb"),
+        ),
+    ],
+    props: [
+        (model: (name: "barrel", path: "barrel.glb"), position: (2.0, 2.0, 1.0), mass: 0.0, restitution: 0.5),
+        (model: (name: "crate", path: "crate.glb")),
+    ],
+)
+"#;
+
+        let desc: SceneDescription = ron::de::from_str(data).unwrap();
+
+        assert_eq!(desc.levels, vec!["levels/castle.glb".to_string()]);
+        assert_eq!(desc.player.position, [0.0, 0.0, 0.0]);
+        assert_eq!(desc.player.animation_graph, "animation_graphs/character.ron");
+        assert_eq!(desc.weapons.len(), 1);
+        assert_eq!(desc.enemies.len(), 2);
+        assert_eq!(desc.enemies[0].ai, AiDescription::RegularEnemy { distance: 4.0 });
+        assert_eq!(desc.enemies[1].ai, AiDescription::None);
+        assert_eq!(desc.enemies[1].rotation, 0.0);
+
+        assert_eq!(desc.props.len(), 2);
+        assert_eq!(desc.props[0].inverse_mass(), 0.0);
+        assert_eq!(desc.props[0].restitution, 0.5);
+        assert_eq!(desc.props[1].inverse_mass(), 1.0 / 20.0);
+        assert_eq!(desc.props[1].friction, 0.6);
+
+        match desc.terrain {
+            TerrainDescription::Perlin(params) => {
+                assert_eq!(params.seed, 3);
+                assert_eq!(params.octaves, 4);
+                assert_eq!(params.domain_warp, Some(crate::render_gl::DomainWarp { strength: 0.5, scale: 2.0 }));
+                assert_eq!(params.resolution, TerrainParams::default().resolution);
+            },
+            _ => panic!("Expected perlin terrain")
+        };
+    }
+
+
+    #[test]
+    fn parse_weapon_stats() {
+
+        let data = r#"
+(
+    damage: 40.0,
+    hit_stop: 0.1,
+    attacks: [
+        (hit_start_frame: 5, hit_end_frame: 12, combo_start_frame: 8, combo_end_frame: 20),
+        (hit_start_frame: 7, hit_end_frame: 15, combo_start_frame: 10, combo_end_frame: 25),
+    ],
+)
+"#;
+
+        let stats: shared::WeaponStats = ron::de::from_str(data).unwrap();
+
+        assert_eq!(stats.damage, 40.0);
+        assert_eq!(stats.reach, shared::WeaponStats::default().reach);
+
+        assert_eq!(stats.attacks[1].hit_start_frame, 7);
+        assert_eq!(stats.attacks[1].combo_end_frame, 25);
+        assert_eq!(stats.attack_info(1).combo_num, 1);
+
+        // the combo starts over after the last attack
+        assert_eq!(stats.attack_info(2).combo_num, 0);
+    }
+
+
+    #[test]
+    fn parse_heightmap_terrain() {
+        let terrain: TerrainDescription = ron::de::from_str(r#"Heightmap((path: "heightmaps/hills.png", size: 250.0, max_height: 20.0))"#).unwrap();
+
+        assert_eq!(terrain, TerrainDescription::Heightmap(HeightmapParams {
+            path: "heightmaps/hills.png".to_string(),
+            size: 250.0,
+            min_height: 0.0,
+            max_height: 20.0,
+        }));
+    }
+}
@@ -0,0 +1,318 @@
+use nalgebra as na;
+
+use crate::cube;
+use crate::entity;
+use crate::render_gl;
+use crate::game::Scene;
+use crate::text_render;
+use crate::types::*;
+
+
+// Owns everything gpu related for a scene, shaders and the uploaded models.
+// Models are created from the scene meshes, and recreated when the mesh revision changes
+pub struct SceneRenderer {
+    pub cube_shader: render_gl::Shader,
+    pub mesh_shader: render_gl::Shader,
+    pub world_shader: render_gl::Shader,
+    pub hitbox_shader: render_gl::Shader,
+    pub text_shader: render_gl::Shader,
+
+    // model name to (mesh revision, model)
+    models: std::collections::HashMap<String, (usize, entity::Model)>,
+
+    pub render_hitboxes: bool,
+}
+
+
+impl SceneRenderer {
+
+    pub fn new(render_context: &render_gl::context::Context) -> Result<SceneRenderer, failure::Error> {
+
+        let cube_shader = render_gl::Shader::new("light_color_shader", &render_context.res, &render_context.gl)?;
+
+        let mesh_shader = render_gl::Shader::new("mesh_shader", &render_context.res, &render_context.gl)?;
+
+        let world_shader = render_gl::Shader::new("world_shader", &render_context.res, &render_context.gl)?;
+
+        let hitbox_shader = render_gl::Shader::new("hitbox_shader", &render_context.res, &render_context.gl)?;
+
+        let text_shader = render_gl::Shader::new("text_shader", &render_context.res, &render_context.gl)?;
+
+        Ok(SceneRenderer {
+            cube_shader,
+            mesh_shader,
+            world_shader,
+            hitbox_shader,
+            text_shader,
+            models: std::collections::HashMap::new(),
+            render_hitboxes: false,
+        })
+    }
+
+
+    // Upload new and changed meshes, and drop models no longer in the scene
+    pub fn sync_models(&mut self, gl: &gl::Gl, scene: &Scene) {
+
+        self.models.retain(|name, _| scene.meshes.contains_key(name));
+
+        for (name, mesh) in &scene.meshes {
+
+            let up_to_date = match self.models.get(name) {
+                Some((revision, _)) => *revision == mesh.revision,
+                None => false
+            };
+
+            if up_to_date {
+                continue;
+            }
+
+            let model = match mesh.skinned {
+                true => entity::Model::skinned_model(render_gl::SkinnedMesh::new(gl, &mesh.data)),
+                false => entity::Model::mesh(render_gl::Mesh::new(gl, &mesh.data)),
+            };
+
+            self.models.insert(name.clone(), (mesh.revision, model));
+        }
+    }
+
+
+    //TODO store low_poly_texture id and char maps in render context
+    // alpha is how far we are between the previous and the current simulation tick
+    pub fn render(&mut self, scene: &Scene, render_context: &mut render_gl::context::Context, alpha: f32, low_poly_texture_id: u32, charMap: &std::collections::HashMap<u32, text_render::Character>, bmq: &text_render::BitmapQuad) {
+
+        self.sync_models(&render_context.gl, scene);
+
+        // Render meshes
+        self.render_meshes(scene, render_context, alpha, low_poly_texture_id);
+
+        // Render text
+        //self.render_text(render_context, charMap, bmq);
+
+    }
+
+
+    fn render_text(&mut self, render_context: &mut render_gl::context::Context, charMap: &std::collections::HashMap<u32, text_render::Character>, bmq: &text_render::BitmapQuad) {
+
+        //TODO get height and width from not hard coded
+        let projection = na::Matrix4::new_orthographic(0.0, 700.0, 0.0, 800.0, -1.0, 1.0);
+        self.text_shader.set_projection(&render_context.gl, projection);
+
+        render_gl::render_text(
+            &render_context.gl,
+            charMap,
+            &mut self.text_shader,
+            &bmq.vao,
+            &bmq.vbo,
+            &V3::new(0.0, 0.0, 1.0));
+    }
+
+    fn render_meshes(&mut self, scene: &Scene, render_context: &mut render_gl::context::Context, alpha: f32, low_poly_texture_id: u32) {
+        let gl = &render_context.gl;
+        // RENDER SCENE WITH CUBE SHADER
+        self.cube_shader.set_used();
+
+
+        let light_pos = na::Vector3::new(0.0, 0.0, 10.0);
+
+        // CAN BE MOVED OUTSIDE THE LOOP
+        self.cube_shader.set_vec3(gl, "lightPos", light_pos);
+        self.cube_shader.set_vec3(gl, "lightColor", na::Vector3::new(1.0, 1.0, 1.0));
+
+        self.cube_shader.set_projection_and_view(gl, scene.camera().projection(), scene.camera().view());
+
+        // RENDER WITH MESH SHADER
+
+        // Set low_poly_texture as active
+        render_gl::texture::set_texture(&render_context.gl, low_poly_texture_id);
+
+
+        self.mesh_shader.set_used();
+        self.mesh_shader.set_vec3(gl, "lightPos", light_pos);
+        self.mesh_shader.set_vec3(gl, "lightColor", na::Vector3::new(1.0, 1.0, 1.0));
+        self.mesh_shader.set_vec3(gl, "viewPos", scene.camera().pos());
+
+        self.mesh_shader.set_projection_and_view(gl, scene.camera().projection(), scene.camera().view());
+
+
+        for entity in scene.entities.values() {
+            let model = &self.models[&entity.model_name].1;
+            render_gl::render_entity(&entity, model, gl, &self.mesh_shader, alpha);
+
+            match &entity.weapon {
+                Some(w) => {
+                    let model = &self.models[&w.model_name].1;
+                    render_gl::render_entity(&w, model, gl, &self.mesh_shader, alpha);
+                },
+                None => {}
+            };
+
+        }
+
+
+        if let Some(terrain) = &scene.terrain {
+            for name in terrain.mesh_names() {
+                let model = &self.models[&name].1;
+                render_gl::render_world(model, gl, &self.mesh_shader);
+            }
+        }
+
+        for name in &scene.world_models {
+            let model = &self.models[name].1;
+            render_gl::render_world(model, gl, &self.mesh_shader);
+        }
+
+
+        if self.render_hitboxes {
+            self.render_hitboxes(scene, render_context);
+        }
+
+        self.render_ik_targets(scene, render_context);
+    }
+
+
+    fn render_ik_targets(&mut self, scene: &Scene, render_context: &mut render_gl::context::Context) {
+
+        let skeleton = &scene.entities.player.skeleton;
+        let gl = &render_context.gl;
+
+        let ik_legs = match skeleton.legs {
+            None =>  {return;},
+            Some(ref legs) => legs
+        };
+
+        let clr = na::Vector3::new(1.0, 0.0, 0.0);
+        let _cube_model = cube::Cube::new(clr, gl);
+
+        let mut scale_mat = na::Matrix4::identity();
+
+
+        scale_mat = scale_mat * 0.2;
+        scale_mat[15] = 1.0;
+
+        self.cube_shader.set_used();
+        let proj = scene.camera().projection();
+        let view = scene.camera().view();
+        self.cube_shader.set_projection_and_view(gl, proj, view);
+
+        // maybe do this and translation to the ik.target.translation and rotation
+        // so render is just ik.target.translation for tran, and . rotation for rot
+        // As it is now we might forget go get it in correct spot at some point
+        // on the other hand no, we need to store base target anyways
+
+        // Current next target, and relative target for left leg
+        self.render_pos(scene, na::Vector3::new(1.0, 1.0, 1.0), render_context, &ik_legs.left_leg.current_target());
+
+        match ik_legs.next_targets() {
+            (Some(left_target), Some(right_target)) => {
+                self.render_pos(scene, na::Vector3::new(0.0, 0.0, 0.0), render_context, &left_target);
+                self.render_pos(scene, na::Vector3::new(0.0, 0.0, 0.0), render_context, &right_target);
+            },
+            (None, Some(right_target)) => {
+                self.render_pos(scene, na::Vector3::new(0.0, 0.0, 0.0), render_context, &right_target);
+
+            },
+            (Some(left_target), None) => {
+                self.render_pos(scene, na::Vector3::new(0.0, 0.0, 0.0), render_context, &left_target);
+            },
+            _ => {}
+        };
+
+
+        //self.render_pos(na::Vector3::new(0.0, 0.0, 1.0), render_context, &(ik_legs.relative_target + scene.entities.player.base_entity.physics.pos));
+
+
+        // skeleton joints positions left leg
+        self.render_pos(scene, na::Vector3::new(0.0, 1.0, 0.0), render_context, &(ik_legs.left_leg.joint_pos(1, &skeleton.joints) + scene.entities.player.base_entity.physics.pos));
+
+        self.render_pos(scene, na::Vector3::new(0.0, 1.0, 0.0), render_context, &(ik_legs.left_leg.joint_pos(2, &skeleton.joints) + scene.entities.player.base_entity.physics.pos));
+
+
+
+        //self.render_pos(render_context, &ik_legs.target.translation);
+
+    }
+
+    fn render_pos(&self, scene: &Scene, clr: na::Vector3::<f32>, render_context: &mut render_gl::context::Context, pos: &na::Vector3::<f32>) {
+
+        let gl = &render_context.gl;
+
+        let cube_model = cube::Cube::new(clr, gl);
+
+        let mut scale_mat = na::Matrix4::identity();
+
+
+        scale_mat = scale_mat * 0.1;
+        scale_mat[15] = 1.0;
+
+        self.cube_shader.set_used();
+        let proj = scene.camera().projection();
+        let view = scene.camera().view();
+        self.cube_shader.set_projection_and_view(gl, proj, view);
+
+        let trans_mat_world = na::Matrix4::new_translation(&pos);
+
+
+        cube_model.render(gl, &self.cube_shader, trans_mat_world * scale_mat);
+
+    }
+
+    fn render_hitboxes(&mut self, scene: &Scene, render_context: &mut render_gl::context::Context) {
+        let mut switched = false;
+        if !render_context.wire_frame {
+            switched = true;
+            render_context.switch_mode();
+        }
+
+
+        //RENDER HITBOXES
+
+        let gl = &render_context.gl;
+
+        self.hitbox_shader.set_used();
+        self.hitbox_shader.set_projection_and_view(gl, scene.camera().projection(), scene.camera().view());
+
+
+
+        for entity in scene.entities.hitbox_entities() {
+            match entity.is_hit {
+                true => self.hitbox_shader.set_vec3(gl, "color", na::Vector3::new(1.0, 0.0, 0.0)),
+                false => self.hitbox_shader.set_vec3(gl, "color", na::Vector3::new(1.0, 1.0, 1.0))
+            };
+
+            for hitbox in &entity.hitboxes {
+                let col_box = hitbox.make_transformed(entity.base_entity.physics.pos, entity.base_entity.physics.rotation);
+
+                let clr = na::Vector3::new(1.0, 1.0, 0.0);
+                let cube_model = cube::Cube::from_collision_box(col_box.bounding_box(), clr, gl);
+
+                cube_model.render(gl, &self.hitbox_shader, na::Matrix4::identity());
+            }
+        }
+
+        if switched {
+            render_context.switch_mode();
+        }
+
+    }
+
+
+    pub fn reload_shaders(&mut self, render_context: &render_gl::context::Context) {
+
+        let shaders = vec![("world_shader", &mut self.world_shader),
+                           ("mesh_shader", &mut self.mesh_shader),
+                           ("hitbox_shader", &mut self.hitbox_shader)];
+
+        for (name, shader) in shaders {
+            match render_gl::Shader::new(name, &render_context.res, &render_context.gl) {
+                Ok(new_shader) => {
+
+                    println!("Reloaded {}", name);
+                    *shader = new_shader;
+                },
+                Err(err) => {
+                    println!("Error loading {}: {}", name, err);
+                }
+            };
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::controls;
+use crate::game;
+use crate::physics;
+
+
+// One update of the game, shared by the windowed game loop and headless runs
+pub fn step(scene: &mut game::Scene, inputs: &controls::Inputs, delta: f32) -> Vec<physics::EntityCollision> {
+
+    if inputs.reset {
+        reset(scene);
+    }
+
+    // keep where everything was, so rendering can interpolate between this and the next tick
+    scene.entities.player.store_previous_physics();
+    for enemy in scene.entities.enemies.values_mut() {
+        enemy.store_previous_physics();
+    }
+    for prop in scene.entities.props.values_mut() {
+        prop.store_previous_physics();
+    }
+
+    // load the terrain around where entities are now, before they collide with it
+    scene.update_terrain();
+
+    scene.entities.player.base_entity.stamina.regenerate(delta);
+    for enemy in scene.entities.enemies.values_mut() {
+        enemy.base_entity.stamina.regenerate(delta);
+    }
+
+    game::ai::run_ais(scene);
+
+    //PHYSICS PROCESSING
+    let collisions = physics::process(scene, delta);
+
+    // SPAWN PROJECTILES, HANDLE COLLISION THAT WAS NOT WITH ENVIROMENT
+    game::update_game_state(scene, inputs, &collisions);
+
+    //UPDATE FOLLOW CAMERA, also while looking through the free camera, which is moved by the window loop
+    update_follow_camera(scene, inputs);
+
+    // ANIMATIONS UPDATE
+    scene.update_animations(delta);
+
+    collisions
+}
+
+
+pub fn reset(scene: &mut game::Scene) {
+    scene.entities.player.base_entity.physics = shared::Physics::new();
+
+    scene.entities.player.base_entity.physics.pos.x = 0.0;
+    scene.entities.player.base_entity.physics.pos.y = 0.0;
+    scene.entities.player.base_entity.physics.pos.z = 10.0;
+
+    scene.entities.player.skeleton.reset_ik();
+
+    for enemy in scene.entities.enemies.values_mut() {
+        enemy.base_entity.physics = shared::Physics::new();
+        enemy.skeleton.reset_ik();
+    }
+}
+
+
+fn update_follow_camera(scene: &mut game::Scene, inputs: &controls::Inputs) {
+
+    let player = &scene.entities.player;
+
+    let mut physics = player.base_entity.physics;
+
+    // pos.z bottom of player model
+    physics.pos.z += 1.6;
+
+
+    // readjust to player
+    scene.cameras.follow_mut().update_target(physics.pos);
+
+    // camera movement by stick
+    inputs.right_stick.map(|right_stick| {
+        scene.cameras.follow_mut().update_movement(right_stick.x, right_stick.y);
+    });
+
+}
@@ -0,0 +1,310 @@
+use quadtree as qt;
+use std::collections::BTreeMap;
+
+use crate::physics;
+use crate::render_gl::{self, ChunkCoord, NeighbourLods, TerrainParams};
+use crate::types::*;
+
+
+// Terrain split into chunks, that are generated around the player and dropped when far away.
+// Rendered chunks use a lower level of detail the further away they are.
+// Collision always uses full detail, but is only kept for the chunks around entities
+pub struct Terrain {
+    pub params: TerrainParams,
+
+    rendered: BTreeMap<ChunkCoord, RenderedChunk>,
+
+    collision: BTreeMap<ChunkCoord, ChunkCollision>,
+}
+
+
+// What a rendered chunk mesh was generated with, the mesh itself is in Scene.meshes
+#[derive(Debug, PartialEq)]
+struct RenderedChunk {
+    lod: u32,
+    neighbour_lods: NeighbourLods,
+}
+
+
+pub struct ChunkCollision {
+    pub triangles: Vec::<physics::Triangle>,
+    pub tree: qt::QuadTree::<usize>,
+}
+
+
+// Meshes to add to and remove from the scene after an update
+#[derive(Default)]
+pub struct TerrainChanges {
+    pub added: Vec::<(String, render_gl::GltfMesh)>,
+    pub removed: Vec::<String>,
+}
+
+
+impl Terrain {
+
+    // Nothing is loaded until the first update
+    pub fn new(params: TerrainParams) -> Terrain {
+
+        render_gl::write_noise_image(&params);
+
+        Terrain {
+            params,
+            rendered: BTreeMap::new(),
+            collision: BTreeMap::new(),
+        }
+    }
+
+
+    pub fn mesh_name(chunk: ChunkCoord) -> String {
+        format!("terrain_{}_{}", chunk.0, chunk.1)
+    }
+
+
+    // Mesh names of all rendered chunks
+    pub fn mesh_names(&self) -> Vec::<String> {
+        self.rendered.keys().map(|chunk| Terrain::mesh_name(*chunk)).collect()
+    }
+
+
+    pub fn lod(&self, chunk: ChunkCoord) -> Option<u32> {
+        self.rendered.get(&chunk).map(|rendered| rendered.lod)
+    }
+
+
+    pub fn has_collision(&self, chunk: ChunkCoord) -> bool {
+        self.collision.contains_key(&chunk)
+    }
+
+
+    // Load and unload chunks. Rendered chunks are picked from the distance to view_pos,
+    // and collision is loaded for the chunks at and next to the entity positions
+    pub fn update(&mut self, view_pos: V3, entity_positions: &[V3]) -> TerrainChanges {
+
+        let mut changes = TerrainChanges::default();
+
+        self.update_collision(entity_positions);
+
+        let lods = self.wanted_lods(view_pos);
+
+        let removed: Vec::<ChunkCoord> = self.rendered.keys().filter(|chunk| !lods.contains_key(chunk)).cloned().collect();
+        for chunk in removed {
+            self.rendered.remove(&chunk);
+            changes.removed.push(Terrain::mesh_name(chunk));
+        }
+
+        for (chunk, lod) in &lods {
+
+            let mut neighbour_lods = [None; 4];
+            for (i, neighbour) in neighbours(*chunk).iter().enumerate() {
+                neighbour_lods[i] = neighbour.and_then(|n| lods.get(&n).cloned());
+            }
+
+            let wanted = RenderedChunk {
+                lod: *lod,
+                neighbour_lods,
+            };
+
+            // regenerate when the lod changes, or a neighbour lod changes, since then the edges have to be stitched again
+            if self.rendered.get(chunk) == Some(&wanted) {
+                continue;
+            }
+
+            let mesh = render_gl::perlin_chunk(&self.params, *chunk, *lod, &neighbour_lods);
+            changes.added.push((Terrain::mesh_name(*chunk), mesh));
+
+            self.rendered.insert(*chunk, wanted);
+        }
+
+        changes
+    }
+
+
+    fn wanted_lods(&self, view_pos: V3) -> BTreeMap<ChunkCoord, u32> {
+
+        let mut lods = BTreeMap::new();
+
+        let count = self.params.chunk_count() as i64;
+        let chunk_size = self.params.chunk_size();
+        let half_size = self.params.size / 2.0;
+
+        // only look at the chunks inside the view distance square
+        let min_x = (((view_pos.x - self.params.view_distance + half_size) / chunk_size).floor() as i64).max(0);
+        let max_x = (((view_pos.x + self.params.view_distance + half_size) / chunk_size).floor() as i64).min(count - 1);
+        let min_y = (((view_pos.y - self.params.view_distance + half_size) / chunk_size).floor() as i64).max(0);
+        let max_y = (((view_pos.y + self.params.view_distance + half_size) / chunk_size).floor() as i64).min(count - 1);
+
+        for cx in min_x..=max_x {
+            for cy in min_y..=max_y {
+                let chunk = (cx as u32, cy as u32);
+
+                let distance = self.distance_to_chunk(view_pos, chunk);
+                if distance > self.params.view_distance {
+                    continue;
+                }
+
+                lods.insert(chunk, self.params.lod_for_distance(distance));
+            }
+        }
+
+        lods
+    }
+
+
+    // Distance in x y to the closest point of the chunk, 0 when inside it
+    fn distance_to_chunk(&self, pos: V3, chunk: ChunkCoord) -> f32 {
+        let min = self.params.chunk_origin(chunk);
+        let max = min + V2::new(self.params.chunk_size(), self.params.chunk_size());
+
+        let dx = f32::max(f32::max(min.x - pos.x, pos.x - max.x), 0.0);
+        let dy = f32::max(f32::max(min.y - pos.y, pos.y - max.y), 0.0);
+
+        (dx * dx + dy * dy).sqrt()
+    }
+
+
+    fn update_collision(&mut self, entity_positions: &[V3]) {
+
+        let mut wanted = Vec::new();
+        for pos in entity_positions {
+            if let Some(chunk) = self.params.chunk_at(pos.x, pos.y) {
+                wanted.push(chunk);
+                wanted.extend(neighbours(chunk).iter().filter_map(|n| *n));
+            }
+        }
+
+        let count = self.params.chunk_count();
+        wanted.retain(|chunk| chunk.0 < count && chunk.1 < count);
+
+        self.collision.retain(|chunk, _| wanted.contains(chunk));
+
+        for chunk in wanted {
+            if self.collision.contains_key(&chunk) {
+                continue;
+            }
+
+            let mesh = render_gl::perlin_chunk(&self.params, chunk, 0, &[None; 4]);
+            let triangles = mesh.triangles();
+
+            let min = self.params.chunk_origin(chunk);
+            let max = min + V2::new(self.params.chunk_size(), self.params.chunk_size());
+
+            let mut tree = qt::QuadTree::new(qt::QuadRect::new(qt::QuadPoint {x: min.x.floor() as i32 - 1, y: min.y.floor() as i32 - 1},
+                                                               qt::QuadPoint {x: max.x.ceil() as i32 + 1, y: max.y.ceil() as i32 + 1}));
+
+            for i in 0..triangles.len() {
+                tree.insert(i, qt::Aabb::from(triangles[i]));
+            }
+
+            self.collision.insert(chunk, ChunkCollision {
+                triangles,
+                tree
+            });
+        }
+    }
+
+
+    // Full detail triangles within radius of world x y, empty when no collision is loaded there
+    pub fn triangles_near(&self, x: f32, y: f32, radius: f32) -> Vec::<physics::Triangle> {
+
+        let mut triangles = Vec::new();
+
+        // the circle can cross into the chunks next to it, chunks it does not touch are skipped by the tree
+        for collision in self.collision.values() {
+            for i in collision.tree.query_circle(V2::new(x, y), radius) {
+                triangles.push(collision.triangles[*i]);
+            }
+        }
+
+        triangles
+    }
+
+
+    // Full detail triangles touching aabb in x y, with the chunk and index of each
+    pub fn triangles_in(&self, aabb: &qt::Aabb) -> Vec::<(ChunkCoord, usize, physics::Triangle)> {
+
+        let mut triangles = Vec::new();
+
+        for (chunk, collision) in &self.collision {
+            for i in collision.tree.query_aabb(aabb) {
+                triangles.push((*chunk, *i, collision.triangles[*i]));
+            }
+        }
+
+        triangles
+    }
+}
+
+
+// Chunks next to chunk, in the order -x, +x, -y, +y. The chunks past the last one are not checked here
+fn neighbours(chunk: ChunkCoord) -> [Option<ChunkCoord>; 4] {
+    [
+        chunk.0.checked_sub(1).map(|x| (x, chunk.1)),
+        Some((chunk.0 + 1, chunk.1)),
+        chunk.1.checked_sub(1).map(|y| (chunk.0, y)),
+        Some((chunk.0, chunk.1 + 1)),
+    ]
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn large_params() -> TerrainParams {
+        TerrainParams {
+            size: 1000.0,
+            resolution: 1025,
+            chunk_quads: 16,
+            lod_distances: vec![20.0, 60.0],
+            view_distance: 100.0,
+            ..TerrainParams::default()
+        }
+    }
+
+
+    #[test]
+    fn chunks_stream_around_view_pos() {
+        let mut terrain = Terrain::new(large_params());
+
+        let changes = terrain.update(V3::new(0.0, 0.0, 0.0), &[]);
+        assert!(changes.removed.is_empty());
+        assert_eq!(changes.added.len(), terrain.mesh_names().len());
+
+        // far away chunks are not loaded, and close chunks have more detail
+        let near = terrain.params.chunk_at(0.0, 0.0).unwrap();
+        let mid = terrain.params.chunk_at(50.0, 0.0).unwrap();
+        let far = terrain.params.chunk_at(400.0, 0.0).unwrap();
+        assert_eq!(terrain.lod(near), Some(0));
+        assert_eq!(terrain.lod(mid), Some(1));
+        assert_eq!(terrain.lod(far), None);
+
+        // nothing changes when not moving
+        let changes = terrain.update(V3::new(0.0, 0.0, 0.0), &[]);
+        assert!(changes.added.is_empty() && changes.removed.is_empty());
+
+        let changes = terrain.update(V3::new(400.0, 0.0, 0.0), &[]);
+        assert!(changes.removed.contains(&Terrain::mesh_name(near)));
+        assert_eq!(terrain.lod(far), Some(0));
+        assert_eq!(terrain.lod(near), None);
+    }
+
+
+    #[test]
+    fn collision_around_entities() {
+        let mut terrain = Terrain::new(large_params());
+
+        let enemy = V3::new(-300.0, 200.0, 0.0);
+        terrain.update(V3::new(0.0, 0.0, 0.0), &[V3::new(0.0, 0.0, 0.0), enemy]);
+
+        // collision for the enemy even if it is outside the view distance
+        assert!(terrain.has_collision(terrain.params.chunk_at(enemy.x, enemy.y).unwrap()));
+        assert!(!terrain.triangles_near(enemy.x, enemy.y, 0.0).is_empty());
+        assert!(!terrain.triangles_near(0.0, 0.0, 0.5).is_empty());
+        assert!(terrain.triangles_near(200.0, 200.0, 0.5).is_empty());
+
+        terrain.update(V3::new(0.0, 0.0, 0.0), &[V3::new(0.0, 0.0, 0.0)]);
+        assert!(terrain.triangles_near(enemy.x, enemy.y, 0.0).is_empty());
+    }
+}
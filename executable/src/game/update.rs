@@ -1,4 +1,5 @@
 use crate::game;
+use crate::game::hits::{self, weapon_active};
 use crate::physics;
 use crate::entity;
 
@@ -22,28 +23,81 @@ fn format_matrix3(mat: &na::Matrix3::<f32>) {
     println!("{:.2} {:.2} {:.2} ", mat[6], mat[7], mat[8]);
 }
 
-pub fn update_game_state(scene: &mut game::Scene, controls: &controls::Controls,  _collisions: &Vec<physics::EntityCollision>) {
+// knockback from a prop faster than this plays a hit reaction
+const STAGGER_KNOCKBACK: f32 = 1.0;
+
+
+pub fn update_game_state(scene: &mut game::Scene, inputs: &controls::Inputs,  collisions: &Vec<physics::EntityCollision>) {
 
     // also "action" system update fx sword arc ect
     //action_system::update_actions(&mut scene.ecs.actions_info, &mut scene.ecs.physics, &mut scene.state, delta as f32, &scene.actions);
 
 
+    react_to_collisions(scene, collisions);
+
     // MOVEMENT AND STATES
-    update_player(scene.cameras.current(), controls, &mut scene.entities.player, &scene.entities.weapons, &scene.animations);
+    update_player(scene.cameras.follow(), inputs, &mut scene.entities.player, &scene.entities.weapons, &scene.animations);
     update_enemies(scene);
 
 
     // WEAPONS TRANSFORMS AND COLLISIONS
-    let player = &mut scene.entities.player;
-    update_entity_weapon(player, &mut scene.entities.weapons);
+    update_entity_weapon(&mut scene.entities.player, &mut scene.entities.weapons);
 
     for enemy in scene.entities.enemies.values_mut() {
+        update_entity_weapon(enemy, &mut scene.entities.weapons);
+    }
 
-        update_entity_weapon_collisions(player, &scene.entities.weapons, enemy);
+    // weapons have moved, so update the hitboxes before checking hits
+    scene.broadphase.update(&scene.entities);
 
+    hits::update_hits(scene);
 
-        update_entity_weapon(enemy, &mut scene.entities.weapons);
-        update_entity_weapon_collisions(enemy, &mut scene.entities.weapons, player);
+    // swings that hit the world, so a deflect reaction can be played
+    scene.entities.player.weapon_hit_world = weapon_active(&scene.entities.player)
+        && physics::weapon_world_collision(scene, physics::EntityKey::Player).is_some();
+
+    let enemy_world_hits: Vec::<(usize, bool)> = scene.entities.enemies.entities.iter()
+        .map(|(id, enemy)| (*id, weapon_active(enemy) && physics::weapon_world_collision(scene, physics::EntityKey::Enemy(*id)).is_some()))
+        .collect();
+
+    for (id, hit_world) in enemy_world_hits {
+        if let Some(enemy) = scene.entities.enemies.get_mut(id) {
+            enemy.weapon_hit_world = hit_world;
+        }
+    }
+}
+
+
+
+// Characters hit hard by a prop this tick stagger, which cuts an attack or roll short and plays the hit reaction.
+// The knockback itself is given by the physics
+fn react_to_collisions(scene: &mut game::Scene, collisions: &[physics::EntityCollision]) {
+
+    let mut struck = Vec::<physics::EntityKey>::new();
+
+    for collision in collisions {
+        let character = match (collision.entity_1.is_character(), collision.entity_2.is_character()) {
+            (true, false) => collision.entity_1,
+            (false, true) => collision.entity_2,
+            _ => continue
+        };
+
+        if !struck.contains(&character) {
+            struck.push(character);
+        }
+    }
+
+    for key in struck {
+        let entity = match scene.entities.get_mut(key) {
+            Some(entity) => entity,
+            None => continue
+        };
+
+        if entity.knockback.magnitude() > STAGGER_KNOCKBACK {
+            entity.base_entity.queued_action = Some(shared::EntityState::Idle);
+            entity.next_action();
+            entity.play_hit_reaction();
+        }
     }
 }
 
@@ -93,33 +147,6 @@ fn update_entity_weapon_physics(entity: &mut entity::Entity, weapons: &mut entit
 
 }
 
-fn update_entity_weapon_collisions(entity: & entity::Entity, weapons: & entity::EntitiesCollection, target: &mut entity::Entity) {
-
-    let weapon = match entity.weapon {
-        Some(ref weapon) => weapon,
-        None => {
-            return ;
-        }
-    };
-
-    let state = entity.get_state();
-    if let shared::EntityState::Attack(info) = state {
-
-        let current_frame = entity.animation_player.as_ref().unwrap().current_frame_number();
-
-        if current_frame >= info.hit_start_frame && current_frame <= info.hit_end_frame {
-
-
-            target.is_hit = false;
-            if entity_collision(&weapon, target) {
-                resolve_player_hit_enemy(&entity.base_entity, target);
-                target.is_hit = true;
-            }
-
-
-        }
-    }
-}
 
 
 
@@ -130,33 +157,7 @@ fn update_enemies(scene: &mut game::Scene) {
 }
 
 
-fn resolve_player_hit_enemy(_player: &shared::BaseEntity, _enemy: &mut entity::Entity) {
-
-
-}
-
-//TODO move this into physics and call into that one
-fn entity_collision(entity_1: &entity::Entity, entity_2: &entity::Entity) -> bool{
-
-    // TODO make this more optimized, by calculation each transformed hitbox only once
-    for e1_hitbox_base in &entity_1.hitboxes {
-        let e1_hitbox = e1_hitbox_base.make_transformed(entity_1.base_entity.physics.pos, entity_1.base_entity.physics.rotation);
-
-        for e2_hitbox_base in &entity_2.hitboxes {
-            let e2_hitbox = e2_hitbox_base.make_transformed(entity_2.base_entity.physics.pos, entity_2.base_entity.physics.rotation);
-            let collision_res = physics::check_collision(&e1_hitbox, &e2_hitbox);
-            if collision_res.has_collision() {
-                return true;
-            }
-
-        }
-    }
-
-    false
-}
-
-
-fn set_entity_weapon(entity: &mut entity::Entity, weapon_id: usize, weapons: &entity::EntitiesCollection, animations: &std::collections::HashMap<String, render_gl::PlayerAnimations>) {
+fn set_entity_weapon(entity: &mut entity::Entity, weapon_id: usize, weapons: &entity::EntitiesCollection, animations: &std::collections::HashMap<String, render_gl::AnimationSet>) {
 
 
     let new_weapon = match weapons.get(weapon_id) {
@@ -166,6 +167,7 @@ fn set_entity_weapon(entity: &mut entity::Entity, weapon_id: usize, weapons: &en
 
     let weapon_model_name = new_weapon.model_name.to_string();
 
+    entity.base_entity.weapon = new_weapon.base_entity.weapon.clone();
     entity.weapon = Some(Box::new(new_weapon));
 
     let new_animations = animations.get(&weapon_model_name).unwrap();
@@ -176,32 +178,33 @@ fn set_entity_weapon(entity: &mut entity::Entity, weapon_id: usize, weapons: &en
 
 
 
-fn update_player(camera: &dyn camera::Camera, controls: &controls::Controls, player: &mut entity::Entity, weapons: &entity::EntitiesCollection, animations: &std::collections::HashMap<String, render_gl::PlayerAnimations>) {
+fn update_player(camera: &dyn camera::Camera, inputs: &controls::Inputs, player: &mut entity::Entity, weapons: &entity::EntitiesCollection, animations: &std::collections::HashMap<String, render_gl::AnimationSet>) {
 
     // UPDATE STATE, IE WHEN ATTACK IS DONE SET BACK TO IDLE
     update_entity_state(player);
 
-    if controls.roll {
+    if inputs.roll {
         perform_roll(player);
         return;
     }
 
-    if controls.attack {
-        //TODO get attack start and end frame from player/current weapon
+    if inputs.attack {
         perform_attack(player);
         return;
     }
 
     if !can_perform_action(player.get_state()) {
-        shared::physics_functions::update_velocity(&mut player.base_entity.physics, na::Vector3::new(0.0, 0.0, 0.0));
-        return;
+        // a moving attack swings with the upper body, so the legs keep walking
+        if !player.moving_attack {
+            shared::physics_functions::update_velocity(&mut player.base_entity.physics, na::Vector3::new(0.0, 0.0, 0.0));
+            return;
+        }
     }
-
-
-    if controls.next_weapon {
+    else if inputs.next_weapon {
         match &player.weapon {
-            Some(w) => {
+            Some(_) => {
                 player.weapon = None;
+                player.base_entity.weapon = None;
             },
             None => {
                 //TODO using id = 1 is not the real way to do this
@@ -210,24 +213,16 @@ fn update_player(camera: &dyn camera::Camera, controls: &controls::Controls, pla
         }
     }
 
-    match camera.mode() {
-        camera::CameraMode::Follow => {
-            let z_rot = camera.z_rotation();
-
-            let rot_mat = na::Matrix3::new_rotation(z_rot);
-            let _y = -controls.movement_dir.y;
-            let _x = controls.movement_dir.x;
-            let player_move_dir = rot_mat * na::Vector3::new(-controls.movement_dir.y, controls.movement_dir.x, 0.0);
+    // relative to the follow camera
+    let z_rot = camera.z_rotation();
 
-            shared::physics_functions::update_velocity(&mut player.base_entity.physics, player_move_dir);
+    let rot_mat = na::Matrix3::new_rotation(z_rot);
+    let player_move_dir = rot_mat * na::Vector3::new(-inputs.movement_dir.y, inputs.movement_dir.x, 0.0);
 
-            if player_move_dir.magnitude() > 0.0 {
-                player.base_entity.physics.facing_dir = player_move_dir.normalize();
-            }
-
-        },
+    shared::physics_functions::update_velocity(&mut player.base_entity.physics, player_move_dir);
 
-        _ => {},
+    if player_move_dir.magnitude() > 0.0 {
+        player.base_entity.physics.facing_dir = player_move_dir.normalize();
     }
 }
 
@@ -240,31 +235,26 @@ fn perform_roll(entity: &mut entity::Entity) {
 
 
 fn perform_attack(entity: &mut entity::Entity) {
-    match entity.get_state() {
-        shared::EntityState::Attack(info) => {
 
-            let _current_frame = entity.animation_player.as_ref().unwrap().current_frame_number();
+    // an attack is already queued, fx the follow up
+    if let Some(shared::EntityState::Attack(_)) = entity.base_entity.queued_action {
+        return;
+    }
 
-            let attack_info = shared::AttackInfo {
-                combo_num: 1 - info.combo_num,
-                hit_start_frame: 9,
-                hit_end_frame: 20,
-            };
+    let combo_num = match entity.get_state() {
+        shared::EntityState::Attack(info) => {
 
-            entity.base_entity.queued_action = Some(shared::EntityState::Attack(attack_info));
+            // the next attack in the combo can only be queued in the combo window
+            if !entity.animation_flags.combo_window {
+                return;
+            }
 
+            info.combo_num + 1
         },
-        _ => {
-            let attack_info = shared::AttackInfo {
-                combo_num: 0,
-
-                hit_start_frame: 9,
-                hit_end_frame: 20,
-            };
-
-            entity.base_entity.queued_action = Some(shared::EntityState::Attack(attack_info));
-        }
+        _ => 0
     };
+
+    entity.base_entity.queue_attack(combo_num);
 }
 
 fn can_perform_action(state: shared::EntityState) -> bool {
@@ -285,18 +275,25 @@ fn update_entity_state(entity: &mut entity::Entity) {
         target_state = shared::EntityState::Moving;
     }
 
-    if entity.get_state() != target_state {
+    // keep a queued attack or roll, fx a combo follow up
+    let action_queued = match entity.base_entity.queued_action {
+        Some(shared::EntityState::Attack(_)) | Some(shared::EntityState::Roll) => true,
+        _ => false
+    };
+
+    if entity.get_state() != target_state && !action_queued {
         entity.base_entity.queued_action = Some(target_state);
     }
 
     let mut next_action = false;
     match entity.get_state() {
-        shared::EntityState::Attack(info) => {
-            // CHECK IF WE ARE IN COMBO FRAME RANGE
-            let current_frame = entity.animation_player.as_ref().unwrap().current_frame_number();
-            next_action |= current_frame >= info.hit_end_frame;
+        shared::EntityState::Attack(_) => {
+            // a queued follow up starts in the combo window when the weapon is done hitting, otherwise wait out the window
+            let flags = entity.animation_flags;
+            next_action |= action_queued && flags.combo_window && !flags.hit_active;
+            next_action |= entity.animation_event("combo_window_close");
 
-            if entity.animation_player.as_ref().unwrap().has_repeated {
+            if entity.action_animation_done() {
                 next_action = true;
                 if entity.base_entity.queued_action == None {
                     entity.base_entity.queued_action = Some(shared::EntityState::Idle);
@@ -305,7 +302,7 @@ fn update_entity_state(entity: &mut entity::Entity) {
             }
         },
         shared::EntityState::Roll => {
-            next_action = entity.animation_player.as_ref().unwrap().has_repeated;
+            next_action = entity.action_animation_done();
             if entity.base_entity.queued_action == None {
                 entity.base_entity.queued_action = Some(shared::EntityState::Idle);
             }
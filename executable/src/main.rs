@@ -21,8 +21,11 @@ use std::time::Duration;
 use std::io;
 use std::thread;
 
+use config::Config;
+
 pub mod render_gl;
 pub mod resources;
+pub mod config;
 
 pub mod text_render;
 pub mod types;
@@ -53,8 +56,6 @@ enum Command {
     ReloadAssets,
     ReloadAi,
     Quit,
-    DecrementWalkTime,
-    IncrementWalkTime,
 }
 
 
@@ -62,7 +63,7 @@ static mut CMD: Command = Command::Nop;
 
 
 
-fn start_cmd_thread() {
+fn start_cmd_thread(config: Config) {
     thread::spawn(move || {
 
         loop {
@@ -78,14 +79,12 @@ fn start_cmd_thread() {
                 println!("Input is '{}'", input.trim());
                 let msg = match input.trim() {
                     "r" =>{
-                        copy_assets();
+                        copy_assets(&config);
                         Command::ReloadAssets
                     }
                     ,
                     "m" => Command::SwitchRenderMode,
                     "q" => Command::Quit,
-                    "d" => Command::DecrementWalkTime,
-                    "w" => Command::IncrementWalkTime,
                     _ => Command::Nop,
                 };
 
@@ -96,7 +95,7 @@ fn start_cmd_thread() {
     });
 }
 
-fn start_notify_thread_assets() {
+fn start_notify_thread_assets(config: Config) {
     thread::spawn(move || {
         // Create a channel to receive the events.
         let (tx, rx) = channel();
@@ -107,13 +106,17 @@ fn start_notify_thread_assets() {
 
         // Add a path to be watched. All files and directories at that path and
         // below will be monitored for changes.
-        watcher.watch("E:/repos/Game-in-rust/assets/", RecursiveMode::Recursive).unwrap();
+        let assets_path = config.assets_path();
+        if let Err(e) = watcher.watch(&assets_path, RecursiveMode::Recursive) {
+            println!("Cannot watch {:?}: {:?}", assets_path, e);
+            return;
+        }
 
         loop {
             match rx.recv() {
                 Ok(_) => {
                     println!("Updated on disk copy assets");
-                    copy_assets();
+                    copy_assets(&config);
                     unsafe {
                         CMD = Command::ReloadAssets;
                     }
@@ -125,7 +128,7 @@ fn start_notify_thread_assets() {
 }
 
 
-fn start_notify_thread_ais() {
+fn start_notify_thread_ais(config: Config) {
     thread::spawn(move || {
         // Create a channel to receive the events.
         let (tx, rx) = channel();
@@ -136,13 +139,17 @@ fn start_notify_thread_ais() {
 
         // Add a path to be watched. All files and directories at that path and
         // below will be monitored for changes.
-        watcher.watch("E:/repos/Game-in-rust/target/debug/ai.dll", RecursiveMode::Recursive).unwrap();
+        let lib_path = exe_dir().join(game::ai::lib_file_name());
+        if let Err(e) = watcher.watch(&lib_path, RecursiveMode::Recursive) {
+            println!("Cannot watch {:?}: {:?}", lib_path, e);
+            return;
+        }
 
         loop {
             match rx.recv() {
                 Ok(_) => {
                     println!("Updated AI disk copy assets");
-                    copy_assets();
+                    copy_assets(&config);
                     unsafe {
                         CMD = Command::ReloadAi;
                     }
@@ -158,30 +165,63 @@ fn start_notify_thread_ais() {
 
 
 fn main() {
+
+    let mut config = Config::load();
+
+    // a replay starts the same scene, terrain and tick rate it was recorded with
+    let replay = match &config.replay {
+        Some(path) => match game::Replay::load(path) {
+            Ok(replay) => {
+                println!("Replaying {:?}, {} ticks", path, replay.tick_count());
+                replay.apply_to(&mut config);
+                Some(replay)
+            },
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        },
+        None => None
+    };
+
     // set up commands channel and thread
-    start_cmd_thread();
+    start_cmd_thread(config.clone());
 
 
     // monitor assets on disk and reload them
-    start_notify_thread_assets();
+    start_notify_thread_assets(config.clone());
 
     // monitor ais on disk and reload them
-    start_notify_thread_ais();
+    start_notify_thread_ais(config.clone());
+
+    if let Some(ticks) = config.headless_ticks {
+        if let Err(e) = game::headless::run(&config, ticks, replay) {
+            println!("{}", debug::failure_to_string(e));
+        }
+        return;
+    }
 
     // start game
-    if let Err(e) = run() {
+    if let Err(e) = run(&config, replay) {
         println!("{}", debug::failure_to_string(e));
     }
 }
 
 
-fn copy_assets() {
+fn exe_dir() -> std::path::PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+
+// Keep the assets next to the executable up to date with the ones in the asset root
+fn copy_assets(config: &Config) {
 
     let mut options = fs_extra::dir::CopyOptions::new(); //Initialize default values for CopyOptions
     options.overwrite = true;
 
     // copy source/dir1 to target/dir1
-    let copy_res = fs_extra::dir::copy("E:/repos/Game-in-rust/assets", "E:/repos/Game-in-rust/target/debug/", &options);
+    let copy_res = fs_extra::dir::copy(config.assets_path(), exe_dir(), &options);
 
     match copy_res {
         Err(err) => println!("{:#?}", err),
@@ -192,9 +232,15 @@ fn copy_assets() {
 
 
 
-fn run() -> Result<(), failure::Error> {
+fn run(config: &Config, replay: Option<game::Replay>) -> Result<(), failure::Error> {
+
+    let mut ctx = game::Context::new(config)?;
+
+    // record the inputs of each tick, saved when the game quits
+    let mut recorder = config.record.as_ref().map(|_| game::Replay::new(&ctx.scene, config.tick_rate));
 
-    let mut ctx = game::Context::new()?;
+    // while replaying, ticks use the recorded inputs instead of the controls
+    let mut replay_player = replay.map(game::ReplayPlayer::new);
 
     let mut physics_test = test_physics::PhysicsTest::new(&ctx.render_context.gl);
 
@@ -213,11 +259,14 @@ fn run() -> Result<(), failure::Error> {
 
 
 
+    // inputs waiting for the next simulation tick
+    let mut tick_inputs = controls::Inputs::none();
+
     'main: loop{
         ctx.update_delta();
-        // For debugging always set delta to 0.016 (60fps) even when having long breaks in visualStudio
-        //let delta = ctx.get_delta_time();
-        let delta = 0.016;
+        // real frame time, the simulation runs in fixed ticks of ctx.fixed_step.tick_delta()
+        // long breaks, fx in a debugger, are capped by max_ticks_per_frame
+        let delta = ctx.get_delta_time();
         ctx.handle_inputs();
 
 
@@ -225,65 +274,54 @@ fn run() -> Result<(), failure::Error> {
             break 'main;
         }
 
-        if ctx.controls.reset {
-            ctx.scene.entities.player.base_entity.physics = shared::Physics::new();
-
-            ctx.scene.entities.player.base_entity.physics.pos.x = 0.0;
-            ctx.scene.entities.player.base_entity.physics.pos.y = 0.0;
-            ctx.scene.entities.player.base_entity.physics.pos.z = 10.0;
-
-            ctx.scene.entities.player.skeleton.reset_ik();
-
-            for enemy in ctx.scene.entities.enemies.values_mut() {
-                enemy.base_entity.physics = shared::Physics::new();
-                enemy.skeleton.reset_ik();
-            }
-        }
-
         unsafe {
             ctx.render_context.gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
 
+        // AI, PHYSICS, GAME STATE, FOLLOW CAMERA AND ANIMATIONS
+        tick_inputs.accumulate(&frame_inputs(&ctx));
 
-        game::ai::run_ais(&mut ctx.scene);
+        let ticks = ctx.fixed_step.advance(delta);
+        for _ in 0..ticks {
+            let inputs = match replay_player.as_mut().and_then(|player| player.next()) {
+                Some(recorded) => recorded,
+                None => tick_inputs
+            };
 
-        //PHYSICS PROCESSING
-        let collisions = physics::process(&mut ctx.scene, delta);
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&inputs);
+            }
 
-        // SPAWN PROJECTILES, HANDLE COLLISION THAT WAS NOT WITH ENVIROMENT
-        game::update_game_state(&mut ctx.scene, &ctx.controls, &collisions);
+            game::simulation::step(&mut ctx.scene, &inputs, ctx.fixed_step.tick_delta());
+            tick_inputs.clear_buttons();
+        }
 
+        if replay_player.as_ref().map_or(false, |player| player.is_finished()) {
+            println!("Replay finished");
+            replay_player = None;
+        }
 
 
         //println!("FPS: {:?}", 1.0 / delta);
         //println!("{:?}", ctx.scene.entities.player.base_entity.physics.velocity.magnitude());
 
-        //UPDATE CAMERA IF FOLLOW MODE
-
-        let mode = ctx.scene.camera().mode();
-        match mode {
-            camera::CameraMode::Free => {
-                update_free_camera(&mut ctx, delta);
-            },
-            camera::CameraMode::Follow => {
-                update_follow_camera(&mut ctx.scene, &ctx.controls);
-            },
-        };
+        //UPDATE CAMERA IF FREE MODE
+        if ctx.scene.camera().mode() == camera::CameraMode::Free {
+            update_free_camera(&mut ctx, delta);
+        }
 
 
         //PHYSICS TEST
         physics_test.update(&ctx.controls, delta);
-        physics_test.render(&ctx.render_context.gl, &ctx.scene, &collision_shader);
+        physics_test.render(&ctx.render_context.gl, &ctx.scene, &ctx.renderer.cube_shader, &collision_shader);
 
 
 
         // DEBUG
         debug_keys(&mut ctx, &bone_cube);
 
-        // ANIMATIONS UPDATE
-        ctx.scene.update_animations(delta);
         // RENDERING
-        ctx.scene.render(&mut ctx.render_context, low_poly_texture_id, &bitmaps, &bitmap_quad);
+        ctx.renderer.render(&ctx.scene, &mut ctx.render_context, ctx.fixed_step.alpha(), low_poly_texture_id, &bitmaps, &bitmap_quad);
 
         ctx.render_context.gl_swap_window();
 
@@ -294,7 +332,8 @@ fn run() -> Result<(), failure::Error> {
                 Command::Quit => { break 'main},
                 Command::ReloadAssets => {
                     println!("Reload assets");
-                    ctx.scene.reload_shaders(&ctx.render_context);
+                    ctx.renderer.reload_shaders(&ctx.render_context);
+                    ctx.scene.reload_description(&ctx.render_context.res, &ctx.res_models);
                 },
                 Command::ReloadAi => {
                     println!("Reload Ais");
@@ -303,23 +342,17 @@ fn run() -> Result<(), failure::Error> {
                 Command::SwitchRenderMode => {
                     ctx.render_context.switch_mode();
                 },
-
-                Command::DecrementWalkTime => {
-                    let player = &mut ctx.scene.entities.player;
-                    player.animation_player.as_mut().map(|ap| ap.animations.walk.duration -= 0.1);
-                    println!("{:#?}", player.animation_player.as_ref().map(|ap| ap.animations.walk.duration));
-                },
-                Command::IncrementWalkTime => {
-                    let player = &mut ctx.scene.entities.player;
-                    player.animation_player.as_mut().map(|ap| ap.animations.walk.duration += 0.1);
-                    println!("{:#?}", player.animation_player.as_ref().map(|ap| ap.animations.walk.duration));
-                },
             }
             CMD = Command::Nop;
         }
 
     }
 
+    if let (Some(path), Some(recorder)) = (&config.record, &recorder) {
+        recorder.save(path)?;
+        println!("Saved replay of {} ticks to {:?}", recorder.tick_count(), path);
+    }
+
     Ok(())
 }
 
@@ -348,7 +381,7 @@ fn debug_keys(ctx: &mut game::Context, bone_cube: &cube::Cube) {
 
     match ctx.controls.keys.get(&sdl2::keyboard::Keycode::T) {
         Some(true) => {
-            animation_player.set_current(render_gl::Animation::TPose, &skeleton);
+            animation_player.play("t_pose", 0.2, &skeleton);
 
         },
         _ => {}
@@ -357,7 +390,7 @@ fn debug_keys(ctx: &mut game::Context, bone_cube: &cube::Cube) {
     match ctx.controls.keys.get(&sdl2::keyboard::Keycode::K) {
         Some(true) => {
             println!("Setting to waalk");
-            animation_player.set_current(render_gl::Animation::Walk, &skeleton);
+            animation_player.play("walk", 0.2, &skeleton);
         },
         _ => {
         }
@@ -367,7 +400,7 @@ fn debug_keys(ctx: &mut game::Context, bone_cube: &cube::Cube) {
     //HIT BOXES
     match ctx.controls.keys.get(&sdl2::keyboard::Keycode::H) {
         Some(true) => {
-            ctx.scene.render_hitboxes = true;
+            ctx.renderer.render_hitboxes = true;
         },
         _ => {
         }
@@ -396,10 +429,10 @@ fn debug_keys(ctx: &mut game::Context, bone_cube: &cube::Cube) {
     match ctx.controls.keys.get(&sdl2::keyboard::Keycode::V) {
         Some(true) => {
 
-            ctx.scene.cube_shader.set_used();
+            ctx.renderer.cube_shader.set_used();
             let proj = ctx.scene.camera().projection();
             let view = ctx.scene.camera().view();
-            ctx.scene.cube_shader.set_projection_and_view(&ctx.render_context.gl, proj, view);
+            ctx.renderer.cube_shader.set_projection_and_view(&ctx.render_context.gl, proj, view);
 
 
             let mut scale_mat = na::Matrix4::identity();
@@ -408,7 +441,7 @@ fn debug_keys(ctx: &mut game::Context, bone_cube: &cube::Cube) {
 
 
             for joint in &skeleton.joints {
-                bone_cube.render(&ctx.render_context.gl, &ctx.scene.cube_shader, joint.world_matrix * scale_mat);
+                bone_cube.render(&ctx.render_context.gl, &ctx.renderer.cube_shader, joint.world_matrix * scale_mat);
             }
 
         },
@@ -423,26 +456,21 @@ fn set_t_pose(bones: &mut [na::Matrix4::<f32>]) {
     }
 }
 
-fn update_follow_camera(scene: &mut game::Scene, controls: &controls::Controls) {
-
-    let player = &scene.entities.player;
-
-    let mut physics = player.base_entity.physics;
-
-    // pos.z bottom of player model
-    physics.pos.z += 1.6;
+// The controls for the simulation. With the free camera the movement keys and stick fly the camera instead
+// of the player, so they are left out here and not recorded
+fn frame_inputs(ctx: &game::Context) -> controls::Inputs {
 
+    let mut inputs = ctx.controls.inputs();
 
-    // readjust to player
-    scene.camera_mut().update_target(physics.pos);
-
-    // camera movement by stick
-    controls.right_stick.map(|right_stick| {
-        scene.camera_mut().update_movement(right_stick.x, right_stick.y);
-    });
+    if ctx.scene.camera().mode() == camera::CameraMode::Free {
+        inputs.movement_dir = na::Vector3::new(0.0, 0.0, 0.0);
+        inputs.right_stick = None;
+    }
 
+    inputs
 }
 
+
 fn update_free_camera(ctx: &mut game::Context, delta: f32) {
 
     use sdl2::keyboard::Keycode;
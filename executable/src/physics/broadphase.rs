@@ -0,0 +1,506 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::entity;
+use crate::physics::collision_3d::*;
+use crate::physics::collision_shapes::*;
+use crate::types::*;
+
+
+// Which entity a proxy belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntityKey {
+    Player,
+    Enemy(usize),
+    Prop(usize),
+}
+
+
+impl EntityKey {
+
+    pub fn is_character(&self) -> bool {
+        match self {
+            EntityKey::Player | EntityKey::Enemy(_) => true,
+            EntityKey::Prop(_) => false,
+        }
+    }
+}
+
+
+// The characters own hitboxes, or the hitboxes of the weapon it holds
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Part {
+    Body,
+    Weapon,
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProxyKey {
+    pub entity: EntityKey,
+    pub part: Part,
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb3 {
+    pub min: V3,
+    pub max: V3,
+}
+
+
+impl Aabb3 {
+
+    pub fn from_shapes(shapes: &[CollisionShape]) -> Aabb3 {
+        let mut min = V3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = V3::new(f32::MIN, f32::MIN, f32::MIN);
+
+        for b in shapes {
+            min = min.inf(&V3::new(b.min_x(), b.min_y(), b.min_z()));
+            max = max.sup(&V3::new(b.max_x(), b.max_y(), b.max_z()));
+        }
+
+        Aabb3 { min, max }
+    }
+
+
+    pub fn union(&self, other: &Aabb3) -> Aabb3 {
+        Aabb3 { min: self.min.inf(&other.min), max: self.max.sup(&other.max) }
+    }
+
+
+    pub fn intersects(&self, other: &Aabb3) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x &&
+            self.min.y <= other.max.y && other.min.y <= self.max.y &&
+            self.min.z <= other.max.z && other.min.z <= self.max.z
+    }
+}
+
+
+// Where two proxies touch, the hitboxes are indices into the hitboxes of each
+#[derive(Debug, Copy, Clone)]
+pub struct Contact {
+    pub hitbox_a: usize,
+    pub hitbox_b: usize,
+    // on b, closest to the center of the hitbox of a
+    pub point: V3,
+}
+
+
+// Hitboxes in world space, and what they were transformed with
+struct Proxy {
+    pos: V3,
+    rotation: na::UnitQuaternion::<f32>,
+    model_name: String,
+    hitboxes: Vec::<CollisionShape>,
+    aabb: Aabb3,
+    // the hitboxes before the last move, None when it did not move in the last update
+    previous: Option<Vec::<CollisionShape>>,
+    // used for the pairs. For weapons it covers both the previous and the current hitboxes, so a fast swing
+    // finds what it passed. Bodies only pair where they are now, their long moves are clamped in physics
+    swept_aabb: Aabb3,
+}
+
+
+impl Proxy {
+
+    // How many steps to check the move in, so no step moves further than the thinnest hitbox is thick
+    fn sweep_steps(&self) -> usize {
+        let previous = match &self.previous {
+            Some(previous) => previous,
+            None => return 1
+        };
+
+        let mut steps = 1;
+        for (from, to) in previous.iter().zip(self.hitboxes.iter()) {
+            let thickness = f32::max(to.min_half_size() * 2.0, 0.01);
+            steps = usize::max(steps, (from.distance_moved(to) / thickness).ceil() as usize);
+        }
+
+        // a teleport, not a move worth sweeping
+        usize::min(steps, 32)
+    }
+
+
+    // Hitboxes part of the way through the last move, t = 1 is the current hitboxes
+    fn hitboxes_at(&self, t: f32) -> Vec::<CollisionShape> {
+        match &self.previous {
+            Some(previous) if t < 1.0 => previous.iter().zip(self.hitboxes.iter()).map(|(from, to)| from.lerp(to, t)).collect(),
+            _ => self.hitboxes.clone()
+        }
+    }
+}
+
+
+// Sweep and prune over the x axis of all character, prop and weapon hitboxes.
+// Transformed hitboxes are kept between updates and only redone for entities that moved,
+// and the sort order from last update is reused, so an insertion sort is close to linear
+pub struct Broadphase {
+    proxies: BTreeMap<ProxyKey, Proxy>,
+    sorted: Vec::<ProxyKey>,
+    pairs: Vec::<(ProxyKey, ProxyKey)>,
+}
+
+
+impl Broadphase {
+
+    pub fn new() -> Broadphase {
+        Broadphase {
+            proxies: BTreeMap::new(),
+            sorted: Vec::new(),
+            pairs: Vec::new(),
+        }
+    }
+
+
+    // Transform the hitboxes of entities that moved since the last update, and find the overlapping pairs
+    pub fn update(&mut self, entities: &entity::Entities) {
+
+        let mut parts = Vec::new();
+        add_parts(&mut parts, EntityKey::Player, &entities.player);
+        for (id, enemy) in &entities.enemies.entities {
+            add_parts(&mut parts, EntityKey::Enemy(*id), enemy);
+        }
+        for (id, prop) in &entities.props.entities {
+            add_parts(&mut parts, EntityKey::Prop(*id), prop);
+        }
+
+        let keys: BTreeSet::<ProxyKey> = parts.iter().map(|(key, _)| *key).collect();
+        self.proxies.retain(|key, _| keys.contains(key));
+
+        for (key, entity) in &parts {
+            let physics = &entity.base_entity.physics;
+
+            if let Some(proxy) = self.proxies.get_mut(key) {
+                if proxy.pos == physics.pos && proxy.rotation == physics.rotation && proxy.model_name == entity.model_name {
+                    proxy.previous = None;
+                    proxy.swept_aabb = proxy.aabb;
+                    continue;
+                }
+            }
+
+            let hitboxes: Vec::<CollisionShape> = entity.hitboxes.iter()
+                .map(|hitbox| hitbox.make_transformed(physics.pos, physics.rotation))
+                .collect();
+
+            let aabb = Aabb3::from_shapes(&hitboxes);
+
+            // keep where it came from, so fast moves can be swept. Not across a model change
+            let previous = self.proxies.remove(key)
+                .filter(|proxy| proxy.model_name == entity.model_name && proxy.hitboxes.len() == hitboxes.len())
+                .map(|proxy| proxy.hitboxes);
+
+            let swept_aabb = match &previous {
+                Some(previous) if key.part == Part::Weapon => aabb.union(&Aabb3::from_shapes(previous)),
+                _ => aabb
+            };
+
+            self.proxies.insert(*key, Proxy {
+                pos: physics.pos,
+                rotation: physics.rotation,
+                model_name: entity.model_name.clone(),
+                hitboxes,
+                aabb,
+                previous,
+                swept_aabb
+            });
+        }
+
+        self.sort();
+        self.find_pairs();
+    }
+
+
+    fn sort(&mut self) {
+        let proxies = &self.proxies;
+
+        self.sorted.retain(|key| proxies.contains_key(key));
+        if self.sorted.len() != proxies.len() {
+            for key in proxies.keys() {
+                if !self.sorted.contains(key) {
+                    self.sorted.push(*key);
+                }
+            }
+        }
+
+        // insertion sort, since the order rarely changes much from one tick to the next
+        for i in 1..self.sorted.len() {
+            let mut j = i;
+            while j > 0 && min_x(proxies, self.sorted[j - 1]) > min_x(proxies, self.sorted[j]) {
+                self.sorted.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+
+
+    fn find_pairs(&mut self) {
+        self.pairs.clear();
+
+        for i in 0..self.sorted.len() {
+            let a = &self.proxies[&self.sorted[i]];
+
+            for j in (i + 1)..self.sorted.len() {
+                let b = &self.proxies[&self.sorted[j]];
+
+                // sorted by min x, so nothing after this can overlap a
+                if b.swept_aabb.min.x > a.swept_aabb.max.x {
+                    break;
+                }
+
+                let (key_a, key_b) = (self.sorted[i], self.sorted[j]);
+
+                if key_a.entity == key_b.entity || !a.swept_aabb.intersects(&b.swept_aabb) {
+                    continue;
+                }
+
+                self.pairs.push((key_a.min(key_b), key_a.max(key_b)));
+            }
+        }
+
+        // same order every time, independent of positions
+        self.pairs.sort();
+    }
+
+
+    // All overlapping proxies from the last update, from different entities. Weapons overlap anything they passed since the update before
+    pub fn pairs(&self) -> &[(ProxyKey, ProxyKey)] {
+        &self.pairs
+    }
+
+
+    // Entities whose bodies may touch, fx for movement collision. This includes pairs of enemies and props
+    pub fn body_pairs(&self) -> Vec::<(EntityKey, EntityKey)> {
+        self.pairs.iter()
+            .filter(|(a, b)| a.part == Part::Body && b.part == Part::Body)
+            .map(|(a, b)| (a.entity, b.entity))
+            .collect()
+    }
+
+
+    // (attacker, target) where the attackers weapon may touch the targets body
+    pub fn weapon_pairs(&self) -> Vec::<(EntityKey, EntityKey)> {
+        self.pairs.iter()
+            .filter_map(|(a, b)| match (a.part, b.part) {
+                (Part::Weapon, Part::Body) => Some((a.entity, b.entity)),
+                (Part::Body, Part::Weapon) => Some((b.entity, a.entity)),
+                _ => None
+            })
+            .collect()
+    }
+
+
+    // World space hitboxes from the last update
+    pub fn hitboxes(&self, key: ProxyKey) -> &[CollisionShape] {
+        match self.proxies.get(&key) {
+            Some(proxy) => &proxy.hitboxes,
+            None => &[]
+        }
+    }
+
+
+    pub fn aabb(&self, key: ProxyKey) -> Option<Aabb3> {
+        self.proxies.get(&key).map(|proxy| proxy.aabb)
+    }
+
+
+    // Proxies from the last update whose bounds touch aabb, fx for ray casts
+    pub fn query_aabb(&self, aabb: &Aabb3) -> Vec::<ProxyKey> {
+        self.proxies.iter()
+            .filter(|(_, proxy)| proxy.aabb.intersects(aabb))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+
+    // Narrow phase check between two proxies with the cached hitboxes. Both are swept through
+    // their last move, so a fast weapon swing hits what it passed between two updates
+    pub fn collides(&self, a: ProxyKey, b: ProxyKey) -> bool {
+        self.contact(a, b).is_some()
+    }
+
+
+    // Same as collides, with where they first touched
+    pub fn contact(&self, a: ProxyKey, b: ProxyKey) -> Option<Contact> {
+        if self.pairs.binary_search(&(a.min(b), a.max(b))).is_err() {
+            return None;
+        }
+
+        let (proxy_a, proxy_b) = (&self.proxies[&a], &self.proxies[&b]);
+
+        // the start of the move was checked in the update before
+        let steps = usize::max(proxy_a.sweep_steps(), proxy_b.sweep_steps());
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+
+            let hitboxes_b = proxy_b.hitboxes_at(t);
+            for (index_a, box_a) in proxy_a.hitboxes_at(t).iter().enumerate() {
+                for (index_b, box_b) in hitboxes_b.iter().enumerate() {
+                    if check_collision_shapes(box_a, box_b).has_collision() {
+                        return Some(Contact {
+                            hitbox_a: index_a,
+                            hitbox_b: index_b,
+                            point: box_b.closest_point(&box_a.center()),
+                        });
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+
+fn add_parts<'a>(parts: &mut Vec::<(ProxyKey, &'a entity::Entity)>, key: EntityKey, entity: &'a entity::Entity) {
+
+    if !entity.hitboxes.is_empty() {
+        parts.push((ProxyKey { entity: key, part: Part::Body }, entity));
+    }
+
+    if let Some(weapon) = &entity.weapon {
+        if !weapon.hitboxes.is_empty() {
+            parts.push((ProxyKey { entity: key, part: Part::Weapon }, weapon));
+        }
+    }
+}
+
+
+fn min_x(proxies: &BTreeMap<ProxyKey, Proxy>, key: ProxyKey) -> f32 {
+    proxies[&key].swept_aabb.min.x
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn character(x: f32, y: f32) -> entity::Entity {
+        let mut entity = entity::Entity::new(None, "character".to_string());
+        entity.base_entity.physics.pos = V3::new(x, y, 0.0);
+        entity.hitboxes.push(CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 1.0), na::Rotation3::identity(), na::Matrix3::new_scaling(1.0))));
+        entity
+    }
+
+
+    fn brute_force_body_pairs(entities: &entity::Entities) -> Vec::<(EntityKey, EntityKey)> {
+        let mut all = vec![(EntityKey::Player, &entities.player)];
+        for (id, enemy) in &entities.enemies.entities {
+            all.push((EntityKey::Enemy(*id), enemy));
+        }
+
+        let mut pairs = Vec::new();
+        for i in 0..all.len() {
+            for j in (i + 1)..all.len() {
+                let a = Aabb3::from_shapes(&[all[i].1.hitboxes[0].make_transformed(all[i].1.base_entity.physics.pos, all[i].1.base_entity.physics.rotation)]);
+                let b = Aabb3::from_shapes(&[all[j].1.hitboxes[0].make_transformed(all[j].1.base_entity.physics.pos, all[j].1.base_entity.physics.rotation)]);
+                if a.intersects(&b) {
+                    pairs.push((all[i].0, all[j].0));
+                }
+            }
+        }
+
+        pairs.sort();
+        pairs
+    }
+
+
+    #[test]
+    fn body_pairs_match_brute_force() {
+        let mut entities = entity::Entities::new();
+        entities.player = character(0.0, 0.0);
+
+        // 60 enemies on a small area, so plenty of them overlap
+        let mut seed: u32 = 7;
+        for _ in 0..60 {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let x = (seed >> 8) as f32 / (1 << 24) as f32 * 20.0 - 10.0;
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let y = (seed >> 8) as f32 / (1 << 24) as f32 * 20.0 - 10.0;
+
+            entities.enemies.add(character(x, y));
+        }
+
+        let mut broadphase = Broadphase::new();
+        broadphase.update(&entities);
+
+        let pairs = broadphase.body_pairs();
+        assert_eq!(pairs, brute_force_body_pairs(&entities));
+        assert!(pairs.iter().any(|(a, b)| *a != EntityKey::Player && *b != EntityKey::Player));
+
+        // move some and update again, the reused sort order has to give the same result
+        for (i, enemy) in entities.enemies.values_mut().enumerate() {
+            if i % 3 == 0 {
+                enemy.base_entity.physics.pos.x += 4.0;
+            }
+        }
+
+        broadphase.update(&entities);
+        assert_eq!(broadphase.body_pairs(), brute_force_body_pairs(&entities));
+    }
+
+
+    #[test]
+    fn weapon_pairs_and_removed_entities() {
+        let mut entities = entity::Entities::new();
+        entities.player = character(0.0, 0.0);
+
+        let mut weapon = character(1.5, 0.0);
+        weapon.model_name = "sword".to_string();
+        entities.player.weapon = Some(Box::new(weapon));
+
+        let near = entities.enemies.add(character(2.3, 0.0));
+        let far = entities.enemies.add(character(50.0, 0.0));
+
+        let mut broadphase = Broadphase::new();
+        broadphase.update(&entities);
+
+        let weapon_key = ProxyKey { entity: EntityKey::Player, part: Part::Weapon };
+        let near_key = ProxyKey { entity: EntityKey::Enemy(near), part: Part::Body };
+        let far_key = ProxyKey { entity: EntityKey::Enemy(far), part: Part::Body };
+
+        // the weapon does not hit its owner
+        assert_eq!(broadphase.weapon_pairs(), vec![(EntityKey::Player, EntityKey::Enemy(near))]);
+        assert!(broadphase.collides(weapon_key, near_key));
+        assert!(!broadphase.collides(weapon_key, far_key));
+        assert!(broadphase.body_pairs().is_empty());
+
+        entities.enemies.entities.remove(&near);
+        broadphase.update(&entities);
+
+        assert!(broadphase.weapon_pairs().is_empty());
+        assert!(broadphase.hitboxes(near_key).is_empty());
+        assert_eq!(broadphase.hitboxes(far_key).len(), 1);
+    }
+
+
+    #[test]
+    fn fast_swing_hits_between_updates() {
+        let mut entities = entity::Entities::new();
+        entities.player = character(0.0, 0.0);
+
+        let mut weapon = character(3.0, -3.0);
+        weapon.model_name = "sword".to_string();
+        entities.player.weapon = Some(Box::new(weapon));
+
+        let target = entities.enemies.add(character(3.0, 0.0));
+
+        let mut broadphase = Broadphase::new();
+        broadphase.update(&entities);
+
+        let weapon_key = ProxyKey { entity: EntityKey::Player, part: Part::Weapon };
+        let target_key = ProxyKey { entity: EntityKey::Enemy(target), part: Part::Body };
+        assert!(!broadphase.collides(weapon_key, target_key));
+
+        // from one side of the target to the other in one update, it is never inside at an update
+        entities.player.weapon.as_mut().unwrap().base_entity.physics.pos.y = 3.0;
+        broadphase.update(&entities);
+        assert!(broadphase.collides(weapon_key, target_key));
+
+        // not moving, nothing to sweep
+        broadphase.update(&entities);
+        assert!(!broadphase.collides(weapon_key, target_key));
+        assert!(broadphase.weapon_pairs().is_empty());
+    }
+}
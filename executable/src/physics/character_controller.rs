@@ -0,0 +1,412 @@
+use crate::physics::collision_3d::*;
+use crate::physics::collision_shapes::*;
+use crate::physics::raycast::shape_cast_triangles;
+use crate::types::*;
+
+
+// Kinematic movement for characters. Characters are not pushed around by the world, they move where
+// they are told, slide along walls, walk up steps and slopes, and stick to the ground when walking downhill
+#[derive(Debug, Clone, Copy)]
+pub struct CharacterController {
+    // steeper ground than this is a wall
+    pub max_slope_degrees: f32,
+    // ledges up to this height are walked up onto
+    pub step_height: f32,
+    // when walking over an edge or downhill, snap down to ground this far below
+    pub snap_distance: f32,
+    pub gravity: f32,
+}
+
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        CharacterController {
+            max_slope_degrees: 50.0,
+            step_height: 0.35,
+            snap_distance: 0.3,
+            gravity: -9.82,
+        }
+    }
+}
+
+
+// The character as an upright capsule, from pos (the feet) and up
+#[derive(Debug, Clone, Copy)]
+pub struct CharacterShape {
+    pub radius: f32,
+    pub height: f32,
+}
+
+
+impl CharacterShape {
+
+    // Capsule around the hitboxes, hitboxes are relative to the entity pos
+    pub fn from_hitboxes(hitboxes: &[CollisionShape]) -> CharacterShape {
+
+        if hitboxes.is_empty() {
+            return CharacterShape { radius: 0.3, height: 1.8 };
+        }
+
+        let mut radius: f32 = 0.0;
+        let mut height: f32 = 0.0;
+        for hitbox in hitboxes {
+            let x = f32::max(hitbox.max_x().abs(), hitbox.min_x().abs());
+            let y = f32::max(hitbox.max_y().abs(), hitbox.min_y().abs());
+            radius = radius.max(f32::max(x, y));
+            height = height.max(hitbox.max_z());
+        }
+
+        CharacterShape { radius, height }
+    }
+
+
+    // Upright capsule at pos, from bottom above pos to the top of the character
+    pub fn capsule(&self, pos: V3, bottom: f32) -> CollisionShape {
+
+        let a = bottom + self.radius;
+        let b = f32::max(self.height - self.radius, a);
+
+        CollisionShape::Capsule("".to_string(), Capsule {
+            a: pos + V3::new(0.0, 0.0, a),
+            b: pos + V3::new(0.0, 0.0, b),
+            radius: self.radius
+        })
+    }
+}
+
+
+impl CharacterController {
+
+    fn min_ground_normal_z(&self) -> f32 {
+        self.max_slope_degrees.to_radians().cos()
+    }
+
+
+    // Move by movement, fx velocity * delta or root motion, and apply gravity.
+    // world is the triangles around the character, it has to cover where the character moves to
+    pub fn move_character(&self, physics: &mut shared::Physics, shape: CharacterShape, movement: V3, world: &[Triangle], delta: f32) {
+
+        let was_grounded = physics.grounded;
+
+        // HORIZONTAL, slide along walls
+        let walls = self.walls(world);
+        physics.pos += self.sweep(physics.pos, shape, V3::new(movement.x, movement.y, 0.0), &walls);
+        self.push_out_of_walls(physics, shape, &walls);
+
+
+        // VERTICAL
+        if !was_grounded {
+            physics.velocity.z += self.gravity * delta;
+        }
+
+        let start_z = physics.pos.z;
+        physics.pos.z += movement.z + physics.velocity.z * delta;
+
+
+        // GROUND, look for ground from step height above where we started, so a fast fall does not pass through it
+        let probe_top = f32::max(start_z, physics.pos.z) + self.step_height;
+
+        let mut lowest_allowed = physics.pos.z;
+        if was_grounded && physics.velocity.z <= 0.0 {
+            lowest_allowed -= self.snap_distance;
+        }
+
+        match self.ground_below(physics.pos, shape, probe_top, world) {
+            Some((ground_z, normal)) if ground_z >= lowest_allowed && physics.velocity.z <= 0.0 => {
+                physics.pos.z = ground_z;
+                physics.velocity.z = 0.0;
+                physics.grounded = true;
+                physics.ground_normal = normal;
+                physics.time_since_grounded = 0.0;
+            },
+            _ => {
+                physics.grounded = false;
+                physics.ground_normal = V3::new(0.0, 0.0, 1.0);
+                physics.time_since_grounded += delta;
+            }
+        };
+    }
+
+
+    // Walls are the triangles too steep to stand on
+    fn walls(&self, world: &[Triangle]) -> Vec::<Triangle> {
+        world.iter().filter(|t| t.normal.z < self.min_ground_normal_z()).cloned().collect()
+    }
+
+
+    // The body capsule used against walls. The bottom is step_height up,
+    // so a low ledge is not a wall but something to step onto
+    fn body(&self, pos: V3, shape: CharacterShape) -> CollisionShape {
+        shape.capsule(pos, self.step_height)
+    }
+
+
+    // Moves longer than the radius, fx a roll, are swept so they stop at the first wall instead of passing through it.
+    // What is left of the move after the contact slides along the wall. Shorter moves are left to push_out_of_walls
+    fn sweep(&self, pos: V3, shape: CharacterShape, movement: V3, walls: &[Triangle]) -> V3 {
+
+        let dist = movement.magnitude();
+        if dist <= shape.radius {
+            return movement;
+        }
+
+        let hit = match shape_cast_triangles(&self.body(pos, shape), movement, dist, walls) {
+            Some(hit) => hit,
+            None => return movement
+        };
+
+        let to_contact = movement * (hit.distance / dist);
+        let rest = movement - to_contact;
+
+        let normal = V3::new(hit.normal.x, hit.normal.y, 0.0);
+        if normal.magnitude() < 1e-5 {
+            return to_contact;
+        }
+
+        let normal = normal.normalize();
+        let into_wall = rest.dot(&normal);
+        if into_wall >= 0.0 {
+            return movement;
+        }
+
+        to_contact + rest - normal * into_wall
+    }
+
+
+    // a few times for corners, each push only moves out of the deepest wall
+    fn push_out_of_walls(&self, physics: &mut shared::Physics, shape: CharacterShape, walls: &[Triangle]) {
+
+        if walls.is_empty() {
+            return;
+        }
+
+        for _ in 0..3 {
+            let body = self.body(physics.pos, shape);
+
+            match check_collision_shape_triangles(&body, walls) {
+                CollisionResult::Collision(resolve) => {
+                    let push = V3::new(resolve.x, resolve.y, 0.0);
+                    if push.magnitude() < 1e-5 {
+                        return;
+                    }
+
+                    physics.pos += push;
+
+                    // stop moving into the wall, keep the part along it
+                    let n = push.normalize();
+                    let into_wall = physics.velocity.dot(&n);
+                    if into_wall < 0.0 {
+                        physics.velocity -= n * into_wall;
+                    }
+                },
+                CollisionResult::NoCollision => {
+                    return;
+                }
+            }
+        }
+    }
+
+
+    // Highest walkable ground under the center and the edge of the character, at or below max_z
+    fn ground_below(&self, pos: V3, shape: CharacterShape, max_z: f32, world: &[Triangle]) -> Option<(f32, V3)> {
+
+        let r = shape.radius * 0.7;
+        let probes = [V3::new(0.0, 0.0, 0.0), V3::new(r, 0.0, 0.0), V3::new(-r, 0.0, 0.0), V3::new(0.0, r, 0.0), V3::new(0.0, -r, 0.0)];
+
+        let mut res: Option<(f32, V3)> = None;
+
+        for offset in &probes {
+            let point = pos + offset;
+
+            for triangle in world {
+                if triangle.normal.z < self.min_ground_normal_z() {
+                    continue;
+                }
+
+                let projection = triangle.project_point_z_axis(&point);
+                if projection.z > max_z || !triangle.inside(&projection) {
+                    continue;
+                }
+
+                if res.map(|(z, _)| projection.z > z).unwrap_or(true) {
+                    res = Some((projection.z, triangle.normal));
+                }
+            }
+        }
+
+        res
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn triangle(v0: V3, v1: V3, v2: V3) -> Triangle {
+        let normal = (v1 - v0).cross(&(v2 - v0)).normalize();
+        Triangle { v0, v1, v2, normal, d: -normal.dot(&v0) }
+    }
+
+    // square quad between the 4 corners, counter clockwise seen from the side it faces
+    fn quad(a: V3, b: V3, c: V3, d: V3) -> Vec::<Triangle> {
+        vec![triangle(a, b, c), triangle(a, c, d)]
+    }
+
+    fn floor(z: f32, min_x: f32, max_x: f32) -> Vec::<Triangle> {
+        quad(V3::new(min_x, -10.0, z), V3::new(max_x, -10.0, z), V3::new(max_x, 10.0, z), V3::new(min_x, 10.0, z))
+    }
+
+    // wall at x facing -x, from z min_z to max_z
+    fn wall(x: f32, min_z: f32, max_z: f32) -> Vec::<Triangle> {
+        quad(V3::new(x, -10.0, min_z), V3::new(x, -10.0, max_z), V3::new(x, 10.0, max_z), V3::new(x, 10.0, min_z))
+    }
+
+    fn shape() -> CharacterShape {
+        CharacterShape { radius: 0.3, height: 1.8 }
+    }
+
+    // run ticks moving with velocity along the ground
+    fn walk(physics: &mut shared::Physics, world: &[Triangle], ticks: usize) {
+        let controller = CharacterController::default();
+        let delta = 1.0 / 60.0;
+        for _ in 0..ticks {
+            let movement = V3::new(physics.velocity.x, physics.velocity.y, 0.0) * delta;
+            controller.move_character(physics, shape(), movement, world, delta);
+        }
+    }
+
+
+    #[test]
+    fn falls_and_lands() {
+        let world = floor(0.0, -10.0, 10.0);
+
+        let mut physics = shared::Physics::new();
+        physics.pos = V3::new(0.0, 0.0, 5.0);
+
+        walk(&mut physics, &world, 30);
+        assert!(!physics.grounded);
+        assert!(physics.time_since_grounded > 0.4);
+
+        walk(&mut physics, &world, 120);
+        assert!(physics.grounded);
+        assert_eq!(physics.pos.z, 0.0);
+        assert_eq!(physics.velocity.z, 0.0);
+        assert_eq!(physics.time_since_grounded, 0.0);
+        assert!((physics.ground_normal - V3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+    }
+
+
+    #[test]
+    fn steps_up_low_ledges_only() {
+        // floor, then a 0.2 step at x = 2, and a 1.0 wall at x = 4
+        let mut world = floor(0.0, -10.0, 2.0);
+        world.extend(wall(2.0, 0.0, 0.2));
+        world.extend(floor(0.2, 2.0, 4.0));
+        world.extend(wall(4.0, 0.2, 1.2));
+        world.extend(floor(1.2, 4.0, 10.0));
+
+        let mut physics = shared::Physics::new();
+        physics.grounded = true;
+        physics.velocity = V3::new(3.0, 0.0, 0.0);
+
+        walk(&mut physics, &world, 120);
+
+        // on the step, stopped by the wall
+        assert!(physics.grounded);
+        assert!((physics.pos.z - 0.2).abs() < 1e-4);
+        assert!(physics.pos.x < 4.0 && physics.pos.x > 3.5, "x {}", physics.pos.x);
+    }
+
+
+    #[test]
+    fn slides_along_walls() {
+        let mut world = floor(0.0, -10.0, 10.0);
+        world.extend(wall(1.0, 0.0, 3.0));
+
+        let mut physics = shared::Physics::new();
+        physics.grounded = true;
+        physics.velocity = V3::new(2.0, 2.0, 0.0);
+
+        walk(&mut physics, &world, 60);
+
+        assert!(physics.pos.x < 1.0 - 0.29, "x {}", physics.pos.x);
+        assert!(physics.pos.y > 1.0, "y {}", physics.pos.y);
+        assert!(physics.grounded);
+    }
+
+
+    #[test]
+    fn snaps_down_slopes_but_not_off_ledges() {
+        // 20 degree slope going down along +x, then a drop of 2
+        let drop = 20.0f32.to_radians().tan() * 4.0;
+        let mut world = quad(V3::new(0.0, -10.0, 0.0), V3::new(4.0, -10.0, -drop), V3::new(4.0, 10.0, -drop), V3::new(0.0, 10.0, 0.0));
+        world.extend(floor(-drop - 2.0, 4.0, 10.0));
+        world.extend(floor(0.0, -10.0, 0.0));
+
+        let mut physics = shared::Physics::new();
+        physics.grounded = true;
+        physics.velocity = V3::new(4.0, 0.0, 0.0);
+
+        // walking down the slope keeps ground contact every tick
+        let controller = CharacterController::default();
+        let delta = 1.0 / 60.0;
+        for _ in 0..50 {
+            controller.move_character(&mut physics, shape(), V3::new(4.0, 0.0, 0.0) * delta, &world, delta);
+            if physics.pos.x < 3.7 {
+                assert!(physics.grounded, "x {}", physics.pos.x);
+            }
+        }
+
+        assert!(physics.ground_normal.z < 0.99);
+
+        // off the end, and falling
+        for _ in 0..20 {
+            controller.move_character(&mut physics, shape(), V3::new(4.0, 0.0, 0.0) * delta, &world, delta);
+        }
+        assert!(!physics.grounded);
+    }
+
+
+    #[test]
+    fn long_moves_do_not_pass_through_thin_walls() {
+        let mut world = floor(0.0, -10.0, 10.0);
+        world.extend(wall(1.0, 0.0, 3.0));
+
+        let controller = CharacterController::default();
+
+        let mut physics = shared::Physics::new();
+        physics.grounded = true;
+
+        // a roll moving 3 in one tick, straight into the wall
+        controller.move_character(&mut physics, shape(), V3::new(3.0, 0.0, 0.0), &world, 1.0 / 60.0);
+        assert!(physics.pos.x < 1.0 - 0.29 && physics.pos.x > 0.6, "x {}", physics.pos.x);
+
+        // at an angle, the rest of the move slides along the wall
+        physics.pos = V3::new(0.0, 0.0, 0.0);
+        controller.move_character(&mut physics, shape(), V3::new(2.0, 2.0, 0.0), &world, 1.0 / 60.0);
+        assert!(physics.pos.x < 1.0 - 0.29, "x {}", physics.pos.x);
+        assert!((physics.pos.y - 2.0).abs() < 0.01, "y {}", physics.pos.y);
+        assert!(physics.grounded);
+    }
+
+
+    #[test]
+    fn too_steep_slopes_are_walls() {
+        // 60 degree slope up along +x from x = 1
+        let rise = 60.0f32.to_radians().tan() * 2.0;
+        let mut world = floor(0.0, -10.0, 1.0);
+        world.extend(quad(V3::new(1.0, -10.0, 0.0), V3::new(3.0, -10.0, rise), V3::new(3.0, 10.0, rise), V3::new(1.0, 10.0, 0.0)));
+
+        let mut physics = shared::Physics::new();
+        physics.grounded = true;
+        physics.velocity = V3::new(3.0, 0.0, 0.0);
+
+        walk(&mut physics, &world, 60);
+
+        assert!(physics.pos.x < 1.3, "x {}", physics.pos.x);
+        assert!(physics.pos.z < 0.36, "z {}", physics.pos.z);
+    }
+}
@@ -126,6 +126,16 @@ impl Triangle {
 }
 
 
+impl From<Triangle> for qt::Aabb {
+    fn from(t: Triangle) -> qt::Aabb {
+        let min = t.v0.xy().inf(&t.v1.xy()).inf(&t.v2.xy());
+        let max = t.v0.xy().sup(&t.v1.xy()).sup(&t.v2.xy());
+
+        qt::Aabb::new(min, max)
+    }
+}
+
+
 impl From<Triangle> for qt::QuadRect {
     fn from(t: Triangle) -> qt::QuadRect {
         let left = i32::min(i32::min(t.v0.x as i32, t.v1.x as i32), t.v2.x as i32);
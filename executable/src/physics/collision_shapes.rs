@@ -0,0 +1,656 @@
+use nalgebra as na;
+
+use crate::physics::collision_3d::*;
+
+
+// Shape a hitbox mesh is collided as, set with a custom property "shape" on the blender object.
+// The hitbox mesh is always a box, sphere and capsule are fitted inside it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShapeKind {
+    Box,
+    Sphere,
+    Capsule,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sphere {
+    pub center: na::Vector3::<f32>,
+    pub radius: f32,
+}
+
+
+// All points within radius of the segment a b
+#[derive(Debug, Clone, Copy)]
+pub struct Capsule {
+    pub a: na::Vector3::<f32>,
+    pub b: na::Vector3::<f32>,
+    pub radius: f32,
+}
+
+
+#[derive(Debug, Clone)]
+pub enum CollisionShape {
+    Box(CollisionBox),
+    Sphere(String, Sphere),
+    Capsule(String, Capsule),
+}
+
+
+impl CollisionShape {
+
+    // vertices are the 8 corners of the hitbox mesh, in the order CollisionBox::from_mesh_data uses
+    pub fn from_mesh_data(kind: ShapeKind, vertices: &Vec<na::Vector3::<f32>>, name: &str) -> CollisionShape {
+
+        let mut collision_box = CollisionBox::from_mesh_data(vertices);
+        collision_box.name = name.to_string();
+
+        let (center, axes, half_sizes) = box_frame(&collision_box);
+
+        match kind {
+            ShapeKind::Box => CollisionShape::Box(collision_box),
+            ShapeKind::Sphere => {
+                let radius = half_sizes.iter().cloned().fold(f32::MAX, f32::min);
+                CollisionShape::Sphere(name.to_string(), Sphere { center, radius })
+            },
+            ShapeKind::Capsule => {
+                // along the longest side, as wide as the narrowest of the others
+                let mut long = 0;
+                for i in 1..3 {
+                    if half_sizes[i] > half_sizes[long] {
+                        long = i;
+                    }
+                }
+
+                let radius = (0..3).filter(|i| *i != long).map(|i| half_sizes[i]).fold(f32::MAX, f32::min);
+                let half_segment = axes[long] * (half_sizes[long] - radius).max(0.0);
+
+                CollisionShape::Capsule(name.to_string(), Capsule { a: center - half_segment, b: center + half_segment, radius })
+            },
+        }
+    }
+
+
+    pub fn name(&self) -> &str {
+        match self {
+            CollisionShape::Box(b) => &b.name,
+            CollisionShape::Sphere(name, _) => name,
+            CollisionShape::Capsule(name, _) => name,
+        }
+    }
+
+
+    pub fn kind(&self) -> ShapeKind {
+        match self {
+            CollisionShape::Box(_) => ShapeKind::Box,
+            CollisionShape::Sphere(_, _) => ShapeKind::Sphere,
+            CollisionShape::Capsule(_, _) => ShapeKind::Capsule,
+        }
+    }
+
+
+    pub fn make_transformed(&self, translation: na::Vector3::<f32>, rotation: na::UnitQuaternion::<f32>) -> CollisionShape {
+        match self {
+            CollisionShape::Box(b) => {
+                let mut transformed = b.make_transformed(translation, rotation);
+                transformed.name = b.name.clone();
+                CollisionShape::Box(transformed)
+            },
+            CollisionShape::Sphere(name, s) => CollisionShape::Sphere(name.clone(), Sphere {
+                center: rotation * s.center + translation,
+                radius: s.radius
+            }),
+            CollisionShape::Capsule(name, c) => CollisionShape::Capsule(name.clone(), Capsule {
+                a: rotation * c.a + translation,
+                b: rotation * c.b + translation,
+                radius: c.radius
+            }),
+        }
+    }
+
+
+    // The shape part of the way from self to other, fx between two ticks. Boxes lerp their corners,
+    // so a rotating box is a bit smaller half way. Shapes of different kinds do not lerp and give other
+    pub fn lerp(&self, other: &CollisionShape, t: f32) -> CollisionShape {
+
+        let l = |a: &na::Vector3::<f32>, b: &na::Vector3::<f32>| a + (b - a) * t;
+
+        match (self, other) {
+            (CollisionShape::Box(a), CollisionShape::Box(b)) => CollisionShape::Box(CollisionBox {
+                name: b.name.clone(),
+                v0: l(&a.v0, &b.v0), v1: l(&a.v1, &b.v1), v2: l(&a.v2, &b.v2), v3: l(&a.v3, &b.v3),
+                v4: l(&a.v4, &b.v4), v5: l(&a.v5, &b.v5), v6: l(&a.v6, &b.v6), v7: l(&a.v7, &b.v7),
+            }),
+            (CollisionShape::Sphere(_, a), CollisionShape::Sphere(name, b)) => CollisionShape::Sphere(name.clone(), Sphere {
+                center: l(&a.center, &b.center),
+                radius: b.radius
+            }),
+            (CollisionShape::Capsule(_, a), CollisionShape::Capsule(name, b)) => CollisionShape::Capsule(name.clone(), Capsule {
+                a: l(&a.a, &b.a),
+                b: l(&a.b, &b.b),
+                radius: b.radius
+            }),
+            _ => other.clone()
+        }
+    }
+
+
+    // Longest distance any corner or end moved going from self to other
+    pub fn distance_moved(&self, other: &CollisionShape) -> f32 {
+        self.points().iter().zip(other.points().iter())
+            .map(|(a, b)| (b - a).magnitude())
+            .fold(0.0, f32::max)
+    }
+
+
+    fn points(&self) -> Vec::<na::Vector3::<f32>> {
+        match self {
+            CollisionShape::Box(b) => vec![b.v0, b.v1, b.v2, b.v3, b.v4, b.v5, b.v6, b.v7],
+            CollisionShape::Sphere(_, s) => vec![s.center],
+            CollisionShape::Capsule(_, c) => vec![c.a, c.b],
+        }
+    }
+
+
+    fn bounds(&self) -> (na::Vector3::<f32>, na::Vector3::<f32>) {
+        match self {
+            CollisionShape::Box(b) => (na::Vector3::new(b.min_x(), b.min_y(), b.min_z()), na::Vector3::new(b.max_x(), b.max_y(), b.max_z())),
+            CollisionShape::Sphere(_, s) => {
+                let r = na::Vector3::new(s.radius, s.radius, s.radius);
+                (s.center - r, s.center + r)
+            },
+            CollisionShape::Capsule(_, c) => {
+                let r = na::Vector3::new(c.radius, c.radius, c.radius);
+                (c.a.inf(&c.b) - r, c.a.sup(&c.b) + r)
+            },
+        }
+    }
+
+    pub fn min_x(&self) -> f32 { self.bounds().0.x }
+    pub fn max_x(&self) -> f32 { self.bounds().1.x }
+    pub fn min_y(&self) -> f32 { self.bounds().0.y }
+    pub fn max_y(&self) -> f32 { self.bounds().1.y }
+    pub fn min_z(&self) -> f32 { self.bounds().0.z }
+    pub fn max_z(&self) -> f32 { self.bounds().1.z }
+
+
+    pub fn center(&self) -> na::Vector3::<f32> {
+        let (min, max) = self.bounds();
+        (min + max) / 2.0
+    }
+
+
+    // Half the thinnest side, a shape moved less than this can not pass through a triangle
+    pub fn min_half_size(&self) -> f32 {
+        match self {
+            CollisionShape::Box(b) => {
+                let (_, _, half_sizes) = box_frame(b);
+                half_sizes.iter().cloned().fold(f32::MAX, f32::min)
+            },
+            CollisionShape::Sphere(_, s) => s.radius,
+            CollisionShape::Capsule(_, c) => c.radius,
+        }
+    }
+
+
+    // Closest point on or inside the shape, p itself when it is inside
+    pub fn closest_point(&self, p: &na::Vector3::<f32>) -> na::Vector3::<f32> {
+        let (center, radius) = match self {
+            CollisionShape::Box(b) => return closest_point_on_box(p, b),
+            CollisionShape::Sphere(_, s) => (s.center, s.radius),
+            CollisionShape::Capsule(_, c) => (closest_point_on_segment(p, &c.a, &c.b), c.radius),
+        };
+
+        let d = p - center;
+        if d.magnitude() <= radius {
+            return *p;
+        }
+
+        center + d.normalize() * radius
+    }
+
+
+    // The shape as a box, fx to render it. Spheres and capsules give their axis aligned bounds
+    pub fn bounding_box(&self) -> CollisionBox {
+        match self {
+            CollisionShape::Box(b) => b.clone(),
+            _ => {
+                let (min, max) = self.bounds();
+                CollisionBox::new((min + max) / 2.0, na::Rotation3::identity(), na::Matrix3::from_diagonal(&(max - min)))
+            }
+        }
+    }
+}
+
+
+// Same as check_collision, shape_1 is moved out of shape_2 by subtracting the returned vector
+pub fn check_collision_shapes(shape_1: &CollisionShape, shape_2: &CollisionShape) -> CollisionResult {
+
+    use CollisionShape::*;
+
+    match (shape_1, shape_2) {
+        (Box(b1), Box(b2)) => check_collision(b1, b2),
+        (Sphere(_, s), Box(b)) => sphere_box(s.center, s.radius, b),
+        (Capsule(_, c), Box(b)) => {
+            let p = closest_on_segment_to(c.a, c.b, |p| (closest_point_on_box(&p, b) - p).magnitude());
+            sphere_box(p, c.radius, b)
+        },
+        (Box(_), _) => flip(check_collision_shapes(shape_2, shape_1)),
+        (Sphere(_, s1), Sphere(_, s2)) => sphere_sphere(s1.center, s1.radius, s2.center, s2.radius),
+        (Sphere(_, s), Capsule(_, c)) => {
+            let p = closest_point_on_segment(&s.center, &c.a, &c.b);
+            sphere_sphere(s.center, s.radius, p, c.radius)
+        },
+        (Capsule(_, _), Sphere(_, _)) => flip(check_collision_shapes(shape_2, shape_1)),
+        (Capsule(_, c1), Capsule(_, c2)) => {
+            let p1 = closest_on_segment_to(c1.a, c1.b, |p| (closest_point_on_segment(&p, &c2.a, &c2.b) - p).magnitude());
+            let p2 = closest_point_on_segment(&p1, &c2.a, &c2.b);
+            sphere_sphere(p1, c1.radius, p2, c2.radius)
+        },
+    }
+}
+
+
+// Same as check_collision_triangles, the shape is moved out of the triangles by adding the returned vector.
+// For spheres and capsules the deepest triangle is used
+pub fn check_collision_shape_triangles(shape: &CollisionShape, triangles: &[Triangle]) -> CollisionResult {
+
+    let (center, a, b, radius) = match shape {
+        CollisionShape::Box(b) => return check_collision_triangles(b, triangles),
+        CollisionShape::Sphere(_, s) => (s.center, s.center, s.center, s.radius),
+        CollisionShape::Capsule(_, c) => ((c.a + c.b) / 2.0, c.a, c.b, c.radius),
+    };
+
+    let mut deepest: Option<na::Vector3::<f32>> = None;
+
+    for triangle in triangles {
+
+        // skip triangles that can not be close, most of them when the list is from a big area
+        let closest_to_center = closest_point_on_triangle(&center, triangle);
+        if (closest_to_center - center).magnitude() > radius + (b - a).magnitude() / 2.0 {
+            continue;
+        }
+
+        if let CollisionResult::Collision(resolve) = capsule_triangle(a, b, radius, triangle) {
+            if deepest.map(|d| resolve.magnitude() > d.magnitude()).unwrap_or(true) {
+                deepest = Some(resolve);
+            }
+        }
+    }
+
+    match deepest {
+        Some(resolve) => CollisionResult::Collision(resolve),
+        None => CollisionResult::NoCollision
+    }
+}
+
+
+// Overlap test only, without finding how to get out. Unlike check_collision_triangles this also
+// finds triangles that only poke into a box face, so it is exact for boxes too
+pub fn shape_overlaps_triangle(shape: &CollisionShape, triangle: &Triangle) -> bool {
+    match shape {
+        CollisionShape::Box(b) => box_overlaps_triangle(b, triangle),
+        CollisionShape::Sphere(_, s) => (closest_point_on_triangle(&s.center, triangle) - s.center).magnitude() < s.radius,
+        CollisionShape::Capsule(_, c) => capsule_triangle(c.a, c.b, c.radius, triangle).has_collision(),
+    }
+}
+
+
+// Separating axis test with the 3 box axes, the triangle normal and the 9 box axis x triangle edge axes
+fn box_overlaps_triangle(collision_box: &CollisionBox, triangle: &Triangle) -> bool {
+
+    let (center, axes, half_sizes) = box_frame(collision_box);
+
+    let vertices = [triangle.v0 - center, triangle.v1 - center, triangle.v2 - center];
+    let edges = [vertices[1] - vertices[0], vertices[2] - vertices[1], vertices[0] - vertices[2]];
+
+    let mut test_axes = vec![axes[0], axes[1], axes[2], triangle.normal];
+    for axis in &axes {
+        for edge in &edges {
+            let cross = axis.cross(edge);
+            // parallel, already covered by the other axes
+            if cross.magnitude() > 1e-6 {
+                test_axes.push(cross);
+            }
+        }
+    }
+
+    for axis in &test_axes {
+        let r: f32 = (0..3).map(|i| half_sizes[i] * axes[i].dot(axis).abs()).sum();
+
+        let projected: Vec::<f32> = vertices.iter().map(|v| v.dot(axis)).collect();
+        let min = projected.iter().cloned().fold(f32::MAX, f32::min);
+        let max = projected.iter().cloned().fold(f32::MIN, f32::max);
+
+        if min > r || max < -r {
+            return false;
+        }
+    }
+
+    true
+}
+
+
+fn flip(res: CollisionResult) -> CollisionResult {
+    match res {
+        CollisionResult::Collision(resolve) => CollisionResult::Collision(-resolve),
+        no_collision => no_collision
+    }
+}
+
+
+fn sphere_sphere(c1: na::Vector3::<f32>, r1: f32, c2: na::Vector3::<f32>, r2: f32) -> CollisionResult {
+    let d = c2 - c1;
+    let dist = d.magnitude();
+
+    if dist >= r1 + r2 {
+        return CollisionResult::NoCollision;
+    }
+
+    // same center, any direction works, pick up
+    let dir = if dist > 1e-6 { d / dist } else { na::Vector3::new(0.0, 0.0, 1.0) };
+
+    CollisionResult::Collision(dir * (r1 + r2 - dist))
+}
+
+
+fn sphere_box(center: na::Vector3::<f32>, radius: f32, collision_box: &CollisionBox) -> CollisionResult {
+
+    let (box_center, axes, half_sizes) = box_frame(collision_box);
+    let local = center - box_center;
+
+    let closest = closest_point_on_box(&center, collision_box);
+    let d = closest - center;
+    let dist = d.magnitude();
+
+    if dist > 1e-6 {
+        if dist >= radius {
+            return CollisionResult::NoCollision;
+        }
+
+        return CollisionResult::Collision(d / dist * (radius - dist));
+    }
+
+    // center is inside the box, push out through the closest face
+    let mut best = 0;
+    let mut best_depth = f32::MAX;
+    for i in 0..3 {
+        let depth = half_sizes[i] - local.dot(&axes[i]).abs();
+        if depth < best_depth {
+            best = i;
+            best_depth = depth;
+        }
+    }
+
+    let out = axes[best] * local.dot(&axes[best]).signum();
+
+    CollisionResult::Collision(-out * (best_depth + radius))
+}
+
+
+fn capsule_triangle(a: na::Vector3::<f32>, b: na::Vector3::<f32>, radius: f32, triangle: &Triangle) -> CollisionResult {
+
+    let p = closest_on_segment_to(a, b, |p| (closest_point_on_triangle(&p, triangle) - p).magnitude());
+
+    let closest = closest_point_on_triangle(&p, triangle);
+    let d = p - closest;
+    let dist = d.magnitude();
+
+    if dist >= radius {
+        return CollisionResult::NoCollision;
+    }
+
+    let signed_dist = |v: &na::Vector3::<f32>| triangle.normal.dot(v) + triangle.d;
+
+    // the segment goes through the triangle, push the end below it out
+    if dist < 1e-4 {
+        let lowest = f32::min(signed_dist(&a), signed_dist(&b));
+        return CollisionResult::Collision(triangle.normal * (radius - lowest.min(0.0)));
+    }
+
+    // behind the triangle, out is always along the normal
+    if signed_dist(&p) < 0.0 {
+        return CollisionResult::Collision(triangle.normal * (radius - signed_dist(&p)));
+    }
+
+    CollisionResult::Collision(d / dist * (radius - dist))
+}
+
+
+// Center, unit axes and half sizes of a box
+pub fn box_frame(collision_box: &CollisionBox) -> (na::Vector3::<f32>, [na::Vector3::<f32>; 3], [f32; 3]) {
+
+    let b = collision_box;
+    let center = (b.v0 + b.v1 + b.v2 + b.v3 + b.v4 + b.v5 + b.v6 + b.v7) / 8.0;
+
+    let sides = [b.v1 - b.v0, b.v3 - b.v0, b.v4 - b.v0];
+
+    let mut axes = [na::Vector3::new(1.0, 0.0, 0.0), na::Vector3::new(0.0, 1.0, 0.0), na::Vector3::new(0.0, 0.0, 1.0)];
+    let mut half_sizes = [0.0; 3];
+
+    for i in 0..3 {
+        let len = sides[i].magnitude();
+        if len > 1e-6 {
+            axes[i] = sides[i] / len;
+        }
+        half_sizes[i] = len / 2.0;
+    }
+
+    (center, axes, half_sizes)
+}
+
+
+fn closest_point_on_box(p: &na::Vector3::<f32>, collision_box: &CollisionBox) -> na::Vector3::<f32> {
+
+    let (center, axes, half_sizes) = box_frame(collision_box);
+    let local = p - center;
+
+    let mut res = center;
+    for i in 0..3 {
+        let d = local.dot(&axes[i]).max(-half_sizes[i]).min(half_sizes[i]);
+        res += axes[i] * d;
+    }
+
+    res
+}
+
+
+pub fn closest_point_on_segment(p: &na::Vector3::<f32>, a: &na::Vector3::<f32>, b: &na::Vector3::<f32>) -> na::Vector3::<f32> {
+    let ab = b - a;
+    let len_sq = ab.magnitude_squared();
+
+    if len_sq < 1e-12 {
+        return *a;
+    }
+
+    let t = ((p - a).dot(&ab) / len_sq).max(0.0).min(1.0);
+
+    a + ab * t
+}
+
+
+// Point on the segment a b where dist is smallest. dist has to be convex along the segment,
+// which the distance to any convex shape is, so a ternary search finds it
+fn closest_on_segment_to<F: Fn(na::Vector3::<f32>) -> f32>(a: na::Vector3::<f32>, b: na::Vector3::<f32>, dist: F) -> na::Vector3::<f32> {
+
+    let mut lo = 0.0;
+    let mut hi = 1.0;
+
+    for _ in 0..40 {
+        let t1 = lo + (hi - lo) / 3.0;
+        let t2 = hi - (hi - lo) / 3.0;
+
+        if dist(a + (b - a) * t1) <= dist(a + (b - a) * t2) {
+            hi = t2;
+        }
+        else {
+            lo = t1;
+        }
+    }
+
+    a + (b - a) * ((lo + hi) / 2.0)
+}
+
+
+// From Real-Time Collision Detection, Christer Ericson, 5.1.5
+pub fn closest_point_on_triangle(p: &na::Vector3::<f32>, triangle: &Triangle) -> na::Vector3::<f32> {
+
+    let (a, b, c) = (triangle.v0, triangle.v1, triangle.v2);
+
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn unit_box(center: na::Vector3::<f32>) -> CollisionShape {
+        CollisionShape::Box(CollisionBox::new(center, na::Rotation3::identity(), na::Matrix3::identity()))
+    }
+
+    fn sphere(center: na::Vector3::<f32>, radius: f32) -> CollisionShape {
+        CollisionShape::Sphere("".to_string(), Sphere { center, radius })
+    }
+
+    fn capsule(a: na::Vector3::<f32>, b: na::Vector3::<f32>, radius: f32) -> CollisionShape {
+        CollisionShape::Capsule("".to_string(), Capsule { a, b, radius })
+    }
+
+    // two triangles making the square -10..10 in x y at z = 0, facing up
+    fn ground() -> Vec::<Triangle> {
+        let v = |x, y| na::Vector3::new(x, y, 0.0);
+        let normal = na::Vector3::new(0.0, 0.0, 1.0);
+        vec![
+            Triangle { v0: v(-10.0, -10.0), v1: v(10.0, -10.0), v2: v(10.0, 10.0), normal, d: 0.0 },
+            Triangle { v0: v(-10.0, -10.0), v1: v(10.0, 10.0), v2: v(-10.0, 10.0), normal, d: 0.0 },
+        ]
+    }
+
+    fn assert_resolve(res: CollisionResult, expected: na::Vector3::<f32>) {
+        match res {
+            CollisionResult::Collision(resolve) => assert!((resolve - expected).magnitude() < 1e-3, "{:?} != {:?}", resolve, expected),
+            CollisionResult::NoCollision => panic!("Expected collision {:?}", expected),
+        }
+    }
+
+
+    #[test]
+    fn shapes_from_hitbox_mesh() {
+        // 1 x 1 x 3 box standing at origin
+        let b = CollisionBox::new(na::Vector3::new(0.0, 0.0, 1.5), na::Rotation3::identity(), na::Matrix3::from_diagonal(&na::Vector3::new(1.0, 1.0, 3.0)));
+        let vertices = vec![b.v0, b.v1, b.v2, b.v3, b.v4, b.v5, b.v6, b.v7];
+
+        match CollisionShape::from_mesh_data(ShapeKind::Capsule, &vertices, "body") {
+            CollisionShape::Capsule(name, c) => {
+                assert_eq!(name, "body");
+                assert!((c.radius - 0.5).abs() < 1e-4);
+                assert!((c.a - na::Vector3::new(0.0, 0.0, 0.5)).magnitude() < 1e-4);
+                assert!((c.b - na::Vector3::new(0.0, 0.0, 2.5)).magnitude() < 1e-4);
+            },
+            other => panic!("Not a capsule {:?}", other),
+        }
+
+        let s = CollisionShape::from_mesh_data(ShapeKind::Sphere, &vertices, "head");
+        assert_eq!(s.kind(), ShapeKind::Sphere);
+        assert!((s.max_z() - 2.0).abs() < 1e-4);
+    }
+
+
+    #[test]
+    fn sphere_and_capsule_vs_triangles() {
+        let ground = ground();
+
+        // 0.2 into the ground, pushed up
+        assert_resolve(check_collision_shape_triangles(&sphere(na::Vector3::new(1.0, 1.0, 0.3), 0.5), &ground), na::Vector3::new(0.0, 0.0, 0.2));
+        assert!(!check_collision_shape_triangles(&sphere(na::Vector3::new(1.0, 1.0, 0.6), 0.5), &ground).has_collision());
+
+        // center below the ground, still pushed up and out
+        assert_resolve(check_collision_shape_triangles(&sphere(na::Vector3::new(1.0, 1.0, -0.1), 0.5), &ground), na::Vector3::new(0.0, 0.0, 0.6));
+
+        // standing capsule, the bottom is 0.1 in
+        assert_resolve(check_collision_shape_triangles(&capsule(na::Vector3::new(0.0, 0.0, 0.4), na::Vector3::new(0.0, 0.0, 2.0), 0.5), &ground), na::Vector3::new(0.0, 0.0, 0.1));
+
+        // capsule through the ground, the lowest end is pushed above it
+        assert_resolve(check_collision_shape_triangles(&capsule(na::Vector3::new(0.0, 0.0, -0.3), na::Vector3::new(0.0, 0.0, 1.0), 0.5), &ground), na::Vector3::new(0.0, 0.0, 0.8));
+
+        // lying capsule away from the triangle edge
+        assert!(!check_collision_shape_triangles(&capsule(na::Vector3::new(11.0, 0.0, 0.0), na::Vector3::new(13.0, 0.0, 0.0), 0.5), &ground).has_collision());
+    }
+
+
+    #[test]
+    fn shapes_vs_box() {
+        let b = unit_box(na::Vector3::new(0.0, 0.0, 0.0));
+
+        // sphere to the right of the box, subtracting the result moves it further right
+        assert_resolve(check_collision_shapes(&sphere(na::Vector3::new(0.8, 0.0, 0.0), 0.5), &b), na::Vector3::new(-0.2, 0.0, 0.0));
+        assert_resolve(check_collision_shapes(&b, &sphere(na::Vector3::new(0.8, 0.0, 0.0), 0.5)), na::Vector3::new(0.2, 0.0, 0.0));
+        assert!(!check_collision_shapes(&sphere(na::Vector3::new(1.1, 0.0, 0.0), 0.5), &b).has_collision());
+
+        // center inside the box, out through the closest face
+        assert_resolve(check_collision_shapes(&sphere(na::Vector3::new(0.0, 0.4, 0.0), 0.5), &b), na::Vector3::new(0.0, -0.6, 0.0));
+
+        // capsule lying over the box corner
+        assert_resolve(check_collision_shapes(&capsule(na::Vector3::new(-2.0, 0.0, 0.9), na::Vector3::new(2.0, 0.0, 0.9), 0.5), &b), na::Vector3::new(0.0, 0.0, -0.1));
+    }
+
+
+    #[test]
+    fn shapes_vs_shapes() {
+        assert_resolve(check_collision_shapes(&sphere(na::Vector3::new(0.0, 0.0, 0.0), 1.0), &sphere(na::Vector3::new(1.5, 0.0, 0.0), 1.0)), na::Vector3::new(0.5, 0.0, 0.0));
+        assert!(!check_collision_shapes(&sphere(na::Vector3::new(0.0, 0.0, 0.0), 1.0), &sphere(na::Vector3::new(2.5, 0.0, 0.0), 1.0)).has_collision());
+
+        // two standing capsules side by side
+        let c1 = capsule(na::Vector3::new(0.0, 0.0, 0.5), na::Vector3::new(0.0, 0.0, 1.5), 0.5);
+        let c2 = capsule(na::Vector3::new(0.0, 0.8, 0.5), na::Vector3::new(0.0, 0.8, 1.5), 0.5);
+        assert_resolve(check_collision_shapes(&c1, &c2), na::Vector3::new(0.0, 0.2, 0.0));
+
+        // sphere on top of a capsule, in both orders
+        let s = sphere(na::Vector3::new(0.0, 0.0, 2.5), 0.6);
+        assert_resolve(check_collision_shapes(&s, &c1), na::Vector3::new(0.0, 0.0, -0.1));
+        assert_resolve(check_collision_shapes(&c1, &s), na::Vector3::new(0.0, 0.0, 0.1));
+    }
+}
@@ -1,218 +1,453 @@
-use nalgebra as na;
-
+use std::collections::BTreeMap;
 
 use crate::game;
-use crate::shared;
+use crate::physics::broadphase::*;
+use crate::physics::collision_3d::*;
+use crate::physics::collision_shapes::*;
+use crate::physics::movement_collision::world_triangles_under;
+use crate::physics::physics::EntityCollision;
+use crate::types::*;
 
-use crate::physics::projection_collision::*;
 
-use crate::physics::physics::EntityCollision;
+// slower than this into a contact does not bounce, so props resting on the ground do not jitter from gravity
+const RESTING_SPEED: f32 = 0.5;
+
+// rounds of velocity impulses, so contacts affecting each other, fx a stack of crates, even out
+const ITERATIONS: usize = 4;
 
+// characters are heavy next to props, a 20 kg crate thrown at one staggers it but does not send it flying
+const CHARACTER_INVERSE_MASS: f32 = 1.0 / 80.0;
 
+// a prop moving faster than this into a character knocks it back. Slower ones, fx a crate the character
+// walks into, only push the prop
+const KNOCKBACK_SPEED: f32 = 2.0;
+
+
+// Contact between two bodies, the normal points from body_1 to body_2
 #[derive(Copy, Clone, Debug)]
 pub struct Manifold {
-    entity_1_index: usize,
-    entity_2_index: usize ,
-    penetration: f32,
-    normal: na::Vector3::<f32>
+    pub body_1: usize,
+    pub body_2: usize,
+    pub normal: V3,
+    pub penetration: f32,
 }
 
 
+// Move props with their velocity and gravity, and resolve their collisions with the world, characters and each other.
+// Characters are moved by the character controller, so in here they push props with their velocity, and only get
+// the change in velocity from props hitting them as knockback, see Entity::knockback. Linear only, props do not rotate
+pub fn do_impulse_correction(scene: &mut game::Scene, delta: f32) -> Vec<EntityCollision> {
+
+    if scene.entities.props.count() == 0 {
+        return Vec::new();
+    }
 
-pub fn do_impulse_correction(scene: &mut game::Scene) -> Vec<EntityCollision>{
+    let gravity = scene.character_controller.gravity;
+    for prop in scene.entities.props.values_mut() {
+        let physics = &mut prop.base_entity.physics;
+        physics.velocity.z += gravity * delta;
+        physics.pos += physics.velocity * delta;
+    }
 
-    let (mut impulse_entities, collision_shapes, no_checks) = create_impulse_entities(scene);
+    scene.broadphase.update(&scene.entities);
 
-    let manifolds = do_impulse_collisions(&mut impulse_entities, &collision_shapes, no_checks);
 
-    impulse_collisions_resolution(&manifolds, &mut impulse_entities);
+    // copies of the physics to solve on, body 0 is the static world
+    let mut bodies = vec![static_body()];
+    let mut indices = BTreeMap::<EntityKey, usize>::new();
+    let mut manifolds = Vec::new();
 
-    for _e in impulse_entities.iter() {
+    for (a, b) in scene.broadphase.body_pairs() {
 
-        // Find a better way to not update walls
-        // and update entities in general
+        // characters push each other in resolve_movement_collision
+        if a.is_character() && b.is_character() {
+            continue;
+        }
 
-        //scene.entities.set_physics(e.entity_id, *e);
+        let (body_a, body_b) = match (body_index(scene, &mut bodies, &mut indices, a), body_index(scene, &mut bodies, &mut indices, b)) {
+            (Some(body_a), Some(body_b)) => (body_a, body_b),
+            _ => continue
+        };
+
+        let shapes_a = scene.broadphase.hitboxes(ProxyKey { entity: a, part: Part::Body });
+        let shapes_b = scene.broadphase.hitboxes(ProxyKey { entity: b, part: Part::Body });
+
+        if let Some(manifold) = shape_manifold(body_a, body_b, shapes_a, shapes_b) {
+            manifolds.push(manifold);
+        }
+    }
 
+    let prop_keys: Vec::<EntityKey> = scene.entities.props.entities.keys().map(|id| EntityKey::Prop(*id)).collect();
+    for key in prop_keys {
+        let body = match body_index(scene, &mut bodies, &mut indices, key) {
+            Some(body) => body,
+            None => continue
+        };
+
+        let triangles = match scene.entities.get(key) {
+            Some(prop) => world_triangles_under(scene, prop, 0.0),
+            None => continue
+        };
+
+        let shapes = scene.broadphase.hitboxes(ProxyKey { entity: key, part: Part::Body });
+        if let Some(manifold) = triangle_manifold(0, body, shapes, &triangles) {
+            manifolds.push(manifold);
+        }
     }
 
-    manifolds.iter().filter_map(|m| {
-        let _e1 = impulse_entities[m.entity_1_index];
-        let _e2 = impulse_entities[m.entity_2_index];
+    let keys: BTreeMap::<usize, EntityKey> = indices.iter().map(|(key, index)| (*index, *key)).collect();
+
+    let struck = struck_bodies(&bodies, &manifolds, &keys);
+
+    let before = bodies.clone();
+    resolve(&mut bodies, &manifolds);
 
 
-        Some(EntityCollision {
-            entity_1_id: 0,
-            entity_2_id: 0,
+    // write back the props, characters keep their physics and only take the knockback
+    for (key, index) in &indices {
+        let entity = match scene.entities.get_mut(*key) {
+            Some(entity) => entity,
+            None => continue
+        };
+
+        if !key.is_character() {
+            entity.base_entity.physics = bodies[*index];
+        }
+        else if struck.contains(index) {
+            let change = bodies[*index].velocity - before[*index].velocity;
+            entity.knockback += V3::new(change.x, change.y, 0.0);
+        }
+    }
+
+    manifolds.iter()
+        .filter_map(|m| match (keys.get(&m.body_1), keys.get(&m.body_2)) {
+            (Some(entity_1), Some(entity_2)) => Some(EntityCollision { entity_1: *entity_1, entity_2: *entity_2, normal: m.normal }),
+            _ => None
         })
-    }).collect()
+        .collect()
+}
+
 
+// Index of the entities body, added the first time
+fn body_index(scene: &game::Scene, bodies: &mut Vec::<shared::Physics>, indices: &mut BTreeMap::<EntityKey, usize>, key: EntityKey) -> Option<usize> {
 
+    if let Some(index) = indices.get(&key) {
+        return Some(*index);
+    }
+
+    let mut physics = scene.entities.get(key)?.base_entity.physics;
+    if key.is_character() {
+        physics.inverse_mass = CHARACTER_INVERSE_MASS;
+    }
+
+    bodies.push(physics);
+    indices.insert(key, bodies.len() - 1);
+
+    Some(bodies.len() - 1)
 }
 
 
-fn impulse_collisions_resolution( manifolds: &Vec<Manifold>, entities: &mut Vec<shared::Physics>) {
+// Characters hit by a prop moving into them faster than KNOCKBACK_SPEED
+fn struck_bodies(bodies: &[shared::Physics], manifolds: &[Manifold], keys: &BTreeMap::<usize, EntityKey>) -> Vec::<usize> {
+
+    let is_character = |body: usize| keys.get(&body).map(|key| key.is_character()).unwrap_or(false);
+
+    let mut struck = Vec::new();
+
     for manifold in manifolds {
-        let mut e1 = entities[manifold.entity_1_index];
-        let mut e2 = entities[manifold.entity_2_index];
+        // the normal points from body_1 to body_2
+        let (character, prop_speed) = match (is_character(manifold.body_1), is_character(manifold.body_2)) {
+            (false, true) => (manifold.body_2, bodies[manifold.body_1].velocity.dot(&manifold.normal)),
+            (true, false) => (manifold.body_1, -bodies[manifold.body_2].velocity.dot(&manifold.normal)),
+            _ => continue
+        };
+
+        if prop_speed > KNOCKBACK_SPEED && !struck.contains(&character) {
+            struck.push(character);
+        }
+    }
+
+    struck
+}
+
+
+fn static_body() -> shared::Physics {
+    let mut physics = shared::Physics::new();
+    physics.inverse_mass = 0.0;
+    physics
+}
+
+
+// Deepest contact between the world space shapes of two bodies
+pub fn shape_manifold(body_1: usize, body_2: usize, shapes_1: &[CollisionShape], shapes_2: &[CollisionShape]) -> Option<Manifold> {
 
-        impulse_collision_resolution(&mut e1, &mut e2, &manifold);
-        impulse_position_correction(&mut e1, &mut e2, &manifold);
+    let mut res: Option<Manifold> = None;
 
-        let _ = std::mem::replace(&mut entities[manifold.entity_1_index], e1);
-        let _ = std::mem::replace(&mut entities[manifold.entity_2_index], e2);
+    for shape_1 in shapes_1 {
+        for shape_2 in shapes_2 {
+            // shape_1 is moved out by subtracting, so it points from 1 to 2
+            if let CollisionResult::Collision(resolve) = check_collision_shapes(shape_1, shape_2) {
+                res = deepest(res, body_1, body_2, resolve);
+            }
+        }
     }
+
+    res
+}
+
+
+// Deepest contact between the world space shapes of a body and static triangles, the world is body_1
+pub fn triangle_manifold(world: usize, body: usize, shapes: &[CollisionShape], triangles: &[Triangle]) -> Option<Manifold> {
+
+    let mut res: Option<Manifold> = None;
+
+    for shape in shapes {
+        // the shape is moved out by adding, so it points from the triangles to the body
+        if let CollisionResult::Collision(resolve) = check_collision_shape_triangles(shape, triangles) {
+            res = deepest(res, world, body, resolve);
+        }
+    }
+
+    res
 }
 
-fn impulse_collision_resolution(entity_1: &mut shared::Physics, entity_2: &mut shared::Physics, manifold: &Manifold) {
+
+fn deepest(res: Option<Manifold>, body_1: usize, body_2: usize, resolve: V3) -> Option<Manifold> {
+
+    let penetration = resolve.magnitude();
+    if penetration < 1e-6 || res.map(|m| m.penetration >= penetration).unwrap_or(false) {
+        return res;
+    }
+
+    Some(Manifold { body_1, body_2, normal: resolve / penetration, penetration })
+}
+
+
+// Velocity impulses with restitution and friction for all the contacts, then push the bodies apart
+pub fn resolve(bodies: &mut [shared::Physics], manifolds: &[Manifold]) {
+
+    for _ in 0..ITERATIONS {
+        for manifold in manifolds {
+            impulse_collision_resolution(bodies, manifold);
+        }
+    }
+
+    for manifold in manifolds {
+        impulse_position_correction(bodies, manifold);
+    }
+}
+
+
+fn impulse_collision_resolution(bodies: &mut [shared::Physics], manifold: &Manifold) {
     // from https://gamedevelopment.tutsplus.com/tutorials/how-to-create-a-custom-2d-physics-engine-the-basics-and-impulse-resolution--gamedev-6331
 
+    let (entity_1, entity_2) = (bodies[manifold.body_1], bodies[manifold.body_2]);
+
+    let inverse_mass_sum = entity_1.inverse_mass + entity_2.inverse_mass;
+    if inverse_mass_sum <= 0.0 {
+        return;
+    }
 
     let relative_vel = entity_2.velocity - entity_1.velocity;
 
+    // already moving apart
     let vel_along_normal = relative_vel.dot(&manifold.normal);
     if vel_along_normal > 0.0 {
-
-        // println!("{:#?}", vel_along_normal);
-        return ;
+        return;
     }
 
-
     // restitution, how much energy is transferend back, should be between 0 and 1
     // 1 is no energy absorbed and everything goes to new velocity
-    // with 1 object in motion does not lose energy when hitting fx a wall
-    //
-    let e = 0.6;
+    let e = if -vel_along_normal < RESTING_SPEED {
+        0.0
+    }
+    else {
+        f32::min(entity_1.restitution, entity_2.restitution)
+    };
 
     // impulse scalar
-    let mut j =  -(1.0 + e) * vel_along_normal;
-    j /= entity_1.inverse_mass + entity_2.inverse_mass;
+    let j = -(1.0 + e) * vel_along_normal / inverse_mass_sum;
+    apply_impulse(bodies, manifold, manifold.normal * j);
+
+
+    // FRICTION, against the sliding, at most friction times the normal impulse
+    let relative_vel = bodies[manifold.body_2].velocity - bodies[manifold.body_1].velocity;
+    let tangent = relative_vel - manifold.normal * relative_vel.dot(&manifold.normal);
+    if tangent.magnitude() < 1e-6 {
+        return;
+    }
+
+    let tangent = tangent.normalize();
+    let mu = (entity_1.friction * entity_2.friction).sqrt();
+
+    let jt = (-relative_vel.dot(&tangent) / inverse_mass_sum).max(-j * mu).min(j * mu);
+    apply_impulse(bodies, manifold, tangent * jt);
+}
 
 
-    let impulse = j * manifold.normal;
-    entity_1.velocity -= entity_1.inverse_mass * impulse;
-    entity_2.velocity += entity_2.inverse_mass * impulse;
+fn apply_impulse(bodies: &mut [shared::Physics], manifold: &Manifold, impulse: V3) {
+    let inverse_mass_1 = bodies[manifold.body_1].inverse_mass;
+    let inverse_mass_2 = bodies[manifold.body_2].inverse_mass;
+
+    bodies[manifold.body_1].velocity -= impulse * inverse_mass_1;
+    bodies[manifold.body_2].velocity += impulse * inverse_mass_2;
 }
 
 
-fn impulse_position_correction(entity_1: &mut shared::Physics, entity_2: &mut shared::Physics, manifold: &Manifold) {
+fn impulse_position_correction(bodies: &mut [shared::Physics], manifold: &Manifold) {
+
+    let inverse_mass_1 = bodies[manifold.body_1].inverse_mass;
+    let inverse_mass_2 = bodies[manifold.body_2].inverse_mass;
+
+    let inverse_mass_sum = inverse_mass_1 + inverse_mass_2;
+    if inverse_mass_sum <= 0.0 {
+        return;
+    }
 
     let percent = 0.8;
     let slop = 0.01;
-    let correction = (f32::max(manifold.penetration - slop, 0.0) / (entity_1.inverse_mass + entity_2.inverse_mass)) * percent * manifold.normal;
+    let correction = (f32::max(manifold.penetration - slop, 0.0) / inverse_mass_sum) * percent * manifold.normal;
 
-    entity_1.pos -= entity_1.inverse_mass * correction;
-    entity_2.pos += entity_2.inverse_mass * correction;
+    bodies[manifold.body_1].pos -= inverse_mass_1 * correction;
+    bodies[manifold.body_2].pos += inverse_mass_2 * correction;
 }
 
 
 
-fn create_impulse_entities(scene: &game::Scene) -> (Vec<shared::Physics>, Vec<ConvexCollisionShape>, std::collections::HashSet<(usize,usize)>) {
+#[cfg(test)]
+mod tests {
 
-    let mut entities = Vec::new();
-    let mut collision_shapes = Vec::new();
-    let no_checks = std::collections::HashSet::new();
-
-    // Get some data out of enitiy component system
-    let player_physics = scene.entities.player.base_entity.physics;
-    entities.push(player_physics);
-    collision_shapes.push(ConvexCollisionShape::rectangle(&player_physics.pos, 1.0, 1.0, &player_physics));
+    use super::*;
 
+    fn body(x: f32, velocity_x: f32, inverse_mass: f32) -> shared::Physics {
+        let mut physics = shared::Physics::new();
+        physics.pos = V3::new(x, 0.0, 0.0);
+        physics.velocity = V3::new(velocity_x, 0.0, 0.0);
+        physics.inverse_mass = inverse_mass;
+        physics
+    }
 
+    fn unit_box(center: V3) -> CollisionShape {
+        CollisionShape::Box(CollisionBox::new(center, na::Rotation3::identity(), na::Matrix3::identity()))
+    }
 
+    // the square -10..10 in x y at z = 0, facing up
+    fn ground() -> Vec::<Triangle> {
+        let v = |x, y| V3::new(x, y, 0.0);
+        vec![Triangle::new(v(-10.0, -10.0), v(10.0, -10.0), v(10.0, 10.0)), Triangle::new(v(-10.0, -10.0), v(10.0, 10.0), v(-10.0, 10.0))]
+    }
 
-    /*
-    for enemy_id in &scene.state.enemies {
-    match scene.ecs.get_physics(*enemy_id) {
-    Some(en) => {
-    entities.push(*en);
-    collision_shapes.push(ConvexCollisionShape::rectangle(&en.pos, 1.0, 1.0, en ));
-},
-    None => continue
-};
-}
 
-    // ADD PLAYER PROJECTILES AS IMPULSE ENTITIES
-    for shot in &scene.state.player_shots {
-    match scene.ecs.get_physics(*shot) {
-    Some(proj) => {
+    #[test]
+    fn manifold_from_boxes() {
+        let manifold = shape_manifold(3, 5, &[unit_box(V3::new(0.0, 0.0, 0.0))], &[unit_box(V3::new(0.8, 0.0, 0.0))]).unwrap();
 
-    no_checks.insert((scene.player_id, proj.entity_id));
+        assert_eq!((manifold.body_1, manifold.body_2), (3, 5));
+        assert!((manifold.normal - V3::new(1.0, 0.0, 0.0)).magnitude() < 1e-4, "{:?}", manifold.normal);
+        assert!((manifold.penetration - 0.2).abs() < 1e-4);
 
-    entities.push(*proj);
-    collision_shapes.push(ConvexCollisionShape::rectangle(&proj.pos, 1.0, 1.0, proj));
-},
-    None => continue
-}
-}
+        assert!(shape_manifold(0, 1, &[unit_box(V3::new(0.0, 0.0, 0.0))], &[unit_box(V3::new(1.2, 0.0, 0.0))]).is_none());
+    }
 
-    // ADD PLAYER PROJECTILES AS IMPULSE ENTITIES
-    for shot in &scene.state.enemy_shots {
-    match scene.ecs.get_physics(*shot) {
-    Some(proj) => {
-    for e in &scene.state.enemies {
-    no_checks.insert((*e, proj.entity_id));
 
-}
-    entities.push(*proj);
-    collision_shapes.push(ConvexCollisionShape::rectangle(&proj.pos, 1.0, 1.0, proj));
-},
-    None => continue
-}
-}
+    #[test]
+    fn restitution_and_mass() {
+        let manifold = Manifold { body_1: 0, body_2: 1, normal: V3::new(1.0, 0.0, 0.0), penetration: 0.0 };
+
+        // equal mass and fully elastic, the velocities are swapped
+        let mut bodies = vec![body(0.0, 2.0, 1.0), body(1.0, -2.0, 1.0)];
+        bodies[0].restitution = 1.0;
+        bodies[1].restitution = 1.0;
+        resolve(&mut bodies, &[manifold]);
+        assert!((bodies[0].velocity.x + 2.0).abs() < 1e-4);
+        assert!((bodies[1].velocity.x - 2.0).abs() < 1e-4);
+
+        // no restitution, they move on together
+        let mut bodies = vec![body(0.0, 2.0, 1.0), body(1.0, -2.0, 1.0)];
+        bodies[0].restitution = 0.0;
+        resolve(&mut bodies, &[manifold]);
+        assert!(bodies[0].velocity.x.abs() < 1e-4 && bodies[1].velocity.x.abs() < 1e-4);
+
+        // against a body that can not move, it bounces back with the restitution
+        let mut bodies = vec![body(0.0, 0.0, 0.0), body(1.0, -4.0, 1.0)];
+        bodies[0].restitution = 0.5;
+        bodies[1].restitution = 0.5;
+        resolve(&mut bodies, &[manifold]);
+        assert_eq!(bodies[0].velocity.x, 0.0);
+        assert!((bodies[1].velocity.x - 2.0).abs() < 1e-4);
+
+        // already moving apart
+        let mut bodies = vec![body(0.0, -1.0, 1.0), body(1.0, 1.0, 1.0)];
+        resolve(&mut bodies, &[manifold]);
+        assert_eq!(bodies[1].velocity.x, 1.0);
+    }
 
-    for w in scene.scene.border_sides() {
-    // todo have these stored in the scene along side the collision shapes
-    let mut entity = shared::Physics::new(usize::MIN);
 
-    entity.inverse_mass = 0.0;
+    #[test]
+    fn friction_and_position_correction() {
+        let manifold = Manifold { body_1: 0, body_2: 1, normal: V3::new(0.0, 0.0, 1.0), penetration: 0.21 };
 
-    entities.push(entity);
-    collision_shapes.push(w.clone());
-}
-     */
+        // landing while sliding, friction takes some of the sliding but never reverses it
+        let mut bodies = vec![body(0.0, 0.0, 0.0), body(0.0, 3.0, 1.0)];
+        bodies[1].velocity.z = -2.0;
+        resolve(&mut bodies, &[manifold]);
 
+        let slide = bodies[1].velocity.x;
+        assert!(slide < 3.0 && slide > 0.0, "slide {}", slide);
+        assert!(bodies[1].velocity.z >= 0.0);
 
+        // only the body that can move is pushed out, percent of penetration minus slop
+        assert_eq!(bodies[0].pos.z, 0.0);
+        assert!((bodies[1].pos.z - 0.16).abs() < 1e-4, "z {}", bodies[1].pos.z);
 
-    (entities, collision_shapes, no_checks)
+        // between equal masses it is split
+        let mut bodies = vec![body(0.0, 0.0, 1.0), body(0.0, 0.0, 1.0)];
+        resolve(&mut bodies, &[manifold]);
+        assert!((bodies[0].pos.z + 0.08).abs() < 1e-4 && (bodies[1].pos.z - 0.08).abs() < 1e-4);
+    }
 
-}
 
+    #[test]
+    fn thrown_prop_knocks_character_back() {
+        // a crate at 6 m/s into a character standing still, and a character walking into a crate at 4 m/s
+        let manifold = Manifold { body_1: 0, body_2: 1, normal: V3::new(1.0, 0.0, 0.0), penetration: 0.0 };
+        let keys: BTreeMap::<usize, EntityKey> = vec![(0, EntityKey::Prop(3)), (1, EntityKey::Player)].into_iter().collect();
 
+        let mut bodies = vec![body(0.0, 6.0, 1.0 / 20.0), body(1.0, 0.0, CHARACTER_INVERSE_MASS)];
+        assert_eq!(struck_bodies(&bodies, &[manifold], &keys), vec![1]);
 
-fn do_impulse_collisions(entities: &[shared::Physics], shapes: &[ConvexCollisionShape], _no_checks: std::collections::HashSet<(usize,usize)> ) -> Vec<Manifold> {
+        resolve(&mut bodies, &[manifold]);
+        let knockback = bodies[1].velocity.x;
+        assert!(knockback > 0.5 && knockback < 6.0, "knockback {}", knockback);
+        assert!(bodies[0].velocity.x < knockback);
 
-    let mut res = Vec::new();
+        let bodies = vec![body(0.0, 0.0, 1.0 / 20.0), body(1.0, -4.0, CHARACTER_INVERSE_MASS)];
+        assert!(struck_bodies(&bodies, &[manifold], &keys).is_empty());
+    }
 
-    for index_1 in 0..entities.len() {
-        let _e1 = entities[index_1];
-        for index_2 in (index_1+1)..entities.len() {
-            let _e2 = entities[index_2];
-            // if both are a wall we don't care about collision
-            // TODO again find a better way, maybe just have a bool that says wall ??
-            /*
-            if e1.entity_id == 0 && e2.entity_id == 0 {
-            continue;
-        }
-             */
 
+    #[test]
+    fn dropped_box_comes_to_rest_on_the_ground() {
+        let ground = ground();
+        let hitbox = unit_box(V3::new(0.0, 0.0, 0.5));
 
-            let (col, normal, penetration) = collision_sat_shapes_impulse(&shapes[index_1], &shapes[index_2]);
+        let mut bodies = vec![static_body(), body(0.0, 1.0, 1.0)];
+        bodies[1].pos.z = 2.0;
 
+        let delta = 1.0 / 60.0;
+        for _ in 0..180 {
+            bodies[1].velocity.z += -9.82 * delta;
+            let velocity = bodies[1].velocity;
+            bodies[1].pos += velocity * delta;
 
-            if col {
-                // println!("{} - {}", index_1, index_2);
-                res.push(Manifold {
-                    entity_1_index: index_1,
-                    entity_2_index: index_2,
-                    normal,
-                    penetration
-                });
-            }
+            let shapes = vec![hitbox.make_transformed(bodies[1].pos, na::UnitQuaternion::identity())];
+            let manifolds: Vec::<Manifold> = triangle_manifold(0, 1, &shapes, &ground).into_iter().collect();
+            resolve(&mut bodies, &manifolds);
         }
-    }
-
-    //println!("{:#?}", res);
-    res
 
+        // resting on the ground, stopped by friction
+        assert!(bodies[1].pos.z.abs() < 0.05, "z {}", bodies[1].pos.z);
+        assert!(bodies[1].velocity.x.abs() < 1e-4, "vx {}", bodies[1].velocity.x);
+        assert!(bodies[1].velocity.z.abs() < 0.2, "vz {}", bodies[1].velocity.z);
+    }
 }
@@ -9,8 +9,24 @@ mod collision_3d;
 pub use self::collision_3d::{CollisionBox, check_collision, check_collision_triangles, Triangle, CollisionResult};
 
 
+mod collision_shapes;
+pub use self::collision_shapes::{CollisionShape, ShapeKind, Sphere, Capsule, check_collision_shapes, check_collision_shape_triangles};
+
+
+mod broadphase;
+pub use self::broadphase::{Broadphase, EntityKey, Part, ProxyKey, Aabb3, Contact};
+
+
+mod character_controller;
+pub use self::character_controller::{CharacterController, CharacterShape};
+
+
+mod raycast;
+pub use self::raycast::{raycast, shape_cast, sphere_cast, box_cast, raycast_triangles, shape_cast_triangles, HitTarget, RayHit, QueryFilter};
+
+
 mod physics;
 pub use self::physics::{process, EntityCollision};
 
 mod movement_collision;
-pub use self::movement_collision::{resolve_movement_collision};
+pub use self::movement_collision::{resolve_movement_collision, weapon_world_collision};
@@ -2,181 +2,203 @@ use crate::entity;
 use crate::game;
 use crate::types::*;
 use crate::physics::collision_3d::*;
+use crate::physics::collision_shapes::*;
+use crate::physics::broadphase::{Broadphase, EntityKey, Part, ProxyKey};
+use crate::physics::character_controller::CharacterShape;
 
 
-
-use quadtree as qt;
-
 pub fn resolve_movement_collision(scene: &mut game::Scene) {
 
-    for enemy in scene.entities.enemies.entities.values_mut() {
-        resolve_movement_collision_entities(&mut scene.entities.player, enemy);
+    scene.broadphase.update(&scene.entities);
+
+    // find all corrections first and then move, so the order of the pairs does not matter
+    let corrections = character_corrections(&scene.broadphase, &scene.entities);
+    for (key, correction) in corrections {
+        if let Some(entity) = scene.entities.get_mut(key) {
+            entity.base_entity.physics.pos += correction;
+        }
     }
 
-    let mut triangle_indices = scene.world_triangles_tree.query(&qt::Query::point(scene.entities.player.base_entity.physics.pos.x as i32, scene.entities.player.base_entity.physics.pos.y as i32));
+    // the pushes can move characters into walls or off the ground, so settle them in the world without moving
+    let controller = scene.character_controller;
 
-    triangle_indices.sort_by(|a, b| a.partial_cmp(b).unwrap());
     // maybe use a free list stored on scene or something to avoid reallocating each frame
-    // or just give the function both a list of triangles and list of indices??
-    let mut triangles = Vec::new();
-    for i in &triangle_indices {
-        triangles.push(scene.world_triangles[**i]);
-    }
+    let triangles = world_triangles_under(scene, &scene.entities.player, 0.0);
+
+    let player = &mut scene.entities.player;
+    controller.move_character(&mut player.base_entity.physics, CharacterShape::from_hitboxes(&player.hitboxes), V3::new(0.0, 0.0, 0.0), &triangles, 0.0);
 
-    //println!(" ({}, {}) {:?} ", scene.entities.player.base_entity.physics.pos.x as i32, scene.entities.player.base_entity.physics.pos.y as i32, triangle_indices);
 
-    resolve_world_collision_entity(&mut scene.entities.player, &triangles);
+    // find the triangles first, scene can not be borrowed while enemies are
+    let enemy_triangles: Vec::<Vec::<Triangle>> = scene.entities.enemies.values()
+        .map(|enemy| world_triangles_under(scene, enemy, 0.0))
+        .collect();
 
+    for (enemy, triangles) in scene.entities.enemies.values_mut().zip(enemy_triangles.iter()) {
+        controller.move_character(&mut enemy.base_entity.physics, CharacterShape::from_hitboxes(&enemy.hitboxes), V3::new(0.0, 0.0, 0.0), triangles, 0.0);
+    }
 
-    for enemy in scene.entities.enemies.values_mut() {
+}
 
-        let mut triangle_indices = scene.world_triangles_tree.query(&qt::Query::point(enemy.base_entity.physics.pos.x as i32, enemy.base_entity.physics.pos.y as i32));
 
-        triangle_indices.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        // maybe use a free list stored on scene or something to avoid reallocating each frame
-        // or just give the function both a list of triangles and list of indices??
-        let mut triangles = Vec::new();
-        for i in &triangle_indices {
-            triangles.push(scene.world_triangles[**i]);
-        }
+// Triangles the entity hitboxes can touch, also when moving up to extra in any direction.
+// Hitboxes are relative to the entity pos
+pub fn world_triangles_under(scene: &game::Scene, entity: &entity::Entity, extra: f32) -> Vec::<Triangle> {
 
-        resolve_world_collision_entity(enemy, &triangles);
+    let mut radius: f32 = extra;
+    for hitbox in &entity.hitboxes {
+        // corner of the hitbox xy bounds, so any rotation is covered
+        let x = f32::max(hitbox.max_x().abs(), hitbox.min_x().abs());
+        let y = f32::max(hitbox.max_y().abs(), hitbox.min_y().abs());
+        radius = radius.max((x * x + y * y).sqrt());
     }
 
+    let pos = entity.base_entity.physics.pos;
+    scene.world_triangles_near(pos.x, pos.y, radius)
 }
 
 
-fn resolve_movement_collision_entities(entity: &mut entity::Entity, e2: &mut entity::Entity ) {
+// How much to move each character to push overlapping characters apart. The push is split
+// by inverse_mass, so a character with inverse_mass 0 is not moved and the other one takes all of it.
+// Characters with several hitboxes are pushed by the deepest overlap between them, not the sum
+fn character_corrections(broadphase: &Broadphase, entities: &entity::Entities) -> Vec::<(EntityKey, V3)> {
+
+    let mut corrections = Vec::new();
+
+    for (a, b) in broadphase.body_pairs() {
+
+        // props are pushed by impulses instead
+        if !a.is_character() || !b.is_character() {
+            continue;
+        }
+
+        let (inverse_mass_a, inverse_mass_b) = match (entities.get(a), entities.get(b)) {
+            (Some(entity_a), Some(entity_b)) => (entity_a.base_entity.physics.inverse_mass, entity_b.base_entity.physics.inverse_mass),
+            _ => continue
+        };
 
-    // TODO make this more optimized, by calculation each transformed hitbox only once
-    for entity_hitbox_base in &entity.hitboxes {
-        let entity_hitbox = entity_hitbox_base.make_transformed(entity.base_entity.physics.pos, entity.base_entity.physics.rotation);
+        let total = inverse_mass_a + inverse_mass_b;
+        if total <= 0.0 {
+            continue;
+        }
+
+        let hitboxes_b = broadphase.hitboxes(ProxyKey { entity: b, part: Part::Body });
 
-        for e2_hitbox_base in &entity.hitboxes {
-            let e2_hitbox = e2_hitbox_base.make_transformed(e2.base_entity.physics.pos, e2.base_entity.physics.rotation);
+        let mut deepest: Option<V3> = None;
 
-            let collision_res = check_collision(&entity_hitbox, &e2_hitbox);
-            match collision_res {
-                CollisionResult::Collision(resolve_vec) => {
-                    entity.base_entity.physics.pos -= resolve_vec;
-                },
-                _ => {}
-            };
+        for hitbox_a in broadphase.hitboxes(ProxyKey { entity: a, part: Part::Body }) {
+            for hitbox_b in hitboxes_b {
 
+                // a is moved out of b by subtracting resolve_vec
+                if let CollisionResult::Collision(resolve_vec) = check_collision_shapes(hitbox_a, hitbox_b) {
+                    if deepest.map(|d| resolve_vec.magnitude() > d.magnitude()).unwrap_or(true) {
+                        deepest = Some(resolve_vec);
+                    }
+                }
+            }
+        }
 
+        if let Some(resolve_vec) = deepest {
+            corrections.push((a, -resolve_vec * inverse_mass_a / total));
+            corrections.push((b, resolve_vec * inverse_mass_b / total));
         }
     }
+
+    corrections
 }
 
 
-fn resolve_world_collision_entity(entity: &mut entity::Entity, world: &[Triangle] ) {
+// Where the weapon of the entity is inside the world, with the vector that moves it out.
+// Uses the weapon hitboxes from the last broadphase update, fx to deflect a swing that hits the ground
+pub fn weapon_world_collision(scene: &game::Scene, key: EntityKey) -> Option<V3> {
 
-    // TODO make this more optimized, by calculation each transformed hitbox only once
-    for entity_hitbox_base in &entity.hitboxes {
-        let entity_hitbox = entity_hitbox_base.make_transformed(entity.base_entity.physics.pos, entity.base_entity.physics.rotation);
+    let aabb = scene.broadphase.aabb(ProxyKey { entity: key, part: Part::Weapon })?;
 
-        let collision_res = check_collision_triangles(&entity_hitbox, world);
+    let center = (aabb.min + aabb.max) / 2.0;
+    let radius = (aabb.max.xy() - aabb.min.xy()).magnitude() / 2.0;
 
-        match collision_res {
-            CollisionResult::Collision(resolve_vec) => {
-                let resolve_threshold = 0.001;
-                //println!("regular resolve VEC {:?}", resolve_vec);
+    let triangles = scene.world_triangles_near(center.x, center.y, radius);
 
-                // to avoid jitter ing between this regular resolve and the floating by still touching
-                // state. This jitter is due to float numeric instability/imperfections/precision limit
-                // This will keep entity in this state
+    for hitbox in scene.broadphase.hitboxes(ProxyKey { entity: key, part: Part::Weapon }) {
+        if let CollisionResult::Collision(resolve_vec) = check_collision_shape_triangles(hitbox, &triangles) {
+            return Some(resolve_vec);
+        }
+    }
 
-                if resolve_vec.x.abs() > resolve_threshold {
-                    entity.base_entity.physics.pos.x += resolve_vec.x;
-                }
-                if resolve_vec.y.abs() > resolve_threshold {
-                    entity.base_entity.physics.pos.y += resolve_vec.y
-                }
+    None
+}
 
-                if resolve_vec.z.abs() > resolve_threshold {
-                    entity.base_entity.physics.pos.z += resolve_vec.z;
-                }
 
-                entity.base_entity.physics.falling = false;
-                entity.base_entity.physics.velocity.z = 0.0;
-            },
-            _ => {
+#[cfg(test)]
+mod tests {
 
-                // If not falling and close to ground, snap to ground, to avoid jitter
-                if !entity.base_entity.physics.falling {
+    use super::*;
 
-                    // TODO only have 1 movement hitbox for each entity
-                    for hitbox in &entity.hitboxes {
+    fn character(x: f32, inverse_mass: f32) -> entity::Entity {
+        let mut entity = entity::Entity::new(None, "character".to_string());
+        entity.base_entity.physics.pos = V3::new(x, 0.0, 0.0);
+        entity.base_entity.physics.inverse_mass = inverse_mass;
+        entity.hitboxes.push(CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 1.0), na::Rotation3::identity(), na::Matrix3::new_scaling(1.0))));
+        entity
+    }
 
-                        let max_x = hitbox.max_x();
-                        let max_y = hitbox.max_y();
-                        let min_x = hitbox.min_x();
-                        let min_y = hitbox.min_y();
 
+    fn total_correction(corrections: &[(EntityKey, V3)], key: EntityKey) -> V3 {
+        corrections.iter().filter(|(k, _)| *k == key).map(|(_, c)| c).sum()
+    }
 
-                        let mut fall_or_slide_res = FallOrSlide::Fall;
 
-                        fall_or_slide(&(entity.base_entity.physics.pos + V3::new(max_x, max_y,0.0)), &world, &mut fall_or_slide_res);
-                        fall_or_slide(&(entity.base_entity.physics.pos + V3::new(min_x, max_y,0.0)), &world, &mut fall_or_slide_res);
-                        fall_or_slide(&(entity.base_entity.physics.pos + V3::new(max_x, min_y,0.0)), &world, &mut fall_or_slide_res);
-                        fall_or_slide(&(entity.base_entity.physics.pos + V3::new(min_x, min_y,0.0)), &world, &mut fall_or_slide_res);
+    #[test]
+    fn enemies_push_each_other_by_inverse_mass() {
+        let mut entities = entity::Entities::new();
+        entities.player = character(-10.0, 1.0);
 
-                        match fall_or_slide_res {
-                            FallOrSlide::Fall => {
-                                entity.base_entity.physics.falling = true;
-                            },
-                            FallOrSlide::Slide(z_dist) => {
-                                entity.base_entity.physics.pos.z -= z_dist;
-                            }
-                        };
-                    }
-                }
-            }
-        };
-    }
-}
+        let a = entities.enemies.add(character(10.0, 1.0));
+        let b = entities.enemies.add(character(10.6, 1.0));
 
-enum FallOrSlide {
-    Fall,
-    Slide(f32)
-}
+        // c does not move, so d takes all of the push
+        let c = entities.enemies.add(character(20.0, 0.0));
+        let d = entities.enemies.add(character(20.6, 1.0));
 
-fn fall_or_slide(point: &V3, world: &[Triangle], res: &mut FallOrSlide) {
-    let mut set_falling = true;
-    // If not falling and close to ground, snap to ground, to avoid jitter
+        let mut broadphase = Broadphase::new();
+        broadphase.update(&entities);
 
-    let mut max_z = 0.0;
-    for triangle in world {
-        let projection  = triangle.project_point_z_axis(&point);
+        let corrections = character_corrections(&broadphase, &entities);
 
-        let inside = triangle.inside(&projection);
-        let z_diff = point.z - projection.z;
+        let a_correction = total_correction(&corrections, EntityKey::Enemy(a));
+        let b_correction = total_correction(&corrections, EntityKey::Enemy(b));
+        assert!((a_correction.x + 0.2).abs() < 0.001);
+        assert!((b_correction.x - 0.2).abs() < 0.001);
 
-        // close to gorund maybe don't fall
-        // play with this fall heigt
-        if inside {
-            //println!("InSide z_diff {:?}", z_diff);
-            if z_diff < 0.3 {
+        assert_eq!(total_correction(&corrections, EntityKey::Enemy(c)), V3::new(0.0, 0.0, 0.0));
+        assert!((total_correction(&corrections, EntityKey::Enemy(d)).x - 0.4).abs() < 0.001);
 
-                set_falling = false;
-                // to avoid jitter don't snap when too close to ground
-                if z_diff > 0.01 {
-                    max_z = f32::max(max_z, z_diff);
-                }
-            }
-        }
+        assert_eq!(total_correction(&corrections, EntityKey::Player), V3::new(0.0, 0.0, 0.0));
     }
 
 
-    if !set_falling {
-        let mut new_dist = max_z;
-        match res {
-            FallOrSlide::Slide(z_dist) => {
-                new_dist = f32::min(*z_dist, max_z);
-            },
-            _ => {}
-        }
-        *res = FallOrSlide::Slide(new_dist);
-    }
+    #[test]
+    fn several_hitboxes_push_by_deepest() {
+        // a second box on top, so both pairs of boxes overlap
+        let stacked = |x: f32| {
+            let mut entity = character(x, 1.0);
+            entity.hitboxes.push(CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 3.0), na::Rotation3::identity(), na::Matrix3::new_scaling(1.0))));
+            entity
+        };
+
+        let mut entities = entity::Entities::new();
+        entities.player = character(-10.0, 1.0);
+
+        let a = entities.enemies.add(stacked(10.0));
+        let b = entities.enemies.add(stacked(10.6));
 
+        let mut broadphase = Broadphase::new();
+        broadphase.update(&entities);
+
+        let corrections = character_corrections(&broadphase, &entities);
+
+        // pushed apart by the 0.4 overlap once, not once for each pair
+        assert!((total_correction(&corrections, EntityKey::Enemy(a)).x + 0.2).abs() < 0.001);
+        assert!((total_correction(&corrections, EntityKey::Enemy(b)).x - 0.2).abs() < 0.001);
+    }
 }
@@ -6,11 +6,23 @@ use crate::physics::movement_collision::*;
 
 use crate::entity;
 
+use crate::physics::broadphase::EntityKey;
+use crate::physics::character_controller::CharacterShape;
+use crate::physics::raycast::{shape_cast, QueryFilter};
+use crate::physics::Triangle;
+use crate::types::*;
 
+
+// how fast knockback slows down, pr second
+const KNOCKBACK_DAMPING: f32 = 6.0;
+
+
+// Contact between two entities this tick, the normal points from entity_1 to entity_2
 #[derive(Copy, Clone, Debug)]
 pub struct EntityCollision {
-    pub entity_1_id: usize,
-    pub entity_2_id: usize
+    pub entity_1: EntityKey,
+    pub entity_2: EntityKey,
+    pub normal: V3,
 }
 
 
@@ -22,7 +34,7 @@ pub fn process(scene: &mut game::Scene, delta: f32) -> Vec<EntityCollision> {
 
 
     //DO IMPULSE COLLISION AND UPDATE
-    let impulse_collisions = do_impulse_correction(scene);
+    let impulse_collisions = do_impulse_correction(scene, delta);
 
     resolve_movement_collision(scene);
 
@@ -38,52 +50,81 @@ fn update_entities_position(scene: &mut game::Scene, delta: f32) {
     // Maybe with a list of physics entities or something like that, or just go over all of them
     // and take the ones where we want physics
 
-    //for entity in scene.entities.values_mut() {
-    let _entity  = &mut scene.entities.player;
-    update_entity_position(&mut scene.entities.player, delta);
+    let controller = scene.character_controller;
+
+    let movement = entity_movement(&mut scene.entities.player, delta);
+    let movement = clamp_at_characters(scene, EntityKey::Player, &scene.entities.player, movement);
+    let triangles = world_triangles_under(scene, &scene.entities.player, movement.magnitude());
+
+    let player = &mut scene.entities.player;
+    controller.move_character(&mut player.base_entity.physics, CharacterShape::from_hitboxes(&player.hitboxes), movement, &triangles, delta);
+
+
+    // movements and triangles first, scene can not be borrowed while enemies are
+    let enemy_movements: Vec::<V3> = scene.entities.enemies.values_mut()
+        .map(|enemy| entity_movement(enemy, delta))
+        .collect();
+
+    let enemy_movements: Vec::<V3> = scene.entities.enemies.entities.iter().zip(enemy_movements.iter())
+        .map(|((id, enemy), movement)| clamp_at_characters(scene, EntityKey::Enemy(*id), enemy, *movement))
+        .collect();
 
-    for enemy in scene.entities.enemies.values_mut() {
-        update_entity_position(enemy, delta);
+    let enemy_triangles: Vec::<Vec::<Triangle>> = scene.entities.enemies.values().zip(enemy_movements.iter())
+        .map(|(enemy, movement)| world_triangles_under(scene, enemy, movement.magnitude()))
+        .collect();
+
+    for ((enemy, movement), triangles) in scene.entities.enemies.values_mut().zip(enemy_movements.iter()).zip(enemy_triangles.iter()) {
+        controller.move_character(&mut enemy.base_entity.physics, CharacterShape::from_hitboxes(&enemy.hitboxes), *movement, triangles, delta);
     }
 }
 
 
-fn update_entity_position(entity: &mut entity::Entity, delta: f32) {
-    // maybe there is root_motion
-    let mut update_with_vel = true;
+// Where the entity wants to move this tick, root motion when the animation has it, else the velocity.
+// Knockback is added on top. Falling is done by the character controller
+fn entity_movement(entity: &mut entity::Entity, delta: f32) -> V3 {
 
-    match entity.animation_player.as_mut() {
-        Some(animation_player) => {
-            match animation_player.current_root_motion() {
-                Some(root_motion) =>
-                {
-                    update_with_vel = false;
-                    let z_rot = entity.base_entity.physics.rotation.euler_angles().2;
+    let knockback = entity.knockback * delta;
+    entity.knockback *= f32::max(1.0 - KNOCKBACK_DAMPING * delta, 0.0);
+    if entity.knockback.magnitude() < 0.05 {
+        entity.knockback = V3::new(0.0, 0.0, 0.0);
+    }
 
-                    let rot_mat = na::Matrix3::new_rotation(z_rot);
-                    let offset = rot_mat * root_motion;
+    let root_motion = entity.animation_player.as_mut().and_then(|animation_player| animation_player.current_root_motion());
 
+    let movement = match root_motion {
+        Some(root_motion) => {
+            let z_rot = entity.base_entity.physics.rotation.euler_angles().2;
 
-                    // maybe still apply z for gravity
-                    entity.base_entity.physics.pos += offset;
-                },
-                None => {}
-            }
+            let rot_mat = na::Matrix3::new_rotation(z_rot);
+            rot_mat * root_motion
         },
-        _ => {}
-    }
+        None => {
+            let velocity = entity.base_entity.physics.velocity;
+            V3::new(velocity.x, velocity.y, 0.0) * delta
+        }
+    };
+
+    movement + knockback
+}
+
+
+// Long moves, fx a roll or a lunge, stop at the first character in the way instead of passing through it.
+// The other characters are where the last broadphase update had them. Short moves are left to the pushes in resolve_movement_collision
+fn clamp_at_characters(scene: &game::Scene, key: EntityKey, entity: &entity::Entity, movement: V3) -> V3 {
+
+    let shape = CharacterShape::from_hitboxes(&entity.hitboxes);
 
-    if update_with_vel {
-        entity.base_entity.physics.pos += entity.base_entity.physics.velocity * delta;
+    let dist = movement.magnitude();
+    if dist <= shape.radius {
+        return movement;
     }
 
-    if entity.base_entity.physics.falling {
-        // APPLY GRAVITY -- results in jancky motion down hill
-        let v = entity.base_entity.physics.velocity.z;
-        let a = -9.82;
-        let gravity = v + (1.0/2.0 * a);
+    let body = shape.capsule(entity.base_entity.physics.pos, 0.0);
 
-        entity.base_entity.physics.velocity.z += gravity * delta;
+    match shape_cast(scene, &body, movement, dist, &QueryFilter::bodies_only().ignoring(key)) {
+        // moving away from one it already touches is fine
+        Some(hit) if hit.normal.dot(&movement) < 0.0 => movement * (hit.distance / dist),
+        _ => movement
     }
 }
 
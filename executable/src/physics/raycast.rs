@@ -0,0 +1,617 @@
+use quadtree as qt;
+
+use crate::game;
+use crate::physics::broadphase::*;
+use crate::physics::collision_3d::*;
+use crate::physics::collision_shapes::*;
+use crate::render_gl::ChunkCoord;
+use crate::types::*;
+
+
+// What a ray or shape cast hit
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HitTarget {
+    // index into Scene.world_triangles
+    World(usize),
+    // chunk and index into the chunks collision triangles
+    Terrain(ChunkCoord, usize),
+    Entity(ProxyKey),
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    pub point: V3,
+    // unit length, facing back towards where the cast came from
+    pub normal: V3,
+    // along the cast direction. For shape casts how far the shape can move before touching
+    pub distance: f32,
+    pub target: HitTarget,
+}
+
+
+// What a cast can hit. Entities are hit with their hitboxes from the last broadphase update
+#[derive(Debug, Copy, Clone)]
+pub struct QueryFilter {
+    pub world: bool,
+    pub bodies: bool,
+    pub weapons: bool,
+    // fx the entity casting, so it does not hit itself
+    pub ignore: Option<EntityKey>,
+}
+
+
+impl QueryFilter {
+
+    // Only the static world and the terrain
+    pub fn world_only() -> QueryFilter {
+        QueryFilter { world: true, bodies: false, weapons: false, ignore: None }
+    }
+
+
+    // Only the bodies of characters and props
+    pub fn bodies_only() -> QueryFilter {
+        QueryFilter { world: false, bodies: true, weapons: false, ignore: None }
+    }
+
+
+    // The world and character bodies, not weapons
+    pub fn all() -> QueryFilter {
+        QueryFilter { world: true, bodies: true, weapons: false, ignore: None }
+    }
+
+
+    pub fn ignoring(self, key: EntityKey) -> QueryFilter {
+        QueryFilter { ignore: Some(key), ..self }
+    }
+
+
+    fn allows(&self, key: ProxyKey) -> bool {
+        if self.ignore == Some(key.entity) {
+            return false;
+        }
+
+        match key.part {
+            Part::Body => self.bodies,
+            Part::Weapon => self.weapons,
+        }
+    }
+}
+
+
+// Closest hit along the ray within max_dist, dir does not have to be normalized.
+// A ray starting inside a hitbox hits it at distance 0. Triangles are hit from both sides
+pub fn raycast(scene: &game::Scene, origin: V3, dir: V3, max_dist: f32, filter: &QueryFilter) -> Option<RayHit> {
+
+    let dir = normalized(dir)?;
+    let end = origin + dir * max_dist;
+
+    let mut best: Option<RayHit> = None;
+
+    for (target, triangle) in world_candidates(scene, filter, origin.inf(&end), origin.sup(&end)) {
+        if let Some(distance) = ray_triangle(origin, dir, max_dist, &triangle) {
+            let normal = facing(triangle.normal, dir);
+            keep_closest(&mut best, RayHit { point: origin + dir * distance, normal, distance, target });
+        }
+    }
+
+    for (key, shapes) in entity_candidates(scene, filter, origin.inf(&end), origin.sup(&end)) {
+        for shape in shapes {
+            if let Some((distance, normal)) = ray_shape(origin, dir, max_dist, shape) {
+                keep_closest(&mut best, RayHit { point: origin + dir * distance, normal, distance, target: HitTarget::Entity(key) });
+            }
+        }
+    }
+
+    best
+}
+
+
+// Move a world space shape along dir and find the first thing it touches within max_dist
+pub fn shape_cast(scene: &game::Scene, shape: &CollisionShape, dir: V3, max_dist: f32, filter: &QueryFilter) -> Option<RayHit> {
+
+    let dir = normalized(dir)?;
+    let end = shape.make_transformed(dir * max_dist, na::UnitQuaternion::identity());
+
+    let min = V3::new(f32::min(shape.min_x(), end.min_x()), f32::min(shape.min_y(), end.min_y()), f32::min(shape.min_z(), end.min_z()));
+    let max = V3::new(f32::max(shape.max_x(), end.max_x()), f32::max(shape.max_y(), end.max_y()), f32::max(shape.max_z(), end.max_z()));
+
+    let mut best: Option<RayHit> = None;
+
+    for (target, triangle) in world_candidates(scene, filter, min, max) {
+        if let Some(hit) = shape_cast_triangle(shape, dir, max_dist, &triangle, target) {
+            keep_closest(&mut best, hit);
+        }
+    }
+
+    for (key, shapes) in entity_candidates(scene, filter, min, max) {
+        for other in shapes {
+            let toi = time_of_impact(max_dist, step_size(shape), |t| check_collision_shapes(&moved(shape, dir, t), other).has_collision());
+
+            if let Some(distance) = toi {
+                keep_closest(&mut best, contact(shape, dir, distance, |p| other.closest_point(p), HitTarget::Entity(key)));
+            }
+        }
+    }
+
+    best
+}
+
+
+pub fn sphere_cast(scene: &game::Scene, center: V3, radius: f32, dir: V3, max_dist: f32, filter: &QueryFilter) -> Option<RayHit> {
+    shape_cast(scene, &CollisionShape::Sphere("".to_string(), Sphere { center, radius }), dir, max_dist, filter)
+}
+
+
+pub fn box_cast(scene: &game::Scene, collision_box: &CollisionBox, dir: V3, max_dist: f32, filter: &QueryFilter) -> Option<RayHit> {
+    shape_cast(scene, &CollisionShape::Box(collision_box.clone()), dir, max_dist, filter)
+}
+
+
+// Closest hit of the ray with the triangles, the target is World with the index in triangles
+pub fn raycast_triangles(origin: V3, dir: V3, max_dist: f32, triangles: &[Triangle]) -> Option<RayHit> {
+
+    let dir = normalized(dir)?;
+
+    let mut best: Option<RayHit> = None;
+    for (i, triangle) in triangles.iter().enumerate() {
+        if let Some(distance) = ray_triangle(origin, dir, max_dist, triangle) {
+            let normal = facing(triangle.normal, dir);
+            keep_closest(&mut best, RayHit { point: origin + dir * distance, normal, distance, target: HitTarget::World(i) });
+        }
+    }
+
+    best
+}
+
+
+// Same as shape_cast, against just the triangles
+pub fn shape_cast_triangles(shape: &CollisionShape, dir: V3, max_dist: f32, triangles: &[Triangle]) -> Option<RayHit> {
+
+    let dir = normalized(dir)?;
+
+    let mut best: Option<RayHit> = None;
+    for (i, triangle) in triangles.iter().enumerate() {
+        if let Some(hit) = shape_cast_triangle(shape, dir, max_dist, triangle, HitTarget::World(i)) {
+            keep_closest(&mut best, hit);
+        }
+    }
+
+    best
+}
+
+
+// Distance and normal where the ray enters the shape, dir has to be normalized
+pub fn ray_shape(origin: V3, dir: V3, max_dist: f32, shape: &CollisionShape) -> Option<(f32, V3)> {
+
+    if (shape.closest_point(&origin) - origin).magnitude() < 1e-5 {
+        return Some((0.0, -dir));
+    }
+
+    match shape {
+        CollisionShape::Box(b) => ray_box(origin, dir, max_dist, b),
+        CollisionShape::Sphere(_, s) => ray_sphere(origin, dir, max_dist, s.center, s.radius),
+        CollisionShape::Capsule(_, c) => {
+            let mut best = ray_capsule_side(origin, dir, max_dist, c);
+
+            // the round ends
+            for end in &[c.a, c.b] {
+                if let Some((t, normal)) = ray_sphere(origin, dir, max_dist, *end, c.radius) {
+                    if best.map(|(best_t, _)| t < best_t).unwrap_or(true) {
+                        best = Some((t, normal));
+                    }
+                }
+            }
+
+            best
+        }
+    }
+}
+
+
+fn world_candidates(scene: &game::Scene, filter: &QueryFilter, min: V3, max: V3) -> Vec::<(HitTarget, Triangle)> {
+
+    let mut candidates = Vec::new();
+    if !filter.world {
+        return candidates;
+    }
+
+    let aabb = qt::Aabb::new(na::Vector2::new(min.x, min.y), na::Vector2::new(max.x, max.y));
+
+    for i in scene.world_triangles_tree.query_aabb(&aabb) {
+        candidates.push((HitTarget::World(*i), scene.world_triangles[*i]));
+    }
+
+    if let Some(terrain) = &scene.terrain {
+        for (chunk, i, triangle) in terrain.triangles_in(&aabb) {
+            candidates.push((HitTarget::Terrain(chunk, i), triangle));
+        }
+    }
+
+    candidates
+}
+
+
+fn entity_candidates<'a>(scene: &'a game::Scene, filter: &QueryFilter, min: V3, max: V3) -> Vec::<(ProxyKey, &'a [CollisionShape])> {
+    scene.broadphase.query_aabb(&Aabb3 { min, max }).into_iter()
+        .filter(|key| filter.allows(*key))
+        .map(|key| (key, scene.broadphase.hitboxes(key)))
+        .collect()
+}
+
+
+fn shape_cast_triangle(shape: &CollisionShape, dir: V3, max_dist: f32, triangle: &Triangle, target: HitTarget) -> Option<RayHit> {
+
+    let distance = time_of_impact(max_dist, step_size(shape), |t| shape_overlaps_triangle(&moved(shape, dir, t), triangle))?;
+
+    Some(contact(shape, dir, distance, |p| closest_point_on_triangle(p, triangle), target))
+}
+
+
+// Largest distance along the path that is free, 0 when overlapping from the start.
+// Steps a bit less than the shape is thick, so nothing thin is stepped over, then bisects the step that hit
+fn time_of_impact<F: Fn(f32) -> bool>(max_dist: f32, step: f32, overlaps: F) -> Option<f32> {
+
+    if overlaps(0.0) {
+        return Some(0.0);
+    }
+
+    let mut free = 0.0;
+    loop {
+        if free >= max_dist {
+            return None;
+        }
+
+        let t = f32::min(free + step, max_dist);
+        if overlaps(t) {
+            let mut hit = t;
+            for _ in 0..20 {
+                let mid = (free + hit) / 2.0;
+                if overlaps(mid) {
+                    hit = mid;
+                }
+                else {
+                    free = mid;
+                }
+            }
+
+            return Some(free);
+        }
+
+        free = t;
+    }
+}
+
+
+fn step_size(shape: &CollisionShape) -> f32 {
+    f32::max(shape.min_half_size(), 0.01)
+}
+
+
+fn moved(shape: &CollisionShape, dir: V3, distance: f32) -> CollisionShape {
+    shape.make_transformed(dir * distance, na::UnitQuaternion::identity())
+}
+
+
+// Hit for a shape moved distance along dir. The point is the closest point on the target
+// to the shape center, and the normal points from there to the center
+fn contact<F: Fn(&V3) -> V3>(shape: &CollisionShape, dir: V3, distance: f32, closest_point: F, target: HitTarget) -> RayHit {
+
+    let center = shape.center() + dir * distance;
+    let point = closest_point(&center);
+
+    let normal = match normalized(center - point) {
+        Some(n) => n,
+        None => -dir
+    };
+
+    RayHit { point, normal, distance, target }
+}
+
+
+fn keep_closest(best: &mut Option<RayHit>, hit: RayHit) {
+    if best.map(|b| hit.distance < b.distance).unwrap_or(true) {
+        *best = Some(hit);
+    }
+}
+
+
+fn normalized(v: V3) -> Option<V3> {
+    let len = v.magnitude();
+    if len < 1e-6 {
+        return None;
+    }
+
+    Some(v / len)
+}
+
+
+// normal flipped to face against dir
+fn facing(normal: V3, dir: V3) -> V3 {
+    if normal.dot(&dir) > 0.0 {
+        return -normal;
+    }
+
+    normal
+}
+
+
+// Möller–Trumbore, hits from both sides
+fn ray_triangle(origin: V3, dir: V3, max_dist: f32, triangle: &Triangle) -> Option<f32> {
+
+    let e1 = triangle.v1 - triangle.v0;
+    let e2 = triangle.v2 - triangle.v0;
+
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+
+    // parallel with the triangle
+    if det.abs() < 1e-8 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = origin - triangle.v0;
+
+    let u = s.dot(&p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = s.cross(&e1);
+    let v = dir.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(&q) * inv_det;
+    if t < 0.0 || t > max_dist {
+        return None;
+    }
+
+    Some(t)
+}
+
+
+fn ray_sphere(origin: V3, dir: V3, max_dist: f32, center: V3, radius: f32) -> Option<(f32, V3)> {
+
+    let m = origin - center;
+    let b = m.dot(&dir);
+    let c = m.dot(&m) - radius * radius;
+
+    // outside and pointing away
+    if c > 0.0 && b > 0.0 {
+        return None;
+    }
+
+    let disc = b * b - c;
+    if disc < 0.0 {
+        return None;
+    }
+
+    let t = f32::max(-b - disc.sqrt(), 0.0);
+    if t > max_dist {
+        return None;
+    }
+
+    let normal = normalized(origin + dir * t - center).unwrap_or(-dir);
+
+    Some((t, normal))
+}
+
+
+// The cylinder part of a capsule, between the ends
+fn ray_capsule_side(origin: V3, dir: V3, max_dist: f32, capsule: &Capsule) -> Option<(f32, V3)> {
+
+    let axis = normalized(capsule.b - capsule.a)?;
+    let len = (capsule.b - capsule.a).magnitude();
+
+    // only the parts across the axis
+    let m = origin - capsule.a;
+    let m_across = m - axis * m.dot(&axis);
+    let dir_across = dir - axis * dir.dot(&axis);
+
+    let a = dir_across.dot(&dir_across);
+    // along the axis, the ends are hit first
+    if a < 1e-8 {
+        return None;
+    }
+
+    let b = m_across.dot(&dir_across);
+    let c = m_across.dot(&m_across) - capsule.radius * capsule.radius;
+
+    let disc = b * b - a * c;
+    if disc < 0.0 {
+        return None;
+    }
+
+    let t = (-b - disc.sqrt()) / a;
+    if t < 0.0 || t > max_dist {
+        return None;
+    }
+
+    let along = (m + dir * t).dot(&axis);
+    if along < 0.0 || along > len {
+        return None;
+    }
+
+    Some((t, normalized(m_across + dir_across * t)?))
+}
+
+
+// Slabs in the box frame
+fn ray_box(origin: V3, dir: V3, max_dist: f32, collision_box: &CollisionBox) -> Option<(f32, V3)> {
+
+    let (center, axes, half_sizes) = box_frame(collision_box);
+    let local = origin - center;
+
+    let mut t_min = 0.0;
+    let mut t_max = max_dist;
+    let mut normal = -dir;
+
+    for i in 0..3 {
+        let o = local.dot(&axes[i]);
+        let d = dir.dot(&axes[i]);
+
+        if d.abs() < 1e-8 {
+            if o.abs() > half_sizes[i] {
+                return None;
+            }
+            continue;
+        }
+
+        let t1 = (-half_sizes[i] - o) / d;
+        let t2 = (half_sizes[i] - o) / d;
+        let (near, far) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+
+        if near > t_min {
+            t_min = near;
+            normal = axes[i] * -d.signum();
+        }
+        t_max = f32::min(t_max, far);
+
+        if t_min > t_max {
+            return None;
+        }
+    }
+
+    Some((t_min, normal))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn triangle(v0: V3, v1: V3, v2: V3) -> Triangle {
+        let normal = (v1 - v0).cross(&(v2 - v0)).normalize();
+        Triangle { v0, v1, v2, normal, d: -normal.dot(&v0) }
+    }
+
+    // the square -10..10 in x y at z, facing up
+    fn floor(z: f32) -> Vec::<Triangle> {
+        let v = |x, y| V3::new(x, y, z);
+        vec![triangle(v(-10.0, -10.0), v(10.0, -10.0), v(10.0, 10.0)), triangle(v(-10.0, -10.0), v(10.0, 10.0), v(-10.0, 10.0))]
+    }
+
+    // thin wall at x, facing -x, from y -1 to 1 and z 0 to 2
+    fn wall(x: f32) -> Vec::<Triangle> {
+        let v = |y, z| V3::new(x, y, z);
+        vec![triangle(v(-1.0, 0.0), v(-1.0, 2.0), v(1.0, 2.0)), triangle(v(-1.0, 0.0), v(1.0, 2.0), v(1.0, 0.0))]
+    }
+
+    fn assert_close(a: V3, b: V3) {
+        assert!((a - b).magnitude() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+
+    #[test]
+    fn ray_hits_closest_triangle() {
+        let mut triangles = floor(0.0);
+        triangles.extend(floor(1.0));
+
+        // down onto the upper floor
+        let hit = raycast_triangles(V3::new(2.0, 3.0, 5.0), V3::new(0.0, 0.0, -2.0), 100.0, &triangles).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert_close(hit.point, V3::new(2.0, 3.0, 1.0));
+        assert_close(hit.normal, V3::new(0.0, 0.0, 1.0));
+        assert!(hit.target == HitTarget::World(2) || hit.target == HitTarget::World(3));
+
+        // from between the floors up, hits the underside of the upper one, normal facing down
+        let hit = raycast_triangles(V3::new(-2.0, -3.0, 0.5), V3::new(0.0, 0.0, 1.0), 100.0, &triangles).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-5);
+        assert_close(hit.normal, V3::new(0.0, 0.0, -1.0));
+
+        // too short, pointing away, outside and parallel
+        assert!(raycast_triangles(V3::new(2.0, 3.0, 5.0), V3::new(0.0, 0.0, -1.0), 3.9, &triangles).is_none());
+        assert!(raycast_triangles(V3::new(2.0, 3.0, 5.0), V3::new(0.0, 0.0, 1.0), 100.0, &triangles).is_none());
+        assert!(raycast_triangles(V3::new(12.0, 0.0, 5.0), V3::new(0.0, 0.0, -1.0), 100.0, &triangles).is_none());
+        assert!(raycast_triangles(V3::new(0.0, 0.0, 0.5), V3::new(1.0, 0.0, 0.0), 100.0, &triangles).is_none());
+
+        // slanted
+        let hit = raycast_triangles(V3::new(0.0, 0.0, 3.0), V3::new(1.0, 0.0, -1.0), 100.0, &triangles).unwrap();
+        assert!((hit.distance - 2.0 * 2.0f32.sqrt()).abs() < 1e-4);
+        assert_close(hit.point, V3::new(2.0, 0.0, 1.0));
+    }
+
+
+    #[test]
+    fn ray_hits_shapes() {
+        let b = CollisionShape::Box(CollisionBox::new(V3::new(5.0, 0.0, 0.0), na::Rotation3::identity(), na::Matrix3::identity()));
+        let (t, normal) = ray_shape(V3::new(0.0, 0.0, 0.0), V3::new(1.0, 0.0, 0.0), 100.0, &b).unwrap();
+        assert!((t - 4.5).abs() < 1e-5);
+        assert_close(normal, V3::new(-1.0, 0.0, 0.0));
+        assert!(ray_shape(V3::new(0.0, 0.6, 0.0), V3::new(1.0, 0.0, 0.0), 100.0, &b).is_none());
+
+        let s = CollisionShape::Sphere("".to_string(), Sphere { center: V3::new(0.0, 0.0, 5.0), radius: 1.0 });
+        let (t, normal) = ray_shape(V3::new(0.0, 0.0, 0.0), V3::new(0.0, 0.0, 1.0), 100.0, &s).unwrap();
+        assert!((t - 4.0).abs() < 1e-5);
+        assert_close(normal, V3::new(0.0, 0.0, -1.0));
+
+        // standing capsule, hit on the side and on the top
+        let c = CollisionShape::Capsule("".to_string(), Capsule { a: V3::new(0.0, 0.0, 0.5), b: V3::new(0.0, 0.0, 1.5), radius: 0.5 });
+        let (t, normal) = ray_shape(V3::new(-3.0, 0.0, 1.0), V3::new(1.0, 0.0, 0.0), 100.0, &c).unwrap();
+        assert!((t - 2.5).abs() < 1e-4);
+        assert_close(normal, V3::new(-1.0, 0.0, 0.0));
+
+        let (t, normal) = ray_shape(V3::new(0.0, 0.0, 5.0), V3::new(0.0, 0.0, -1.0), 100.0, &c).unwrap();
+        assert!((t - 3.0).abs() < 1e-4);
+        assert_close(normal, V3::new(0.0, 0.0, 1.0));
+
+        // starting inside
+        let (t, _) = ray_shape(V3::new(0.0, 0.0, 1.0), V3::new(1.0, 0.0, 0.0), 100.0, &c).unwrap();
+        assert_eq!(t, 0.0);
+    }
+
+
+    #[test]
+    fn sphere_cast_onto_triangles() {
+        let sphere = CollisionShape::Sphere("".to_string(), Sphere { center: V3::new(1.0, 2.0, 3.0), radius: 0.5 });
+
+        let hit = shape_cast_triangles(&sphere, V3::new(0.0, 0.0, -1.0), 10.0, &floor(0.0)).unwrap();
+        assert!((hit.distance - 2.5).abs() < 1e-3, "{}", hit.distance);
+        assert_close(hit.point, V3::new(1.0, 2.0, 0.0));
+        assert_close(hit.normal, V3::new(0.0, 0.0, 1.0));
+
+        // along the floor without touching it
+        assert!(shape_cast_triangles(&sphere, V3::new(1.0, 0.0, 0.0), 10.0, &floor(0.0)).is_none());
+
+        // the edge of the wall top is hit by the bottom of the sphere, the normal points from the edge to the center
+        let sphere = CollisionShape::Sphere("".to_string(), Sphere { center: V3::new(0.0, 0.0, 2.3), radius: 0.5 });
+        let hit = shape_cast_triangles(&sphere, V3::new(1.0, 0.0, 0.0), 10.0, &wall(3.0)).unwrap();
+        assert!((hit.distance - 2.6).abs() < 1e-3, "{}", hit.distance);
+        assert_close(hit.point, V3::new(3.0, 0.0, 2.0));
+        assert!(hit.normal.x < 0.0 && hit.normal.z > 0.0);
+    }
+
+
+    #[test]
+    fn box_cast_does_not_pass_thin_walls() {
+        // 0.2 thick box, moving 10 at once through a wall without thickness
+        let b = CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 1.0), na::Rotation3::identity(), na::Matrix3::from_diagonal_element(0.2)));
+
+        let mut triangles = floor(0.0);
+        triangles.extend(wall(5.0));
+
+        let hit = shape_cast_triangles(&b, V3::new(1.0, 0.0, 0.0), 10.0, &triangles).unwrap();
+        assert!((hit.distance - 4.9).abs() < 1e-3, "{}", hit.distance);
+        assert!(hit.target == HitTarget::World(2) || hit.target == HitTarget::World(3));
+        assert_close(hit.normal, V3::new(-1.0, 0.0, 0.0));
+
+        // a box rotated 45 degrees around z touches with its corner first
+        let rotated = CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 1.0), na::Rotation3::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_4), na::Matrix3::from_diagonal_element(0.2)));
+        let hit = shape_cast_triangles(&rotated, V3::new(1.0, 0.0, 0.0), 10.0, &triangles).unwrap();
+        assert!((hit.distance - (5.0 - 0.1 * 2.0f32.sqrt())).abs() < 1e-3, "{}", hit.distance);
+
+        // dropped onto the floor
+        let hit = shape_cast_triangles(&b, V3::new(0.0, 0.0, -1.0), 10.0, &triangles).unwrap();
+        assert!((hit.distance - 0.9).abs() < 1e-3, "{}", hit.distance);
+        assert_close(hit.normal, V3::new(0.0, 0.0, 1.0));
+
+        // already touching
+        let low = CollisionShape::Box(CollisionBox::new(V3::new(0.0, 0.0, 0.05), na::Rotation3::identity(), na::Matrix3::from_diagonal_element(0.2)));
+        assert_eq!(shape_cast_triangles(&low, V3::new(1.0, 0.0, 0.0), 10.0, &triangles).unwrap().distance, 0.0);
+    }
+}
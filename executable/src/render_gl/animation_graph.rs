@@ -0,0 +1,587 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::render_gl::{AnimationPlayer, AnimationLayer, JointMask, LayerMode, Skeleton, BlendSpace};
+use crate::resources::{self, Resources};
+
+
+// Which clip plays when, loaded from a .ron file in assets/animation_graphs/.
+// Each tick the first transition, in file order, from the current state whose conditions hold is taken
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AnimationGraph {
+    pub start: String,
+    pub states: BTreeMap<String, GraphState>,
+    pub transitions: Vec<GraphTransition>,
+    #[serde(default)]
+    pub blend_spaces: BTreeMap<String, BlendSpace>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GraphState {
+    pub clip: ClipBinding,
+    // played over the clip while in the state, fx an attack on the upper body while the legs walk
+    #[serde(default)]
+    pub layer: Option<StateLayer>,
+}
+
+
+// An override layer named after its state, see AnimationLayer
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StateLayer {
+    // a Clip or a Parameter, blend spaces can not be played as layers
+    pub clip: ClipBinding,
+    // the joints and the joints below them, all joints when empty
+    #[serde(default)]
+    pub joints: Vec<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum ClipBinding {
+    // a clip in the animation set
+    Clip(String),
+    // the clip named by a parameter, fx the attack clip from the weapon stats
+    Parameter(String),
+    // a blend space in the graph, its parameters are read every update
+    BlendSpace(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GraphTransition {
+    pub from: TransitionSource,
+    pub to: String,
+    // seconds to blend from the current pose, 0 cuts to the first frame
+    #[serde(default)]
+    pub blend: f32,
+    // how far into the current clip, 0 to 1, before the transition can be taken
+    #[serde(default)]
+    pub exit_time: Option<f32>,
+    // all have to hold
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum TransitionSource {
+    // any state but the target, a state only goes to itself from State
+    Any,
+    State(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum Condition {
+    // set this tick, fx attack_requested
+    Trigger(String),
+    // bool parameters
+    True(String),
+    False(String),
+    // number parameters
+    Above(String, f32),
+    Below(String, f32),
+    Equals(String, f32),
+}
+
+
+// Set by gameplay each tick. Unset numbers and bools are 0 and false
+#[derive(Debug, Clone, Default)]
+pub struct AnimationParameters {
+    numbers: HashMap<String, f32>,
+    triggers: HashSet<String>,
+    clips: HashMap<String, String>,
+}
+
+
+// The state an entity is in, in its graph
+#[derive(Debug, Clone)]
+pub struct AnimationStateMachine {
+    graph: AnimationGraph,
+    current: String,
+    started: bool,
+}
+
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Failed to load resource {}", name)]
+    ResourceLoad { name: String, inner: resources::Error },
+    #[fail(display = "Can not parse the animation graph {}: {}", name, inner)]
+    ParseFailed { name: String, inner: ron::de::Error },
+    #[fail(display = "Animation graph {} has no state {}", name, state)]
+    UnknownState { name: String, state: String },
+    #[fail(display = "Animation graph {} has no blend space {}", name, blend_space)]
+    UnknownBlendSpace { name: String, blend_space: String },
+}
+
+
+impl AnimationGraph {
+
+    pub fn load(res: &Resources, name: &str) -> Result<AnimationGraph, Error> {
+
+        let data = res.load_string(name)
+            .map_err(|e| Error::ResourceLoad {
+                name: name.into(),
+                inner: e
+            })?;
+
+        let graph: AnimationGraph = ron::de::from_str(&data)
+            .map_err(|e| Error::ParseFailed {
+                name: name.into(),
+                inner: e
+            })?;
+
+        if let Some(state) = graph.unknown_state() {
+            return Err(Error::UnknownState { name: name.into(), state });
+        }
+
+        match graph.unknown_blend_space() {
+            Some(blend_space) => Err(Error::UnknownBlendSpace { name: name.into(), blend_space }),
+            None => Ok(graph),
+        }
+    }
+
+
+    // first state used by the start or a transition that is not in states
+    fn unknown_state(&self) -> Option<String> {
+
+        let mut used = vec![&self.start];
+
+        for transition in &self.transitions {
+            used.push(&transition.to);
+            if let TransitionSource::State(from) = &transition.from {
+                used.push(from);
+            }
+        }
+
+        used.into_iter()
+            .find(|state| !self.states.contains_key(*state))
+            .cloned()
+    }
+
+
+    fn unknown_blend_space(&self) -> Option<String> {
+        self.states.values()
+            .filter_map(|state| match &state.clip {
+                ClipBinding::BlendSpace(name) if !self.blend_spaces.contains_key(name) => Some(name.clone()),
+                _ => None
+            })
+            .next()
+    }
+}
+
+
+impl GraphTransition {
+
+    fn can_take(&self, current: &str, progress: f32, parameters: &AnimationParameters) -> bool {
+
+        let from = match &self.from {
+            TransitionSource::Any => self.to != current,
+            TransitionSource::State(state) => state == current,
+        };
+
+        let exit_time = self.exit_time.map(|exit_time| progress >= exit_time).unwrap_or(true);
+
+        from && exit_time && self.conditions.iter().all(|condition| condition.holds(parameters))
+    }
+}
+
+
+impl Condition {
+
+    fn holds(&self, parameters: &AnimationParameters) -> bool {
+        match self {
+            Condition::Trigger(name) => parameters.triggered(name),
+            Condition::True(name) => parameters.number(name) != 0.0,
+            Condition::False(name) => parameters.number(name) == 0.0,
+            Condition::Above(name, value) => parameters.number(name) > *value,
+            Condition::Below(name, value) => parameters.number(name) < *value,
+            Condition::Equals(name, value) => parameters.number(name) == *value,
+        }
+    }
+}
+
+
+impl AnimationParameters {
+
+    pub fn new() -> AnimationParameters {
+        AnimationParameters::default()
+    }
+
+
+    pub fn set_number(&mut self, name: &str, value: f32) {
+        self.numbers.insert(name.to_string(), value);
+    }
+
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set_number(name, if value { 1.0 } else { 0.0 });
+    }
+
+
+    pub fn number(&self, name: &str) -> f32 {
+        self.numbers.get(name).copied().unwrap_or(0.0)
+    }
+
+
+    // only set until clear_triggers, so it is seen by one update
+    pub fn set_trigger(&mut self, name: &str) {
+        self.triggers.insert(name.to_string());
+    }
+
+
+    pub fn triggered(&self, name: &str) -> bool {
+        self.triggers.contains(name)
+    }
+
+
+    pub fn clear_triggers(&mut self) {
+        self.triggers.clear();
+    }
+
+
+    pub fn set_clip(&mut self, name: &str, clip: &str) {
+        self.clips.insert(name.to_string(), clip.to_string());
+    }
+
+
+    pub fn clip(&self, name: &str) -> Option<&str> {
+        self.clips.get(name).map(|clip| clip.as_str())
+    }
+}
+
+
+impl AnimationStateMachine {
+
+    pub fn new(graph: AnimationGraph) -> AnimationStateMachine {
+        AnimationStateMachine {
+            current: graph.start.clone(),
+            graph,
+            started: false,
+        }
+    }
+
+
+    pub fn current_state(&self) -> &str {
+        &self.current
+    }
+
+
+    // name of the layer the current state plays, if it has one
+    pub fn state_layer(&self) -> Option<&str> {
+        self.graph.states[&self.current].layer.as_ref().map(|_| self.current.as_str())
+    }
+
+
+    // Take at most one transition and start its clip on the player. The first update starts the start state
+    pub fn update(&mut self, parameters: &AnimationParameters, player: &mut AnimationPlayer, skeleton: &Skeleton) {
+
+        if !self.started {
+            let start = self.current.clone();
+            self.enter(&start, 0.0, parameters, player, skeleton);
+            self.started = true;
+            self.update_blend_space(parameters, player);
+            return;
+        }
+
+        let progress = player.clip_progress();
+
+        let transition = self.graph.transitions.iter()
+            .find(|transition| transition.can_take(&self.current, progress, parameters))
+            .cloned();
+
+        if let Some(transition) = transition {
+            self.enter(&transition.to, transition.blend, parameters, player, skeleton);
+        }
+
+        self.update_blend_space(parameters, player);
+    }
+
+
+    fn update_blend_space(&self, parameters: &AnimationParameters, player: &mut AnimationPlayer) {
+
+        if let ClipBinding::BlendSpace(name) = &self.graph.states[&self.current].clip {
+            let (position, speed) = blend_parameters(&self.graph.blend_spaces[name], parameters);
+            player.set_blend_parameters(position.x, position.y, speed);
+        }
+    }
+
+
+    fn enter(&mut self, state: &str, blend: f32, parameters: &AnimationParameters, player: &mut AnimationPlayer, skeleton: &Skeleton) {
+
+        if self.graph.states[&self.current].layer.is_some() {
+            player.stop_layer(&self.current);
+        }
+
+        // a blend space keeps playing into the next state using it, so the legs do not restart their step
+        let keep_playing = match (&self.graph.states[&self.current].clip, &self.graph.states[state].clip) {
+            (ClipBinding::BlendSpace(from), ClipBinding::BlendSpace(to)) => self.started && from == to,
+            _ => false
+        };
+
+        self.current = state.to_string();
+
+        if let Some(layer) = &self.graph.states[state].layer {
+            match binding_clip(&layer.clip, parameters) {
+                Some(clip) => {
+                    let names = layer.joints.iter().map(|joint| joint.as_str()).collect::<Vec::<&str>>();
+                    let mask = if names.is_empty() { JointMask::all(skeleton) } else { JointMask::from_joints(skeleton, &names) };
+                    player.play_layer(AnimationLayer::new(state, clip, LayerMode::Override, mask));
+                },
+                None => println!("Animation state {} has no layer clip", state),
+            };
+        }
+
+        if keep_playing {
+            return;
+        }
+
+        if let ClipBinding::BlendSpace(name) = &self.graph.states[state].clip {
+            let space = &self.graph.blend_spaces[name];
+            let (position, speed) = blend_parameters(space, parameters);
+            player.play_blend_space(name, space, position, speed, blend, skeleton);
+            return;
+        }
+
+        match binding_clip(&self.graph.states[state].clip, parameters) {
+            Some(clip) => player.play(clip, blend, skeleton),
+            None => println!("Animation state {} has no clip", state),
+        };
+    }
+}
+
+
+// The clip a Clip or Parameter binding names, None for blend spaces
+fn binding_clip<'a>(binding: &'a ClipBinding, parameters: &'a AnimationParameters) -> Option<&'a str> {
+    match binding {
+        ClipBinding::Clip(clip) => Some(clip.as_str()),
+        ClipBinding::Parameter(name) => parameters.clip(name),
+        ClipBinding::BlendSpace(_) => None,
+    }
+}
+
+
+// Where in the space the parameters are, and the speed when the space syncs to it
+fn blend_parameters(space: &BlendSpace, parameters: &AnimationParameters) -> (na::Vector2::<f32>, Option<f32>) {
+
+    let x = parameters.number(&space.x);
+    let y = space.y.as_ref().map(|y| parameters.number(y)).unwrap_or(0.0);
+    let speed = space.sync_speed.as_ref().map(|speed| parameters.number(speed));
+
+    (na::Vector2::new(x, y), speed)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::render_gl::{AnimationSet, Joint, KeyframeAnimation, KeyFrame, Transformation};
+
+    fn character_graph() -> AnimationStateMachine {
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../assets");
+        let graph = AnimationGraph::load(&Resources::from_path(&assets), "animation_graphs/character.ron").unwrap();
+        AnimationStateMachine::new(graph)
+    }
+
+
+    fn player() -> AnimationPlayer {
+        let mut animations = AnimationSet::new();
+        for (name, frames, cyclic) in &[("idle", 10, true), ("walk", 20, true), ("roll", 30, false), ("attack", 40, false), ("attack_follow", 40, false)] {
+            animations.insert(name, KeyframeAnimation::new(*frames as f32 / 40.0, vec![KeyFrame { joints: Vec::new() }; *frames], *cyclic, None));
+        }
+
+        AnimationPlayer::new("idle", animations)
+    }
+
+
+    fn skeleton() -> Skeleton {
+        Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None }
+    }
+
+
+    #[test]
+    fn locomotion_blends_by_speed() {
+        let mut skeleton = skeleton();
+        let mut player = player();
+        let mut graph = character_graph();
+        let mut params = AnimationParameters::new();
+
+        graph.update(&params, &mut player, &skeleton);
+        assert_eq!(graph.current_state(), "locomotion");
+        assert_eq!(player.current_animation_name(), "locomotion");
+
+        // the player model has no run, so walk plays faster past its speed
+        params.set_number("speed", 7.5);
+        graph.update(&params, &mut player, &skeleton);
+        player.update_skeleton(&mut skeleton, 0.25);
+
+        assert!((player.clip_progress() - 0.75).abs() < 0.001, "{}", player.clip_progress());
+    }
+
+
+    #[test]
+    fn attack_cuts_in_and_restarts_for_combo() {
+        let skeleton = skeleton();
+        let mut player = player();
+        let mut graph = character_graph();
+        let mut params = AnimationParameters::new();
+
+        graph.update(&params, &mut player, &skeleton);
+
+        params.set_trigger("attack_requested");
+        params.set_bool("attacking", true);
+        params.set_clip("attack_clip", "attack");
+        graph.update(&params, &mut player, &skeleton);
+        params.clear_triggers();
+
+        assert_eq!(graph.current_state(), "attack");
+        assert_eq!(player.current_animation_name(), "attack");
+
+        // nothing changes without a new trigger
+        graph.update(&params, &mut player, &skeleton);
+        assert_eq!(player.current_animation_name(), "attack");
+
+        params.set_trigger("attack_requested");
+        params.set_clip("attack_clip", "attack_follow");
+        graph.update(&params, &mut player, &skeleton);
+        params.clear_triggers();
+
+        assert_eq!(graph.current_state(), "attack");
+        assert_eq!(player.current_animation_name(), "attack_follow");
+
+        params.set_bool("attacking", false);
+        graph.update(&params, &mut player, &skeleton);
+        assert_eq!(graph.current_state(), "locomotion");
+        assert_eq!(player.next_animation_name(), "locomotion");
+    }
+
+
+    #[test]
+    fn moving_attack_plays_on_upper_body() {
+        let mut skeleton = skeleton();
+        let mut player = player();
+        let mut graph = character_graph();
+        let mut params = AnimationParameters::new();
+
+        params.set_number("speed", 5.0);
+        graph.update(&params, &mut player, &skeleton);
+        player.update_skeleton(&mut skeleton, 0.1);
+        let progress = player.clip_progress();
+
+        params.set_trigger("attack_requested");
+        params.set_bool("attacking", true);
+        params.set_bool("moving_attack", true);
+        params.set_clip("attack_clip", "attack");
+        graph.update(&params, &mut player, &skeleton);
+        params.clear_triggers();
+
+        // the legs keep their step
+        assert_eq!(graph.current_state(), "moving_attack");
+        assert_eq!(graph.state_layer(), Some("moving_attack"));
+        assert_eq!(player.current_animation_name(), "locomotion");
+        assert_eq!(player.clip_progress(), progress);
+        assert_eq!(player.layer("moving_attack").unwrap().clip, "attack");
+
+        params.set_bool("attacking", false);
+        params.set_bool("moving_attack", false);
+        graph.update(&params, &mut player, &skeleton);
+
+        assert_eq!(graph.current_state(), "locomotion");
+        assert!(player.layer("moving_attack").is_none());
+    }
+
+
+    #[test]
+    fn blends_back_into_locomotion_at_current_speed() {
+        // one joint, turned by a different angle in each clip
+        let mut skeleton = Skeleton { name: "test".to_string(), joints: vec![Joint::empty()], legs: None };
+        let pose = |angle: f32| KeyFrame {
+            joints: vec![Transformation { translation: na::Vector3::new(0.0, 0.0, 0.0), rotation: na::UnitQuaternion::from_euler_angles(0.0, 0.0, angle) }]
+        };
+
+        let mut animations = AnimationSet::new();
+        for (name, angle, cyclic) in &[("idle", 0.0, true), ("walk", 1.0, true), ("attack", 2.0, false)] {
+            animations.insert(name, KeyframeAnimation::new(1.0, vec![pose(*angle); 40], *cyclic, None));
+        }
+
+        let mut player = AnimationPlayer::new("idle", animations);
+        let mut graph = character_graph();
+        let mut params = AnimationParameters::new();
+
+        graph.update(&params, &mut player, &skeleton);
+
+        params.set_trigger("attack_requested");
+        params.set_bool("attacking", true);
+        params.set_clip("attack_clip", "attack");
+        graph.update(&params, &mut player, &skeleton);
+        params.clear_triggers();
+
+        // done attacking while moving at walk speed, the transition goes to the walk and not to idle
+        params.set_bool("attacking", false);
+        params.set_number("speed", 5.0);
+        graph.update(&params, &mut player, &skeleton);
+
+        assert_eq!(player.next_animation_name(), "locomotion");
+
+        // near the end of the 0.2 second blend from the attack at 2
+        for _ in 0..11 {
+            player.update_skeleton(&mut skeleton, 1.0 / 60.0);
+            graph.update(&params, &mut player, &skeleton);
+        }
+
+        let angle = skeleton.joints[0].rotation.angle();
+        assert!(angle > 0.95 && angle < 1.2, "blending into angle {}", angle);
+    }
+
+
+    #[test]
+    fn exit_time_waits_for_clip() {
+        let mut skeleton = skeleton();
+        let mut player = player();
+        let params = AnimationParameters::new();
+
+        let mut graph = AnimationStateMachine::new(AnimationGraph {
+            start: "roll".to_string(),
+            states: vec![
+                ("roll".to_string(), GraphState { clip: ClipBinding::Clip("roll".to_string()), layer: None }),
+                ("idle".to_string(), GraphState { clip: ClipBinding::Clip("idle".to_string()), layer: None }),
+            ].into_iter().collect(),
+            transitions: vec![GraphTransition {
+                from: TransitionSource::State("roll".to_string()),
+                to: "idle".to_string(),
+                blend: 0.0,
+                exit_time: Some(0.5),
+                conditions: Vec::new(),
+            }],
+            blend_spaces: BTreeMap::new(),
+        });
+
+        graph.update(&params, &mut player, &skeleton);
+
+        // the roll is 0.75 seconds, so halfway is after 23 ticks
+        for _ in 0..20 {
+            player.update_skeleton(&mut skeleton, 1.0 / 60.0);
+            graph.update(&params, &mut player, &skeleton);
+        }
+        assert_eq!(graph.current_state(), "roll");
+
+        for _ in 0..5 {
+            player.update_skeleton(&mut skeleton, 1.0 / 60.0);
+            graph.update(&params, &mut player, &skeleton);
+        }
+        assert_eq!(graph.current_state(), "idle");
+    }
+
+
+    #[test]
+    fn unknown_state_fails_to_load() {
+        let graph: AnimationGraph = ron::de::from_str(r#"(start: "idle", states: {}, transitions: [])"#).unwrap();
+        assert_eq!(graph.unknown_state(), Some("idle".to_string()));
+
+        let graph: AnimationGraph = ron::de::from_str(r#"(start: "walk", states: {"walk": (clip: BlendSpace("walk"))}, transitions: [])"#).unwrap();
+        assert_eq!(graph.unknown_blend_space(), Some("walk".to_string()));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::render_gl::{AnimationSet, AnimationEvent, KeyFrame, Skeleton};
+
+
+// How much of a layer each joint gets, from 0 to 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct JointMask {
+    weights: Vec<f32>,
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LayerMode {
+    // replaces the pose below by the weight, fx an attack on the upper body over walking
+    Override,
+    // adds how far the clip has moved from its first frame, fx a hit reaction on top of anything
+    Additive,
+}
+
+
+// A clip played over the base animation of an AnimationPlayer. Layers are applied in the order they were started
+#[derive(Debug, Clone)]
+pub struct AnimationLayer {
+    pub name: String,
+    pub clip: String,
+    pub mode: LayerMode,
+    pub mask: JointMask,
+    // set from gameplay, 0 turns the layer off without stopping it
+    pub weight: f32,
+    elapsed: f32,
+    finished: bool,
+}
+
+
+impl JointMask {
+
+    pub fn all(skeleton: &Skeleton) -> JointMask {
+        JointMask {
+            weights: vec![1.0; skeleton.joints.len()]
+        }
+    }
+
+
+    // The named joints and every joint below them, fx spine for the upper body. Unknown names are skipped
+    pub fn from_joints(skeleton: &Skeleton, names: &[&str]) -> JointMask {
+
+        let weights = (0..skeleton.joints.len())
+            .map(|i| if below_any(skeleton, i, names) { 1.0 } else { 0.0 })
+            .collect();
+
+        JointMask {
+            weights
+        }
+    }
+
+
+    // every joint the mask leaves out, fx the lower body from an upper body mask
+    pub fn inverse(&self) -> JointMask {
+        JointMask {
+            weights: self.weights.iter().map(|weight| 1.0 - weight).collect()
+        }
+    }
+
+
+    pub fn weight(&self, joint: usize) -> f32 {
+        self.weights.get(joint).copied().unwrap_or(0.0)
+    }
+}
+
+
+fn below_any(skeleton: &Skeleton, joint: usize, names: &[&str]) -> bool {
+
+    let mut current = joint;
+
+    // the root is its own parent
+    loop {
+        if names.contains(&skeleton.joints[current].name.as_str()) {
+            return true;
+        }
+
+        let parent = skeleton.joints[current].parent_index;
+        if parent == current || current == 0 {
+            return false;
+        }
+
+        current = parent;
+    }
+}
+
+
+impl AnimationLayer {
+
+    pub fn new(name: &str, clip: &str, mode: LayerMode, mask: JointMask) -> AnimationLayer {
+        AnimationLayer {
+            name: name.to_string(),
+            clip: clip.to_string(),
+            mode,
+            mask,
+            weight: 1.0,
+            elapsed: 0.0,
+            finished: false,
+        }
+    }
+
+
+    // a clip that does not loop has played to the end, and the layer is removed
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+
+    // Returns the events passed, when the layer has weight
+    pub fn advance(&mut self, delta: f32, animations: &AnimationSet) -> Vec<AnimationEvent> {
+
+        let clip = match animations.get(&self.clip) {
+            Some(clip) => clip,
+            None => {
+                self.finished = true;
+                return Vec::new();
+            }
+        };
+
+        let mut events = clip.events_between(self.elapsed, self.elapsed + delta);
+
+        self.elapsed += delta;
+
+        if self.elapsed > clip.duration {
+            if clip.cyclic {
+                self.elapsed -= clip.duration;
+                events.extend(clip.events_between(0.0, self.elapsed));
+            }
+            else {
+                self.elapsed = clip.duration;
+                self.finished = true;
+            }
+        }
+
+        if self.weight > 0.0 {
+            events
+        }
+        else {
+            Vec::new()
+        }
+    }
+
+
+    pub fn apply(&self, pose: &mut KeyFrame, animations: &AnimationSet, skeleton: &Skeleton) {
+
+        let clip = match animations.get(&self.clip) {
+            Some(clip) => clip,
+            None => return,
+        };
+
+        let layer_pose = clip.keyframe_at(skeleton, self.elapsed);
+
+        for (i, joint) in pose.joints.iter_mut().enumerate().take(layer_pose.joints.len()) {
+
+            let weight = self.weight * self.mask.weight(i);
+
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let layer_joint = &layer_pose.joints[i];
+
+            match self.mode {
+                LayerMode::Override => {
+                    joint.rotation = joint.rotation.slerp(&layer_joint.rotation, weight);
+                    joint.translation = joint.translation * (1.0 - weight) + layer_joint.translation * weight;
+                },
+                LayerMode::Additive => {
+                    let reference = match clip.key_frames.first().and_then(|frame| frame.joints.get(i)) {
+                        Some(reference) => reference,
+                        None => continue,
+                    };
+
+                    let difference = reference.rotation.inverse() * layer_joint.rotation;
+                    joint.rotation *= na::UnitQuaternion::identity().slerp(&difference, weight);
+                    joint.translation += (layer_joint.translation - reference.translation) * weight;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::render_gl::{Joint, KeyframeAnimation, Transformation};
+
+    fn skeleton() -> Skeleton {
+        // hip -> spine -> arm, hip -> leg
+        let joints = [("hip", 0), ("spine", 0), ("arm", 1), ("leg", 0)].iter()
+            .map(|(name, parent_index)| Joint {
+                name: name.to_string(),
+                parent_index: *parent_index,
+                ..Joint::empty()
+            })
+            .collect();
+
+        Skeleton { name: "test".to_string(), joints, legs: None }
+    }
+
+
+    fn pose(angle: f32, x: f32) -> KeyFrame {
+        let joint = Transformation {
+            translation: na::Vector3::new(x, 0.0, 0.0),
+            rotation: na::UnitQuaternion::from_euler_angles(0.0, 0.0, angle),
+        };
+
+        KeyFrame { joints: vec![joint; 4] }
+    }
+
+
+    // still at the given pose, with a first frame at the rest pose
+    fn animations() -> AnimationSet {
+        let mut animations = AnimationSet::new();
+        animations.insert("swing", KeyframeAnimation::new(1.0, vec![pose(1.0, 0.0); 40], false, None));
+        animations.insert("flinch", KeyframeAnimation::new(1.0, vec![pose(0.0, 0.0), pose(0.5, 1.0), pose(0.5, 1.0)], false, None));
+        animations
+    }
+
+
+    #[test]
+    fn mask_takes_joints_below() {
+        let skeleton = skeleton();
+
+        let upper = JointMask::from_joints(&skeleton, &["spine"]);
+        assert_eq!((0..4).map(|i| upper.weight(i)).collect::<Vec<_>>(), vec![0.0, 1.0, 1.0, 0.0]);
+        assert_eq!((0..4).map(|i| upper.inverse().weight(i)).collect::<Vec<_>>(), vec![1.0, 0.0, 0.0, 1.0]);
+    }
+
+
+    #[test]
+    fn override_only_masked_joints() {
+        let skeleton = skeleton();
+        let animations = animations();
+
+        let mut layer = AnimationLayer::new("upper", "swing", LayerMode::Override, JointMask::from_joints(&skeleton, &["spine"]));
+        layer.weight = 0.5;
+
+        let mut base = pose(0.0, 2.0);
+        layer.apply(&mut base, &animations, &skeleton);
+
+        assert!(base.joints[0].rotation.angle() < 0.001);
+        assert!((base.joints[2].rotation.angle() - 0.5).abs() < 0.001);
+        assert!((base.joints[2].translation.x - 1.0).abs() < 0.001);
+        assert!((base.joints[3].translation.x - 2.0).abs() < 0.001);
+    }
+
+
+    #[test]
+    fn additive_adds_difference() {
+        let skeleton = skeleton();
+        let animations = animations();
+
+        let mut layer = AnimationLayer::new("hit", "flinch", LayerMode::Additive, JointMask::all(&skeleton));
+        layer.advance(0.9, &animations);
+
+        let mut base = pose(0.25, 2.0);
+        layer.apply(&mut base, &animations, &skeleton);
+
+        assert!((base.joints[1].rotation.angle() - 0.75).abs() < 0.001);
+        assert!((base.joints[1].translation.x - 3.0).abs() < 0.001);
+
+        // one shot layers finish
+        assert!(!layer.finished());
+        layer.advance(0.2, &animations);
+        assert!(layer.finished());
+    }
+}
@@ -1,16 +1,14 @@
-use crate::render_gl::{KeyframeAnimation, KeyFrame, Skeleton, PlayerAnimations};
+use crate::render_gl::{KeyframeAnimation, KeyFrame, Skeleton, AnimationSet, AnimationEvent, BlendSpace, BlendSpacePlayback, AnimationLayer};
 use crate::math::*;
 
 
 #[derive(Debug, Clone)]
 pub enum Animation {
-    TPose,
-    Idle,
-    Walk,
-    Attack,
-    Roll,
-    AttackFollow,
-    Transition(KeyframeAnimation)
+    // name in the animation set
+    Clip(String),
+    Transition(KeyframeAnimation),
+    // name in the animation graph
+    Blend(String, BlendSpacePlayback),
 }
 
 #[derive(Clone)]
@@ -19,34 +17,78 @@ pub struct AnimationPlayer {
     next_animation: Option<Animation>,
     elapsed: f32,
     pub has_repeated: bool,
-    pub animations: PlayerAnimations,
+    pub animations: AnimationSet,
     root_motion_prev: na::Vector3::<f32>,
+    // played over the current animation, in order
+    layers: Vec<AnimationLayer>,
+    // events the layers passed in the last update, with the layer name. Kept apart from the base events,
+    // so a layer, fx an upper body swing, does not switch on the gameplay flags of the base animation
+    layer_events: Vec<(String, AnimationEvent)>,
 }
 
 impl AnimationPlayer {
 
-    pub fn new(current_animation: Animation, animations: PlayerAnimations) -> Self {
+    pub fn new(current_animation: &str, animations: AnimationSet) -> Self {
         AnimationPlayer {
-            current_animation,
+            current_animation: Animation::Clip(current_animation.to_string()),
             elapsed: 0.0,
             animations,
             has_repeated: false,
             next_animation: None,
             root_motion_prev: na::Vector3::new(0.0, 0.0, 0.0),
+            layers: Vec::new(),
+            layer_events: Vec::new(),
         }
     }
 
-    pub fn set_current(&mut self, animation: Animation, skeleton: &Skeleton) {
-        let should_transition = match animation {
-            Animation::Attack => false,
-            Animation::Roll => false,
-            Animation::AttackFollow => false,
-            _ => true,
+    // Blend from the current pose into the clip over blend seconds, or start it right away when blend is 0
+    pub fn play(&mut self, animation: &str, blend: f32, skeleton: &Skeleton) {
+
+        if !self.animations.contains(animation) {
+            println!("No animation named {}, has {:?}", animation, self.animations.names());
+            return;
+        }
+
+        self.start(Animation::Clip(animation.to_string()), blend, skeleton);
+    }
+
+
+    // Like play, for a blend space starting at position, see set_blend_parameters. The transition blends into the pose
+    // there, so it has to be known before. Its samples without a clip in the animations are left out
+    pub fn play_blend_space(&mut self, name: &str, space: &BlendSpace, position: na::Vector2::<f32>, speed: Option<f32>, blend: f32, skeleton: &Skeleton) {
+
+        let space = space.playable(&self.animations);
+
+        if space.samples.is_empty() {
+            println!("Blend space {} has no animations, has {:?}", name, self.animations.names());
+            return;
+        }
+
+        let mut playback = BlendSpacePlayback::new(space);
+        playback.set_parameters(position.x, position.y, speed);
+
+        self.start(Animation::Blend(name.to_string(), playback), blend, skeleton);
+    }
+
+
+    // Position in the blend space playing, or blending into. Speed is used when the space syncs to it
+    pub fn set_blend_parameters(&mut self, x: f32, y: f32, speed: Option<f32>) {
+
+        let animation = match self.next_animation {
+            Some(ref mut next) => next,
+            None => &mut self.current_animation
         };
 
+        if let Animation::Blend(_, playback) = animation {
+            playback.set_parameters(x, y, speed);
+        }
+    }
+
+
+    fn start(&mut self, animation: Animation, blend: f32, skeleton: &Skeleton) {
 
-        if should_transition {
-            self.transition_into_next(animation, skeleton);
+        if blend > 0.0 {
+            self.transition_into_next(animation, blend, skeleton);
         }
         else {
             self.current_animation = animation;
@@ -58,6 +100,16 @@ impl AnimationPlayer {
     }
 
 
+    // How far into the clip playing, 0 at the start and 1 at the end. 0 while blending into it
+    pub fn clip_progress(&self) -> f32 {
+        match self.current_animation {
+            Animation::Transition(_) => 0.0,
+            Animation::Blend(_, ref playback) => playback.phase(),
+            Animation::Clip(_) => clamp01(self.elapsed, 0.0, self.current_animation().duration),
+        }
+    }
+
+
     pub fn current_frame_number(&self) -> usize {
         let current_animation = self.current_animation();
         let frame_time = current_animation.duration / current_animation.key_frames.len() as f32;
@@ -66,78 +118,145 @@ impl AnimationPlayer {
 
     }
 
-    pub fn set_animations(&mut self, animations: PlayerAnimations) {
+    // Swap the clips, fx to the ones of another weapon. A clip playing now or next that is not in the new animations,
+    // fx an attack only the old weapon has, is replaced by idle, or the first clip when there is no idle
+    pub fn set_animations(&mut self, animations: AnimationSet) {
+
+        let fallback = if animations.contains("idle") { Some("idle") } else { animations.names().first().copied() };
+
+        let fallback = match fallback {
+            Some(name) => Animation::Clip(name.to_string()),
+            None => {
+                println!("No animations to swap to, keeping {:?}", self.animations.names());
+                return;
+            }
+        };
+
+        match playable_in(&self.current_animation, &animations) {
+            Some(current) => self.current_animation = current,
+            None => {
+                self.current_animation = fallback.clone();
+                self.has_repeated = false;
+                self.root_motion_prev = na::Vector3::new(0.0, 0.0, 0.0);
+                self.elapsed = 0.0;
+            }
+        };
+
+        self.next_animation = self.next_animation.as_ref().map(|next| playable_in(next, &animations).unwrap_or(fallback));
+
         self.animations = animations;
     }
 
-    pub fn update_skeleton(&mut self, skeleton: &mut Skeleton, delta: f32) {
+    // Start a layer over the base animation, replacing a layer with the same name
+    pub fn play_layer(&mut self, layer: AnimationLayer) {
+
+        if !self.animations.contains(&layer.clip) {
+            println!("No animation named {} for layer {}, has {:?}", layer.clip, layer.name, self.animations.names());
+            return;
+        }
+
+        self.stop_layer(&layer.name);
+        self.layers.push(layer);
+    }
+
+
+    pub fn stop_layer(&mut self, name: &str) {
+        self.layers.retain(|layer| layer.name != name);
+    }
+
+
+    pub fn set_layer_weight(&mut self, name: &str, weight: f32) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.name == name) {
+            layer.weight = weight;
+        }
+    }
+
+
+    pub fn layer(&self, name: &str) -> Option<&AnimationLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+
+    // (layer name, event) for the events the layers passed in the last update_skeleton
+    pub fn layer_events(&self) -> &[(String, AnimationEvent)] {
+        &self.layer_events
+    }
+
+
+    // Returns the events of the base animation passed in this update, in the order they happened. The ones of the layers
+    // are in layer_events
+    pub fn update_skeleton(&mut self, skeleton: &mut Skeleton, delta: f32) -> Vec<AnimationEvent> {
+
+        let (mut pose, events) = self.update_base(skeleton, delta);
+
+        self.layer_events.clear();
+
+        for layer in &mut self.layers {
+            self.layer_events.extend(layer.advance(delta, &self.animations).into_iter().map(|event| (layer.name.clone(), event)));
+            layer.apply(&mut pose, &self.animations, skeleton);
+        }
+
+        // one shot layers are done, fx a hit reaction
+        self.layers.retain(|layer| !layer.finished());
+
+        pose.apply(skeleton);
+
+        events
+    }
+
+
+    fn update_base(&mut self, skeleton: &Skeleton, delta: f32) -> (KeyFrame, Vec<AnimationEvent>) {
+
+        // blend spaces loop until something else is played
+        if let Animation::Blend(_, ref mut playback) = self.current_animation {
+            let events = playback.advance(delta, &self.animations);
+            return (playback.pose(&self.animations, skeleton), events);
+        }
 
         let (t, next_frame_index) = self.current_t();
 
-        self.elapsed += delta;
+        let events = self.current_animation().events_between(self.elapsed, self.elapsed + delta);
 
-        let current_animation = match self.current_animation {
-            Animation::TPose => {
-                &mut self.animations.t_pose
-            },
-            Animation::Walk => {
-                &mut self.animations.walk
-            },
-            Animation::Idle => {
-                &mut self.animations.idle
-            },
-            Animation::Attack => {
-                &mut self.animations.attack
-            },
-            Animation::AttackFollow => {
-                &mut self.animations.attack_follow
-            },
-            Animation::Roll => {
-                &mut self.animations.roll
-            },
-            Animation::Transition(ref mut anim) => anim
-        };
+        self.elapsed += delta;
 
-        current_animation.update_skeleton_to_key_frame(skeleton, next_frame_index, t);
+        let pose = self.current_animation().keyframe_from_t(skeleton, next_frame_index, t);
 
-        if self.elapsed > current_animation.duration {
+        if self.elapsed > self.current_animation().duration {
             match self.next_animation {
                 Some(ref next) => {
                     self.current_animation = next.clone();
                     self.next_animation = None;
                     self.has_repeated = false;
+                    // start the next from its first frame, so its early events are not skipped
+                    self.elapsed = 0.0;
+                    self.root_motion_prev = na::Vector3::new(0.0, 0.0, 0.0);
                 },
                 _ => {
                     self.has_repeated = true;
                 }
             };
 
-            if self.is_current_cyclic() {
+            if self.current_animation().cyclic {
                 self.elapsed = 0.0;
                 self.root_motion_prev = na::Vector3::new(0.0, 0.0, 0.0);
             }
         }
+
+        (pose, events)
     }
 
 
-    fn transition_into_next(&mut self, animation: Animation, skeleton: &Skeleton) {
+    fn transition_into_next(&mut self, animation: Animation, transition_time: f32, skeleton: &Skeleton) {
         let next_start_key_frame = match animation {
-            Animation::TPose => &self.animations.t_pose,
-            Animation::Idle => &self.animations.idle,
-            Animation::Walk => &self.animations.walk,
-            Animation::Attack => &self.animations.attack,
-            Animation::AttackFollow => &self.animations.attack_follow,
-            Animation::Transition(ref anim) => anim,
-            Animation::Roll => &self.animations.roll,
-        }.key_frames[0].clone();
+            Animation::Blend(_, ref playback) => playback.pose(&self.animations, skeleton),
+            _ => self.animation(&animation).key_frames[0].clone(),
+        };
 
 
         self.next_animation = Some(animation);
 
 
         // create transition animation from current frame state
-        let transition_time = 0.2;
-
         let key_frames = vec![self.current_frame(skeleton), next_start_key_frame];
         // important that this is after we call current_frame, since that uses the elapsed time
         self.elapsed = 0.0;
@@ -165,21 +284,22 @@ impl AnimationPlayer {
         }
     }
 
-    fn animation_name(&self, animation: &Animation) -> &str {
+    fn animation_name<'a>(&self, animation: &'a Animation) -> &'a str {
 
         match animation {
-            Animation::TPose => "t_pose",
-            Animation::Walk => "walk",
-            Animation::Idle => "idle",
-            Animation::Attack => "attack",
-            Animation::AttackFollow => "attack_follow",
-            Animation::Roll => "roll",
-            Animation::Transition(_) => "transition"
+            Animation::Clip(name) => name,
+            Animation::Transition(_) => "transition",
+            Animation::Blend(name, _) => name,
         }
     }
 
     pub fn current_root_motion(&mut self,) -> Option<na::Vector3::<f32>> {
 
+        // locomotion moves by velocity
+        if let Animation::Blend(..) = self.current_animation {
+            return None;
+        }
+
         let target_position = match &self.current_animation().root_motion {
             Some(rm) => {
                 rm
@@ -207,35 +327,27 @@ impl AnimationPlayer {
 
 
     fn current_animation(&self) -> &KeyframeAnimation {
+        self.animation(&self.current_animation)
+    }
 
-        match &self.current_animation {
-            Animation::TPose => {
-                &self.animations.t_pose
-            },
-            Animation::Walk => {
-                &self.animations.walk
-            },
-            Animation::Idle => {
-                &self.animations.idle
-            },
-            Animation::Attack => {
-                &self.animations.attack
-            },
-            Animation::AttackFollow => {
-                &self.animations.attack_follow
-            },
-            Animation::Roll => {
-                &self.animations.roll
-            },
-            Animation::Transition(ref anim) => {
-                anim
-            }
+
+    fn animation<'a>(&'a self, animation: &'a Animation) -> &'a KeyframeAnimation {
+        match animation {
+            // play only starts clips that are in the set
+            Animation::Clip(name) => self.animations.get(name).unwrap(),
+            Animation::Transition(anim) => anim,
+            // the clip with the most weight
+            Animation::Blend(_, playback) => self.animations.get(playback.main_clip()).unwrap(),
         }
     }
 
 
     fn current_frame(&self, skeleton: &Skeleton) -> KeyFrame {
 
+        if let Animation::Blend(_, ref playback) = self.current_animation {
+            return playback.pose(&self.animations, skeleton);
+        }
+
         let current_animation = self.current_animation();
 
         let (t, next_frame_index) = self.current_t();
@@ -247,49 +359,163 @@ impl AnimationPlayer {
 
 
     fn current_t(&self) -> (f32, usize) {
-        let current_animation = self.current_animation();
+        self.current_animation().frame_t(self.elapsed)
+    }
+}
 
-        let frame_time = current_animation.duration / current_animation.key_frames.len() as f32;
 
-        // find next frame id
-        // +1 to ceil, instead of floor.
-        let next_frame_index = usize::min(current_animation.key_frames.len() -1,  ((self.elapsed / frame_time) + 1.0) as usize);
+// The animation if it can play with the animations, a blend space without some of its clips plays the rest
+fn playable_in(animation: &Animation, animations: &AnimationSet) -> Option<Animation> {
+    match animation {
+        Animation::Clip(name) if animations.contains(name) => Some(animation.clone()),
+        Animation::Clip(_) => None,
+        Animation::Transition(_) => Some(animation.clone()),
+        Animation::Blend(name, playback) => {
+            if playback.space().samples.iter().all(|sample| animations.contains(&sample.clip)) {
+                return Some(animation.clone());
+            }
+
+            let space = playback.space().playable(animations);
+            if space.samples.is_empty() {
+                return None;
+            }
+
+            Some(Animation::Blend(name.clone(), BlendSpacePlayback::new(space)))
+        }
+    }
+}
 
-        let fi = match next_frame_index > 0 {
-            true => next_frame_index - 1,
-            false => next_frame_index
-        } as f32;
 
-        let min = frame_time * fi;
-        let max = frame_time * (next_frame_index + 1) as f32;
+#[cfg(test)]
+mod tests {
 
-        (clamp01(self.elapsed, min, max), next_frame_index)
+    use super::*;
+    use crate::render_gl::{JointMask, LayerMode};
+
+    // 40 frames pr second, like loaded animations
+    fn animation(frames: usize, cyclic: bool) -> KeyframeAnimation {
+        KeyframeAnimation::new(frames as f32 / 40.0, vec![KeyFrame { joints: Vec::new() }; frames], cyclic, None)
     }
 
 
-    fn is_current_cyclic(&self) -> bool {
-        match &self.current_animation {
-            Animation::TPose => {
-                self.animations.t_pose.cyclic
-            },
-            Animation::Walk => {
-                self.animations.walk.cyclic
-            },
-            Animation::Idle => {
-                self.animations.idle.cyclic
-            },
-            Animation::Attack => {
-                self.animations.attack.cyclic
-            },
-            Animation::AttackFollow => {
-                self.animations.attack.cyclic
-            },
-            Animation::Roll => {
-                self.animations.roll.cyclic
-            },
-            Animation::Transition(_) => {
-                false
-            }
+    fn event(name: &str, frame: usize) -> AnimationEvent {
+        AnimationEvent { name: name.to_string(), frame }
+    }
+
+
+    fn player(attack: KeyframeAnimation, walk: KeyframeAnimation) -> AnimationPlayer {
+        let mut animations = AnimationSet::new();
+        animations.insert("walk", walk);
+        animations.insert("attack", attack);
+        animations.insert("idle", animation(10, true));
+
+        AnimationPlayer::new("idle", animations)
+    }
+
+
+    fn run(player: &mut AnimationPlayer, skeleton: &mut Skeleton, ticks: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        for _ in 0..ticks {
+            names.extend(player.update_skeleton(skeleton, 1.0 / 60.0).into_iter().map(|e| e.name));
+        }
+        names
+    }
+
+
+    #[test]
+    fn reports_events_passed() {
+        let mut skeleton = Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None };
+
+        let mut attack = animation(40, false);
+        attack.set_events(vec![event("hit_end", 20), event("hit_start", 10)]);
+
+        let mut player = player(attack, animation(20, true));
+        player.play("attack", 0.0, &skeleton);
+
+        assert_eq!(run(&mut player, &mut skeleton, 30), vec!["hit_start", "hit_end"]);
+
+        // a non cyclic animation does not report them again
+        assert!(run(&mut player, &mut skeleton, 60).is_empty());
+    }
+
+
+    #[test]
+    fn cyclic_events_repeat() {
+        let mut skeleton = Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None };
+
+        let mut walk = animation(20, true);
+        walk.set_events(vec![event("footstep", 0), event("footstep", 10)]);
+
+        let mut player = player(animation(40, false), walk);
+        player.play("walk", 0.2, &skeleton);
+
+        // through the transition and some cycles of the half second walk
+        let steps = run(&mut player, &mut skeleton, 12 + 90).len();
+        assert!(steps >= 4, "only {} footsteps", steps);
+    }
+
+
+    #[test]
+    fn unknown_clip_keeps_playing_current() {
+        let skeleton = Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None };
+
+        let mut player = player(animation(40, false), animation(20, true));
+        player.play("attack", 0.0, &skeleton);
+        player.play("parry", 0.0, &skeleton);
+
+        assert_eq!(player.current_animation_name(), "attack");
+    }
+
+
+    #[test]
+    fn swap_without_playing_clip_falls_back_to_idle() {
+        let mut skeleton = Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None };
+
+        let mut without_attack = AnimationSet::new();
+        without_attack.insert("idle", animation(10, true));
+        without_attack.insert("walk", animation(20, true));
+
+        let mut player = player(animation(40, false), animation(20, true));
+        player.play("attack", 0.0, &skeleton);
+        player.set_animations(without_attack.clone());
+
+        assert_eq!(player.current_animation_name(), "idle");
+        run(&mut player, &mut skeleton, 30);
+
+        // and when blending into it
+        let mut blending = self::player(animation(40, false), animation(20, true));
+        blending.play("walk", 0.0, &skeleton);
+        blending.play("attack", 0.2, &skeleton);
+        blending.set_animations(without_attack);
+
+        assert_eq!(blending.next_animation_name(), "idle");
+        run(&mut blending, &mut skeleton, 30);
+        assert_eq!(blending.current_animation_name(), "idle");
+    }
+
+
+    #[test]
+    fn one_shot_layer_plays_over_walk() {
+        let mut skeleton = Skeleton { name: "test".to_string(), joints: Vec::new(), legs: None };
+
+        let mut attack = animation(40, false);
+        attack.set_events(vec![event("hit_start", 10)]);
+
+        let mut player = player(attack, animation(20, true));
+        player.play("walk", 0.0, &skeleton);
+        player.play_layer(AnimationLayer::new("upper_body", "attack", LayerMode::Override, JointMask::all(&skeleton)));
+
+        // the layer events are reported apart from the base events, with the walk still playing
+        let mut layer_events = Vec::new();
+        for _ in 0..30 {
+            assert!(player.update_skeleton(&mut skeleton, 1.0 / 60.0).is_empty());
+            layer_events.extend(player.layer_events().iter().map(|(layer, event)| (layer.clone(), event.name.clone())));
         }
+        assert_eq!(layer_events, vec![("upper_body".to_string(), "hit_start".to_string())]);
+        assert_eq!(player.current_animation_name(), "walk");
+        assert!(player.layer("upper_body").is_some());
+
+        run(&mut player, &mut skeleton, 40);
+        assert!(player.layer("upper_body").is_none());
     }
 }
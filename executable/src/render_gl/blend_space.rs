@@ -0,0 +1,345 @@
+use serde::Deserialize;
+
+use crate::render_gl::{AnimationSet, AnimationEvent, KeyFrame, Skeleton, Transformation};
+
+
+// Mixes clips by where one or two parameters are between the samples, fx idle, walk and run by speed.
+// Defined in an animation graph, see render_gl::AnimationGraph
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BlendSpace {
+    pub x: String,
+    // a second parameter makes it 2D, fx side_speed and forward_speed to pick strafe clips
+    #[serde(default)]
+    pub y: Option<String>,
+    // the movement speed the playback rate follows, so feet do not slide
+    #[serde(default)]
+    pub sync_speed: Option<String>,
+    pub samples: Vec<BlendSample>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BlendSample {
+    pub clip: String,
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    // movement speed the clip was animated at, 0 for clips standing still
+    #[serde(default)]
+    pub speed: f32,
+}
+
+
+// A blend space being played. All the clips share the phase, so their steps line up
+#[derive(Debug, Clone)]
+pub struct BlendSpacePlayback {
+    space: BlendSpace,
+    weights: Vec<f32>,
+    rate: f32,
+    // 0 to 1 through every clip
+    phase: f32,
+}
+
+
+impl BlendSpace {
+
+    // the space without samples whose clip is not in the animations, fx run on a model with only walk
+    pub fn playable(&self, animations: &AnimationSet) -> BlendSpace {
+        BlendSpace {
+            samples: self.samples.iter().filter(|sample| animations.contains(&sample.clip)).cloned().collect(),
+            ..self.clone()
+        }
+    }
+
+
+    // Weight of each sample at the position, summing to 1. Uses gradient bands, so a position on a sample
+    // only plays that sample, and one between two samples in a line only mixes those two
+    pub fn weights(&self, x: f32, y: f32) -> Vec<f32> {
+
+        let position = na::Vector2::new(x, y);
+        let points: Vec::<na::Vector2::<f32>> = self.samples.iter().map(|sample| na::Vector2::new(sample.x, sample.y)).collect();
+
+        let mut weights: Vec::<f32> = points.iter().enumerate().map(|(i, point)| {
+            let to_position = position - point;
+            points.iter().enumerate()
+                .filter(|(j, other)| *j != i && *other != point)
+                .map(|(_, other)| {
+                    let to_other = other - point;
+                    (1.0 - to_position.dot(&to_other) / to_other.magnitude_squared()).clamp(0.0, 1.0)
+                })
+                .fold(1.0, f32::min)
+        }).collect();
+
+        let total: f32 = weights.iter().sum();
+
+        if total > 0.0 {
+            for weight in &mut weights {
+                *weight /= total;
+            }
+        }
+        else if let Some(nearest) = nearest(&points, position) {
+            weights[nearest] = 1.0;
+        }
+
+        weights
+    }
+}
+
+
+fn nearest(points: &[na::Vector2::<f32>], position: na::Vector2::<f32>) -> Option<usize> {
+    points.iter().enumerate()
+        .min_by(|(_, a), (_, b)| (*a - position).magnitude().partial_cmp(&(*b - position).magnitude()).unwrap())
+        .map(|(i, _)| i)
+}
+
+
+impl BlendSpacePlayback {
+
+    // the space should only have playable samples, and at least one
+    pub fn new(space: BlendSpace) -> BlendSpacePlayback {
+        let mut weights = vec![0.0; space.samples.len()];
+        weights[0] = 1.0;
+
+        BlendSpacePlayback {
+            space,
+            weights,
+            rate: 1.0,
+            phase: 0.0,
+        }
+    }
+
+
+    pub fn space(&self) -> &BlendSpace {
+        &self.space
+    }
+
+
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+
+    // Speed is the current movement speed when the space syncs to it
+    pub fn set_parameters(&mut self, x: f32, y: f32, speed: Option<f32>) {
+
+        self.weights = self.space.weights(x, y);
+
+        let sample_speed: f32 = self.space.samples.iter().zip(&self.weights)
+            .map(|(sample, weight)| sample.speed * weight)
+            .sum();
+
+        // standing still, or a space where nothing moves plays at the normal rate
+        self.rate = match speed {
+            Some(speed) if sample_speed > 0.1 => (speed / sample_speed).clamp(0.25, 3.0),
+            _ => 1.0,
+        };
+    }
+
+
+    // the clip with the highest weight, events are taken from it
+    pub fn main_clip(&self) -> &str {
+        let main = self.weights.iter().enumerate()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+
+        &self.space.samples[main].clip
+    }
+
+
+    // Move the phase by delta seconds of the mixed clip. Returns the events of the main clip passed
+    pub fn advance(&mut self, delta: f32, animations: &AnimationSet) -> Vec<AnimationEvent> {
+
+        // clips of different lengths are stretched to the weighted length
+        let duration: f32 = self.space.samples.iter().zip(&self.weights)
+            .map(|(sample, weight)| animations.get(&sample.clip).map(|clip| clip.duration).unwrap_or(0.0) * weight)
+            .sum();
+
+        if duration <= 0.0 {
+            return Vec::new();
+        }
+
+        let from = self.phase;
+        let to = from + delta * self.rate / duration;
+
+        let main = animations.get(self.main_clip()).unwrap();
+
+        let mut events = main.events_between(from * main.duration, to * main.duration);
+
+        if to >= 1.0 {
+            events.extend(main.events_between(0.0, (to - 1.0) * main.duration));
+        }
+
+        self.phase = to.fract();
+
+        events
+    }
+
+
+    // the clips at the current phase mixed by their weights
+    pub fn pose(&self, animations: &AnimationSet, skeleton: &Skeleton) -> KeyFrame {
+
+        let frames: Vec::<(KeyFrame, f32)> = self.space.samples.iter().zip(&self.weights)
+            .filter(|(_, weight)| **weight > 0.0)
+            .map(|(sample, weight)| {
+                let clip = animations.get(&sample.clip).unwrap();
+                (clip.keyframe_at(skeleton, self.phase * clip.duration), *weight)
+            })
+            .collect();
+
+        blend_key_frames(&frames)
+    }
+}
+
+
+// Weighted mix of poses, the weights should sum to 1
+pub fn blend_key_frames(frames: &[(KeyFrame, f32)]) -> KeyFrame {
+
+    let joint_count = frames.iter().map(|(frame, _)| frame.joints.len()).min().unwrap_or(0);
+
+    let mut joints = Vec::new();
+
+    for i in 0..joint_count {
+
+        let mut translation = na::Vector3::new(0.0, 0.0, 0.0);
+        let mut rotation = frames[0].0.joints[i].rotation;
+        let mut total = 0.0;
+
+        for (frame, weight) in frames {
+            let joint = &frame.joints[i];
+            translation += joint.translation * *weight;
+
+            // slerp towards each next rotation by its share of the weight so far
+            total += weight;
+            if total > 0.0 {
+                rotation = rotation.slerp(&joint.rotation, weight / total);
+            }
+        }
+
+        joints.push(Transformation {
+            translation,
+            rotation
+        });
+    }
+
+    KeyFrame {
+        joints
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::render_gl::KeyframeAnimation;
+
+    fn sample(clip: &str, x: f32, y: f32, speed: f32) -> BlendSample {
+        BlendSample { clip: clip.to_string(), x, y, speed }
+    }
+
+
+    fn locomotion() -> BlendSpace {
+        BlendSpace {
+            x: "speed".to_string(),
+            y: None,
+            sync_speed: Some("speed".to_string()),
+            samples: vec![sample("idle", 0.0, 0.0, 0.0), sample("walk", 2.0, 0.0, 2.0), sample("run", 5.0, 0.0, 5.0)],
+        }
+    }
+
+
+    fn assert_weights(actual: Vec<f32>, expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 0.001, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+
+    #[test]
+    fn speed_mixes_neighbours() {
+        let space = locomotion();
+
+        assert_weights(space.weights(0.0, 0.0), &[1.0, 0.0, 0.0]);
+        assert_weights(space.weights(1.0, 0.0), &[0.5, 0.5, 0.0]);
+        assert_weights(space.weights(3.5, 0.0), &[0.0, 0.5, 0.5]);
+        // past the last sample keeps playing it
+        assert_weights(space.weights(8.0, 0.0), &[0.0, 0.0, 1.0]);
+    }
+
+
+    #[test]
+    fn direction_picks_strafe() {
+        let space = BlendSpace {
+            x: "side_speed".to_string(),
+            y: Some("forward_speed".to_string()),
+            sync_speed: None,
+            samples: vec![
+                sample("idle", 0.0, 0.0, 0.0),
+                sample("walk", 0.0, 2.0, 2.0),
+                sample("strafe_left", -2.0, 0.0, 2.0),
+                sample("strafe_right", 2.0, 0.0, 2.0)
+            ],
+        };
+
+        assert_weights(space.weights(2.0, 0.0), &[0.0, 0.0, 0.0, 1.0]);
+        assert_weights(space.weights(-2.0, 0.0), &[0.0, 0.0, 1.0, 0.0]);
+
+        let diagonal = space.weights(1.0, 1.0);
+        assert!((diagonal[1] - diagonal[3]).abs() < 0.001 && diagonal[1] > 0.0);
+        assert_eq!(diagonal[2], 0.0);
+    }
+
+
+    #[test]
+    fn rate_follows_speed() {
+        let mut playback = BlendSpacePlayback::new(locomotion());
+
+        playback.set_parameters(2.0, 0.0, Some(2.0));
+        assert!((playback.rate() - 1.0).abs() < 0.001);
+
+        // only idle and walk on the model, so running speeds up the walk
+        let mut animations = AnimationSet::new();
+        animations.insert("idle", KeyframeAnimation::new(1.0, vec![KeyFrame { joints: Vec::new() }; 40], true, None));
+        animations.insert("walk", KeyframeAnimation::new(1.0, vec![KeyFrame { joints: Vec::new() }; 40], true, None));
+
+        let mut playback = BlendSpacePlayback::new(locomotion().playable(&animations));
+        playback.set_parameters(3.0, 0.0, Some(3.0));
+        assert!((playback.rate() - 1.5).abs() < 0.001);
+
+        playback.advance(0.5, &animations);
+        assert!((playback.phase() - 0.75).abs() < 0.001);
+
+        // standing still
+        playback.set_parameters(0.0, 0.0, Some(0.0));
+        assert_eq!(playback.rate(), 1.0);
+    }
+
+
+    #[test]
+    fn blend_mixes_poses() {
+        let joint = |x: f32, angle: f32| Transformation {
+            translation: na::Vector3::new(x, 0.0, 0.0),
+            rotation: na::UnitQuaternion::from_euler_angles(0.0, 0.0, angle),
+        };
+
+        let a = KeyFrame { joints: vec![joint(0.0, 0.0)] };
+        let b = KeyFrame { joints: vec![joint(2.0, 1.0)] };
+
+        let mixed = blend_key_frames(&[(a, 0.75), (b, 0.25)]);
+
+        assert!((mixed.joints[0].translation.x - 0.5).abs() < 0.001);
+        assert!((mixed.joints[0].rotation.angle() - 0.25).abs() < 0.001);
+    }
+}
@@ -62,9 +62,9 @@ impl Context {
 
 
 
-pub fn setup(width: u32, height: u32) -> Result<Context, failure::Error>
+pub fn setup(width: u32, height: u32, assets_path: &Path) -> Result<Context, failure::Error>
 {
-    let res = Resources::from_relative_exe_path(Path::new("assets")).unwrap();
+    let res = Resources::from_path(assets_path);
 
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
@@ -1,68 +1,349 @@
 use crate::render_gl::mesh::{GltfMesh};
 use noise::{NoiseFn, Perlin, Seedable};
+use serde::Deserialize;
 
 use crate::types::*;
-use rand::Rng;
 
-pub fn perlin_field() -> GltfMesh {
 
-    let mut noise_data = Vec::new();
+// Chunk x and y index, 0,0 is the chunk at -size/2, -size/2
+pub type ChunkCoord = (u32, u32);
+
+// Level of detail of the chunks next to a chunk, in the order -x, +x, -y, +y.
+// None when there is no chunk, fx at the edge of the terrain
+pub type NeighbourLods = [Option<u32>; 4];
+
+
+// Everything that decides how the generated terrain looks, the same params always give the same terrain
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TerrainParams {
+    pub seed: u32,
+    // world units along x and y, centered on 0,0
+    pub size: f32,
+    // vertices along each side at full detail
+    pub resolution: u32,
+    // quads along each side of a chunk at full detail, rounded up to a power of 2 so it can be halved for each lod
+    pub chunk_quads: u32,
+    // distance from the player where each lower level of detail starts, fx [40, 80] gives lod 0, 1 and 2
+    pub lod_distances: Vec<f32>,
+    // chunks further away than this are unloaded
+    pub view_distance: f32,
+    // noise frequency over the whole terrain
+    pub scale: f64,
+    pub octaves: u32,
+    // frequency multiplier for each octave
+    pub lacunarity: f64,
+    // amplitude multiplier for each octave
+    pub persistence: f64,
+    pub height_scale: f32,
+    pub domain_warp: Option<DomainWarp>,
+    // write the noise as a grayscale png, fx "noise_image.png"
+    pub noise_image: Option<String>,
+}
+
+
+// Offset the noise lookup by another noise, gives more twisted and less uniform hills
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DomainWarp {
+    // how far, in noise space, the lookup can be moved
+    pub strength: f64,
+    // frequency of the warp noise
+    pub scale: f64,
+}
+
+
+// A terrain from a grayscale heightmap image, black is min_height and white is max_height
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HeightmapParams {
+    // image in the assets folder, 8 or 16 bit grayscale
+    pub path: String,
+    // world units along the longest side of the image, centered on 0,0
+    pub size: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+}
+
+
+impl Default for HeightmapParams {
+    fn default() -> Self {
+        HeightmapParams {
+            path: "heightmap.png".to_string(),
+            size: 100.0,
+            min_height: 0.0,
+            max_height: 10.0,
+        }
+    }
+}
+
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        TerrainParams {
+            seed: 0,
+            size: 100.0,
+            resolution: 129,
+            chunk_quads: 32,
+            lod_distances: vec![40.0, 80.0, 160.0],
+            view_distance: 300.0,
+            scale: 7.0,
+            octaves: 1,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            height_scale: 3.0,
+            domain_warp: None,
+            noise_image: None,
+        }
+    }
+}
+
+
+impl TerrainParams {
+
+    // a grid needs at least 2 vertices on each side
+    pub fn grid_resolution(&self) -> u32 {
+        u32::max(self.resolution, 2)
+    }
+
+    // distance between two vertices
+    pub fn spacing(&self) -> f32 {
+        self.size / (self.grid_resolution() - 1) as f32
+    }
+
+    pub fn chunk_quads(&self) -> u32 {
+        u32::max(self.chunk_quads, 1).next_power_of_two()
+    }
+
+    pub fn chunk_size(&self) -> f32 {
+        self.chunk_quads() as f32 * self.spacing()
+    }
+
+    // chunks along each side, the last chunk can go a bit past size
+    pub fn chunk_count(&self) -> u32 {
+        let quads = self.grid_resolution() - 1;
+        (quads + self.chunk_quads() - 1) / self.chunk_quads()
+    }
+
+    // world x y of the chunks first vertex
+    pub fn chunk_origin(&self, chunk: ChunkCoord) -> V2 {
+        V2::new(chunk.0 as f32 * self.chunk_size() - self.size / 2.0,
+                chunk.1 as f32 * self.chunk_size() - self.size / 2.0)
+    }
+
+    // world x y of the chunks center
+    pub fn chunk_center(&self, chunk: ChunkCoord) -> V2 {
+        self.chunk_origin(chunk) + V2::new(self.chunk_size() / 2.0, self.chunk_size() / 2.0)
+    }
+
+    // The chunk containing world x y, None when outside the terrain
+    pub fn chunk_at(&self, x: f32, y: f32) -> Option<ChunkCoord> {
+        let cx = ((x + self.size / 2.0) / self.chunk_size()).floor();
+        let cy = ((y + self.size / 2.0) / self.chunk_size()).floor();
 
-    let h = 100;
-    let w = 100;
+        let count = self.chunk_count() as f32;
+        if cx < 0.0 || cy < 0.0 || cx >= count || cy >= count {
+            return None;
+        }
+
+        Some((cx as u32, cy as u32))
+    }
+
+    // Lowest lod is 0 is full detail, each lod halves the quads, at least 1 quad pr side is left
+    pub fn lod_for_distance(&self, distance: f32) -> u32 {
+        let lod = self.lod_distances.iter().filter(|d| **d < distance).count() as u32;
+        u32::min(lod, self.max_lod())
+    }
+
+    pub fn max_lod(&self) -> u32 {
+        self.chunk_quads().trailing_zeros()
+    }
+
+    // noise value, before height_scale, at world x y
+    pub fn noise_at(&self, perlin: &Perlin, x: f32, y: f32) -> f64 {
+
+        // 0..1 over the terrain
+        let u = ((x + self.size / 2.0) / self.size) as f64;
+        let v = ((y + self.size / 2.0) / self.size) as f64;
+
+        let mut nx = u * self.scale;
+        let mut ny = v * self.scale;
+
+        if let Some(warp) = &self.domain_warp {
+            // offsets so the two warp directions and the terrain are not the same noise
+            nx += warp.strength * perlin.get([u * warp.scale + 5.2, v * warp.scale + 1.3]);
+            ny += warp.strength * perlin.get([u * warp.scale + 9.7, v * warp.scale + 2.8]);
+        }
+
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        let mut noise = 0.0;
+
+        for _ in 0..self.octaves {
+            noise += amplitude * perlin.get([nx * frequency, ny * frequency]);
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        noise
+    }
+}
 
 
-    let mut perlin = Perlin::new();
+// Mesh from a heightmap, one vertex pr pixel. The top of the image is +y
+pub fn heightmap_field(image: &image::ImageBuffer<image::Luma<u16>, Vec<u16>>, params: &HeightmapParams) -> GltfMesh {
 
-    let mut rng = rand::thread_rng();
+    // grid rows are along x, so h is the image width
+    let h = image.width();
+    let w = image.height();
 
-    let random = rng.gen::<u32>();
+    let longest = u32::max(u32::max(h, w), 2);
+    let spacing = params.size / (longest - 1) as f32;
 
-    perlin = perlin.set_seed(random);
-    println!("{:?}", perlin.seed());
+    let mut heights = Vec::new();
+    for i in 0..h {
+        for j in 0..w {
+            let pixel = image.get_pixel(i, w - 1 - j);
+            let value = pixel[0] as f32 / u16::MAX as f32;
+            heights.push(params.min_height + value * (params.max_height - params.min_height));
+        }
+    }
+
+    let origin = V2::new(-((h - 1) as f32) * spacing / 2.0, -((w - 1) as f32) * spacing / 2.0);
+
+    let indices_data = indices_for_grid(h, w);
+    let pos_data = pos_for_grid(&heights, h, w, spacing, origin, 1.0);
+    let normal_data = normals_for_grid(&pos_data, &indices_data, h, w);
+    let tex_data = tex_coord_for_grid(&pos_data, h, w);
+
+    GltfMesh {
+        name: "heightmap_mesh".to_string(),
+        pos_data,
+        normal_data,
+        indices_data,
+        tex_data,
+        vertex_weights: Vec::new()
+    }
+}
 
-    let scale_x = 7.0;
-    let scale_y = 7.0;
 
+// Heights for the whole terrain, at full resolution
+fn field_heights(params: &TerrainParams, perlin: &Perlin, h: u32, w: u32) -> Vec::<f32> {
 
-    // set pos data
+    let spacing = params.spacing();
 
+    let mut heights = Vec::new();
     let mut min = 0.0;
     for i in 0..h {
         for j in 0..w {
-            let i_f = ((i as f64) / h as f64) * scale_y;
-            let j_f = ((j as f64) / w as f64) * scale_x;
-            let noise: f64  = perlin.get([i_f, j_f]);
+            let x = i as f32 * spacing - params.size / 2.0;
+            let y = j as f32 * spacing - params.size / 2.0;
+            let noise = params.noise_at(&perlin, x, y);
 
             min = f64::min(min, noise);
-            noise_data.push(V3::new(i as f32, j as f32, noise as f32));
+            heights.push(noise as f32);
         }
     }
 
-    println!("random = {} seed = {} - min={:?}", random, perlin.seed(), min);
+    println!("seed = {} - min={:?}", perlin.seed(), min);
 
-    save_noise_to_image(&noise_data, h, w);
+    heights
+}
 
-    let indices_data = indices_for_grid(h, w);
 
+// Write the terrain noise to params.noise_image, if set
+pub fn write_noise_image(params: &TerrainParams) {
 
-    let pos_data = pos_for_grid(&noise_data, h as f32, w as f32);
-    let normal_data = normals_for_grid(&pos_data, &indices_data, h, w);
-    let tex_data = tex_coord_for_grid(&pos_data, h, w);
+    let path = match &params.noise_image {
+        Some(path) => path,
+        None => return
+    };
+
+    let h = params.grid_resolution();
+    let w = params.grid_resolution();
+
+    let perlin = Perlin::new().set_seed(params.seed);
+    let heights = field_heights(params, &perlin, h, w);
+
+    if let Err(err) = save_noise_to_image(&heights, h, w, path) {
+        println!("Error saving noise image: {}", err);
+    }
+}
+
+
+// Mesh for a single chunk at a level of detail, in world space.
+// Edges next to a chunk with a lower detail are stitched, so there are no cracks between them
+pub fn perlin_chunk(params: &TerrainParams, chunk: ChunkCoord, lod: u32, neighbour_lods: &NeighbourLods) -> GltfMesh {
+
+    let lod = u32::min(lod, params.max_lod());
+    let quads = params.chunk_quads() >> lod;
+    let n = quads + 1;
+
+    let perlin = Perlin::new().set_seed(params.seed);
+
+    let spacing = params.chunk_size() / quads as f32;
+    let origin = params.chunk_origin(chunk);
+
+    let mut heights = Vec::new();
+    for i in 0..n {
+        for j in 0..n {
+            let x = origin.x + i as f32 * spacing;
+            let y = origin.y + j as f32 * spacing;
+            heights.push(params.noise_at(&perlin, x, y) as f32);
+        }
+    }
+
+    for (edge, neighbour_lod) in neighbour_lods.iter().enumerate() {
+        if let Some(neighbour_lod) = neighbour_lod {
+            let neighbour_lod = u32::min(*neighbour_lod, params.max_lod());
+            if neighbour_lod > lod {
+                stitch_edge(&mut heights, n, edge, 1 << (neighbour_lod - lod));
+            }
+        }
+    }
 
+    let indices_data = indices_for_grid(n, n);
+    let pos_data = pos_for_grid(&heights, n, n, spacing, origin, params.height_scale);
+    let normal_data = normals_for_grid(&pos_data, &indices_data, n, n);
+    let tex_data = tex_coord_for_grid(&pos_data, n, n);
 
     GltfMesh {
-        name: "perlin_noise_mesh".to_string(),
+        name: format!("terrain_chunk_{}_{}", chunk.0, chunk.1),
         pos_data,
         normal_data,
         indices_data,
         tex_data,
         vertex_weights: Vec::new()
     }
+}
+
+
+// The neighbour only has every ratio vertex on the shared edge, move the ones in between
+// onto the line between those, so both chunks have the same edge
+fn stitch_edge(heights: &mut Vec::<f32>, n: u32, edge: usize, ratio: u32) {
+
+    let index = |k: u32| match edge {
+        0 => to_index(0, k, n),
+        1 => to_index(n - 1, k, n),
+        2 => to_index(k, 0, n),
+        _ => to_index(k, n - 1, n),
+    } as usize;
 
+    for k in 0..n {
+        let offset = k % ratio;
+        if offset == 0 {
+            continue;
+        }
+
+        let a = heights[index(k - offset)];
+        let b = heights[index(k - offset + ratio)];
+        let t = offset as f32 / ratio as f32;
+
+        heights[index(k)] = a + (b - a) * t;
+    }
 }
 
-fn save_noise_to_image(pos_data: &Vec::<V3>, h: u32, w: u32) -> Result<(), image::ImageError> {
+
+fn save_noise_to_image(heights: &Vec::<f32>, h: u32, w: u32, path: &str) -> Result<(), image::ImageError> {
 
     let new_scale = 256.0 / 2.0;
 
@@ -76,12 +357,12 @@ fn save_noise_to_image(pos_data: &Vec::<V3>, h: u32, w: u32) -> Result<(), image
         // Now max value is scale * 2
 
 
-        let r = ((pos_data[index].z) * new_scale) as u8;
+        let r = ((heights[index]) * new_scale) as u8;
 
         *pixel = image::Rgb([r, r, r]);
     }
 
-    imgbuf.save("E:/repos/Game-in-rust/noise_image.png").unwrap();
+    imgbuf.save(path)?;
 
     Ok(())
 
@@ -89,11 +370,17 @@ fn save_noise_to_image(pos_data: &Vec::<V3>, h: u32, w: u32) -> Result<(), image
 
 }
 
-fn pos_for_grid(noise_data: &Vec::<V3>, h: f32, w: f32) -> Vec::<V3> {
+// heights are row major, h rows of w. Rows along x starting at origin, with spacing between vertices
+fn pos_for_grid(heights: &Vec::<f32>, h: u32, w: u32, spacing: f32, origin: V2, height_scale: f32) -> Vec::<V3> {
 
     let mut pos_data = Vec::new();
-    for sample in noise_data {
-        pos_data.push(V3::new( sample.x - (w/2.0), sample.y - (h/2.0), sample.z * 3.0));
+    for i in 0..h {
+        for j in 0..w {
+            let height = heights[to_index(i, j, w) as usize];
+            pos_data.push(V3::new(origin.x + i as f32 * spacing,
+                                  origin.y + j as f32 * spacing,
+                                  height * height_scale));
+        }
     }
 
     pos_data
@@ -178,8 +465,6 @@ fn tex_coord_for_grid(pos_data: &Vec::<V3>, h: u32, w: u32) -> Vec::<[f32; 2]> {
     let dark = V2::new(0.2, 0.0);
     let darker = V2::new(0.3, 0.0);
 
-    let mut tex_coords = std::collections::HashSet::new();
-
     for i in 0..h {
         for j in 0..w {
 
@@ -189,13 +474,10 @@ fn tex_coord_for_grid(pos_data: &Vec::<V3>, h: u32, w: u32) -> Vec::<[f32; 2]> {
                 _ =>  green + dark,
             };
 
-            tex_coords.insert(format!("{},{}", color.x, color.y));
             tex_data.push([color.x, color.y]);
         }
     }
 
-    println!("{:?}", tex_coords);
-    //panic!();
     tex_data
 }
 
@@ -203,3 +485,110 @@ fn tex_coord_for_grid(pos_data: &Vec::<V3>, h: u32, w: u32) -> Vec::<[f32; 2]> {
 fn to_index(i: u32, j: u32, w: u32) -> u32 {
     i * w + j
 }
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // vertices with x close to the given x, sorted along y
+    fn edge_vertices(mesh: &GltfMesh, x: f32) -> Vec::<V3> {
+        let mut edge: Vec::<V3> = mesh.pos_data.iter().filter(|p| (p.x - x).abs() < 1e-4).cloned().collect();
+        edge.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
+        edge
+    }
+
+    #[test]
+    fn chunks_cover_the_terrain() {
+        let params = TerrainParams::default();
+
+        assert_eq!(params.chunk_count(), 4);
+        assert_eq!(params.chunk_at(-50.0, -50.0), Some((0, 0)));
+        assert_eq!(params.chunk_at(49.9, 0.1), Some((3, 2)));
+        assert_eq!(params.chunk_at(50.1, 0.0), None);
+        assert_eq!(params.chunk_at(-50.1, 0.0), None);
+
+        let mesh = perlin_chunk(&params, (3, 3), 0, &[None; 4]);
+        assert_eq!(mesh.pos_data.len(), 33 * 33);
+        assert!((mesh.pos_data.last().unwrap().x - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn lod_halves_quads() {
+        let params = TerrainParams::default();
+
+        assert_eq!(params.lod_for_distance(10.0), 0);
+        assert_eq!(params.lod_for_distance(50.0), 1);
+        assert_eq!(params.lod_for_distance(1000.0), 3);
+
+        let mesh = perlin_chunk(&params, (0, 0), 2, &[None; 4]);
+        assert_eq!(mesh.pos_data.len(), 9 * 9);
+        assert_eq!(mesh.indices_data.len(), 8 * 8 * 6);
+    }
+
+    #[test]
+    fn heightmap_size_and_height_range() {
+        // 3 wide and 2 high, white in the top left corner
+        let mut image = image::ImageBuffer::new(3, 2);
+        image.put_pixel(0, 0, image::Luma([u16::MAX]));
+        image.put_pixel(1, 1, image::Luma([u16::MAX / 2]));
+
+        let params = HeightmapParams {
+            size: 10.0,
+            min_height: -2.0,
+            max_height: 4.0,
+            ..HeightmapParams::default()
+        };
+
+        let mesh = heightmap_field(&image, &params);
+
+        assert_eq!(mesh.pos_data.len(), 6);
+        assert_eq!(mesh.indices_data.len(), 2 * 1 * 6);
+
+        let max = mesh.pos_data.iter().cloned().fold(V3::new(f32::MIN, f32::MIN, f32::MIN), |a, b| a.sup(&b));
+        let min = mesh.pos_data.iter().cloned().fold(V3::new(f32::MAX, f32::MAX, f32::MAX), |a, b| a.inf(&b));
+
+        assert!((max.x - 5.0).abs() < 1e-4 && (min.x + 5.0).abs() < 1e-4);
+        assert!((max.y - 2.5).abs() < 1e-4 && (min.y + 2.5).abs() < 1e-4);
+
+        // top left of the image is -x +y
+        let top_left = mesh.pos_data.iter().find(|p| p.x < -4.0 && p.y > 2.0).unwrap();
+        assert!((top_left.z - 4.0).abs() < 1e-4);
+
+        let middle_bottom = mesh.pos_data.iter().find(|p| p.x.abs() < 1e-4 && p.y < -2.0).unwrap();
+        assert!((middle_bottom.z - 1.0).abs() < 1e-3);
+
+        assert_eq!(mesh.pos_data.iter().filter(|p| (p.z + 2.0).abs() < 1e-4).count(), 4);
+    }
+
+
+    #[test]
+    fn stitched_edge_matches_coarser_neighbour() {
+        let params = TerrainParams {
+            octaves: 4,
+            height_scale: 10.0,
+            ..TerrainParams::default()
+        };
+
+        // (0, 0) at full detail next to (1, 0) at lod 2
+        let fine = perlin_chunk(&params, (0, 0), 0, &[None, Some(2), None, None]);
+        let coarse = perlin_chunk(&params, (1, 0), 2, &[Some(0), None, None, None]);
+
+        let edge_x = params.chunk_origin((1, 0)).x;
+        let fine_edge = edge_vertices(&fine, edge_x);
+        let coarse_edge = edge_vertices(&coarse, edge_x);
+
+        assert_eq!(fine_edge.len(), 33);
+        assert_eq!(coarse_edge.len(), 9);
+
+        // every fine vertex is on the coarse edge
+        for p in &fine_edge {
+            let segment = coarse_edge.windows(2).find(|s| s[0].y <= p.y + 1e-4 && p.y <= s[1].y + 1e-4).unwrap();
+            let t = (p.y - segment[0].y) / (segment[1].y - segment[0].y);
+            let z = segment[0].z + (segment[1].z - segment[0].z) * t;
+            assert!((p.z - z).abs() < 1e-3, "fine {:?} coarse z {}", p, z);
+        }
+    }
+}
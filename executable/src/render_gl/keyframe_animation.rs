@@ -1,7 +1,10 @@
 use nalgebra as na;
+use serde::Deserialize;
 use std::collections::{HashMap};
+use std::path::Path;
 
 use crate::render_gl::{Skeleton, Joint};
+use crate::math::clamp01;
 
 use crate::resources;
 
@@ -12,8 +15,31 @@ pub struct KeyframeAnimation {
     pub key_frames: Vec<KeyFrame>,
     pub cyclic: bool,
     pub root_motion: Option<na::Vector3::<f32>>,
+    // sorted by frame
+    pub events: Vec<AnimationEvent>,
 }
 
+
+// A named point on the timeline, fx hit_start, hit_end, combo_window_open, combo_window_close,
+// footstep, invincible_start and invincible_end. The player reports the events it passes
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AnimationEvent {
+    pub name: String,
+    pub frame: usize,
+}
+
+
+// Custom properties on the blender action
+#[derive(Default, Deserialize)]
+struct AnimationExtras {
+    #[serde(default)]
+    events: Vec<AnimationEvent>,
+    cyclic: Option<bool>,
+    // move the character by how far the root moves in the clip
+    root_motion: Option<bool>,
+}
+
+
 #[derive(Debug, Clone)]
 pub struct KeyFrame {
     pub joints: Vec<Transformation>,
@@ -35,6 +61,10 @@ pub enum Error {
     ResourceError(resources::Error),
     #[fail(display = "gltf Error")]
     GltfError(gltf::Error),
+    #[fail(display = "Can not parse the extras of animation {}: {}", _0, _1)]
+    ExtrasParse(String, serde_json::Error),
+    #[fail(display = "Can not parse the animation events {}: {}", _0, _1)]
+    EventsParse(String, ron::de::Error),
 }
 
 
@@ -52,89 +82,175 @@ impl From<gltf::Error> for Error {
     }
 }
 
-#[derive(Clone)]
-pub struct PlayerAnimations {
-    pub walk: KeyframeAnimation,
-    pub attack: KeyframeAnimation,
-    pub attack_follow: KeyframeAnimation,
-    pub t_pose: KeyframeAnimation,
-    pub idle: KeyframeAnimation,
-    pub roll: KeyframeAnimation,
+// Animations by clip name, fx "walk", "attack" or "block_idle". Every clip in the gltf file is loaded
+#[derive(Debug, Clone, Default)]
+pub struct AnimationSet {
+    clips: HashMap<String, KeyframeAnimation>,
 }
 
 
-pub fn load_animations(file_path: &str, skeleton: &Skeleton, base_animations: Option<&PlayerAnimations>) -> Result<PlayerAnimations, Error> {
+impl AnimationSet {
+
+    pub fn new() -> AnimationSet {
+        AnimationSet {
+            clips: HashMap::new(),
+        }
+    }
 
-    let animations = key_frames_from_gltf(file_path, skeleton)?;
 
-    // This is not the FPS is will be played back at, by used to normalise longer and shorter animaions
-    // to be invariant of keyframes
+    pub fn get(&self, name: &str) -> Option<&KeyframeAnimation> {
+        self.clips.get(name)
+    }
+
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut KeyframeAnimation> {
+        self.clips.get_mut(name)
+    }
 
-    let t_pose_frames = animations.get("t_pose");
-    let walk_frames = animations.get("walk");
-    let idle_frames = animations.get("idle");
-    let attack_frames = animations.get("attack");
-    let attack_follow_frames = animations.get("attack_follow");
 
-    let roll_frames = animations.get("roll");
+    pub fn contains(&self, name: &str) -> bool {
+        self.clips.contains_key(name)
+    }
 
-    let t_pose = create_animation(t_pose_frames, base_animations.map(|fb| &fb.t_pose), true);
-    let walk = create_animation(walk_frames, base_animations.map(|fb| &fb.walk), true);
-    let idle = create_animation(idle_frames, base_animations.map(|fb| &fb.idle), true);
-    let attack = create_root_motion_animation(attack_frames, base_animations.map(|fb| &fb.attack), false);
-    let attack_follow = create_root_motion_animation(attack_follow_frames, base_animations.map(|fb| &fb.attack_follow), false);
 
+    pub fn insert(&mut self, name: &str, animation: KeyframeAnimation) {
+        self.clips.insert(name.to_string(), animation);
+    }
 
-    let roll = create_root_motion_animation(roll_frames, base_animations.map(|fb| &fb.roll), false);
 
-    Ok(PlayerAnimations {
-        t_pose,
-        walk,
-        idle,
-        attack,
-        attack_follow,
-        roll
-    })
+    // sorted, so printing them is stable
+    pub fn names(&self) -> Vec::<&str> {
+        let mut names: Vec::<&str> = self.clips.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
 }
 
 
-fn create_root_motion_animation(frames: Option<&Vec::<KeyFrame>>, fall_back: Option<&KeyframeAnimation>, cyclic: bool) -> KeyframeAnimation {
-    let frame_normalize = 40.0;
+// Clips not in the file are taken from base_animations, fx a weapon file only has its attacks
+// and uses the walk and idle of the player
+pub fn load_animations(file_path: &Path, skeleton: &Skeleton, base_animations: Option<&AnimationSet>) -> Result<AnimationSet, Error> {
 
+    let clips = key_frames_from_gltf(file_path, skeleton)?;
 
-    match frames {
-        Some(fs) => {
-            let mut new_frames = fs.clone();
-            // get root_motion into vec
-            // remove movement from animation
-            // so playing it without movement results in inplace animaiton
-            let base = new_frames[0].joints[0].translation;
+    let mut extras = extras_from_gltf(file_path)?;
 
-            let root_motion = (&mut new_frames).last().unwrap().joints[0].translation  - base;
-            for frame in new_frames.iter_mut() {
-                frame.joints[0].translation.x = 0.0;
-                frame.joints[0].translation.y = 0.0;
-            }
+    let mut animations = base_animations.cloned().unwrap_or_default();
+
+    for (name, frames) in clips {
+
+        let clip_extras = extras.remove(&name).unwrap_or_default();
+
+        let cyclic = clip_extras.cyclic.unwrap_or_else(|| default_cyclic(&name));
+        let root_motion = clip_extras.root_motion.unwrap_or_else(|| default_root_motion(&name));
 
-            KeyframeAnimation::new(new_frames.len() as f32 / frame_normalize, new_frames.clone(), cyclic, Some(root_motion))
+        let mut animation = match root_motion {
+            true => create_root_motion_animation(&frames, cyclic),
+            false => create_animation(&frames, cyclic),
+        };
+
+        animation.set_events(clip_extras.events);
+
+        animations.insert(&name, animation);
+    }
 
-        },
-        None => fall_back.unwrap().clone(),
+    // a sidecar file replaces the events from the gltf, fx sword.glb has sword.events.ron.
+    // Clips from the base animations can also get events this way
+    for (name, events) in load_events_file(&file_path.with_extension("events.ron"))? {
+        match animations.get_mut(&name) {
+            Some(animation) => animation.set_events(events),
+            None => println!("Events for {} but there is no such animation in {:?}", name, file_path),
+        }
     }
+
+    Ok(animations)
 }
 
-fn create_animation(frames: Option<&Vec::<KeyFrame>>, fall_back: Option<&KeyframeAnimation>, cyclic: bool,) -> KeyframeAnimation {
+
+// clips that loop when the extras do not say, fx walk and block_idle
+fn default_cyclic(name: &str) -> bool {
+    match name {
+        "t_pose" | "walk" | "run" => true,
+        _ => name.ends_with("idle"),
+    }
+}
+
+
+// clips that move the character when the extras do not say
+fn default_root_motion(name: &str) -> bool {
+    match name {
+        "attack" | "attack_follow" | "roll" => true,
+        _ => false
+    }
+}
+
+
+fn create_root_motion_animation(frames: &Vec::<KeyFrame>, cyclic: bool) -> KeyframeAnimation {
+    // This is not the FPS is will be played back at, by used to normalise longer and shorter animaions
+    // to be invariant of keyframes
     let frame_normalize = 40.0;
 
-    match frames {
-        Some(fs) => {
-            KeyframeAnimation::new(fs.len() as f32 / frame_normalize, fs.clone(), cyclic, None)
-        },
-        None => fall_back.unwrap().clone(),
+    let mut new_frames = frames.clone();
+    // get root_motion into vec
+    // remove movement from animation
+    // so playing it without movement results in inplace animaiton
+    let base = new_frames[0].joints[0].translation;
+
+    let root_motion = (&mut new_frames).last().unwrap().joints[0].translation  - base;
+    for frame in new_frames.iter_mut() {
+        frame.joints[0].translation.x = 0.0;
+        frame.joints[0].translation.y = 0.0;
     }
+
+    KeyframeAnimation::new(new_frames.len() as f32 / frame_normalize, new_frames, cyclic, Some(root_motion))
+}
+
+fn create_animation(frames: &Vec::<KeyFrame>, cyclic: bool,) -> KeyframeAnimation {
+    let frame_normalize = 40.0;
+
+    KeyframeAnimation::new(frames.len() as f32 / frame_normalize, frames.clone(), cyclic, None)
 }
 
-fn key_frames_from_gltf(file_path: &str, skeleton: &Skeleton) -> Result<HashMap<String, Vec<KeyFrame>>, Error> {
+// Extras on the gltf animations, fx { "events": [{ "name": "hit_start", "frame": 9 }], "cyclic": false }
+fn extras_from_gltf(file_path: &Path) -> Result<HashMap<String, AnimationExtras>, Error> {
+    // only the document, the buffers are not needed
+    let gltf = gltf::Gltf::open(file_path)?;
+
+    let mut res = HashMap::new();
+
+    for ani in gltf.animations() {
+        let (name, raw) = match (ani.name(), ani.extras()) {
+            (Some(name), Some(raw)) => (name.to_string(), raw),
+            _ => continue
+        };
+
+        let extras: AnimationExtras = serde_json::from_str(raw.get())
+            .map_err(|e| Error::ExtrasParse(name.clone(), e))?;
+
+        res.insert(name, extras);
+    }
+
+    Ok(res)
+}
+
+
+// Events by animation name, fx { "attack": [(name: "hit_start", frame: 9), (name: "hit_end", frame: 20)] }.
+// No file is no events
+fn load_events_file(path: &Path) -> Result<HashMap<String, Vec<AnimationEvent>>, Error> {
+
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| Error::ResourceError(resources::Error::Io(e)))?;
+
+    ron::de::from_str(&data)
+        .map_err(|e| Error::EventsParse(path.display().to_string(), e))
+}
+
+
+fn key_frames_from_gltf(file_path: &Path, skeleton: &Skeleton) -> Result<HashMap<String, Vec<KeyFrame>>, Error> {
     // should be in resources, but atm the file is not in resources
     let (gltf, buffers, _) = gltf::import(file_path)?;
 
@@ -245,6 +361,25 @@ fn key_frames_from_gltf(file_path: &str, skeleton: &Skeleton) -> Result<HashMap<
     Ok(res)
 }
 
+impl KeyFrame {
+
+    // set the skeleton to the pose, joints moved by ik are left alone
+    pub fn apply(&self, skeleton: &mut Skeleton) {
+
+        let ik_joints = match skeleton.legs.as_ref() {
+            None => Vec::new(),
+            Some(legs) => legs.ik_bones(),
+        };
+
+        for (i, joint) in self.joints.iter().enumerate().take(skeleton.joints.len()) {
+            if !ik_joints.contains(&i) {
+                Skeleton::update_joint_matrices(&mut skeleton.joints, i, joint.rotation, joint.translation)
+            }
+        }
+    }
+}
+
+
 impl Transformation {
 
     pub fn identity(joint: &Joint) -> Self {
@@ -269,7 +404,8 @@ impl KeyframeAnimation {
             cyclic: true,
             duration: 1.0,
             key_frames: Vec::new(),
-            root_motion: None
+            root_motion: None,
+            events: Vec::new(),
         }
     }
 
@@ -279,52 +415,67 @@ impl KeyframeAnimation {
             duration,
             key_frames,
             root_motion,
+            events: Vec::new(),
         }
     }
 
-    pub fn keyframe_from_t(&self, skeleton: &Skeleton, next_keyframe: usize, t: f32) -> KeyFrame {
 
-        let mut joints = Vec::new();
+    pub fn set_events(&mut self, mut events: Vec<AnimationEvent>) {
+        events.sort_by_key(|event| event.frame);
+        self.events = events;
+    }
 
-        for i in 0..skeleton.joints.len() {
 
-            let current_transformation = match next_keyframe {
-                0 => {
-                    self.key_frames[0].joints[i]
+    pub fn frame_time(&self) -> f32 {
+        self.duration / usize::max(self.key_frames.len(), 1) as f32
+    }
 
-                },
-                n => {
-                    self.key_frames[n - 1].joints[i]
-                }
-            };
 
-            let target_joint = &self.key_frames[next_keyframe].joints[i];
+    // Events at times in [from, to), in seconds from the start of the animation
+    pub fn events_between(&self, from: f32, to: f32) -> Vec<AnimationEvent> {
+        let frame_time = self.frame_time();
 
-            let rotation = current_transformation.rotation.slerp(&target_joint.rotation, t);
+        self.events.iter()
+            .filter(|event| {
+                let time = event.frame as f32 * frame_time;
+                from <= time && time < to
+            })
+            .cloned()
+            .collect()
+    }
 
-            let translation = current_transformation.translation * (1.0 - t) + target_joint.translation * t;
+    // The frame after time, and how far, 0 to 1, we are into it from the one before
+    pub fn frame_t(&self, time: f32) -> (f32, usize) {
 
+        let frame_time = self.frame_time();
 
-            joints.push(Transformation {
-                translation,
-                rotation
-            });
-        }
+        // find next frame id
+        // +1 to ceil, instead of floor.
+        let next_frame_index = usize::min(self.key_frames.len() -1,  ((time / frame_time) + 1.0) as usize);
 
-        KeyFrame {
-            joints,
-        }
+        let fi = match next_frame_index > 0 {
+            true => next_frame_index - 1,
+            false => next_frame_index
+        } as f32;
+
+        let min = frame_time * fi;
+        let max = frame_time * (next_frame_index + 1) as f32;
+
+        (clamp01(time, min, max), next_frame_index)
     }
 
 
-    pub fn update_skeleton_to_key_frame(&mut self, skeleton: &mut Skeleton, next_keyframe: usize, t: f32) {
+    // the pose at time, in seconds from the start
+    pub fn keyframe_at(&self, skeleton: &Skeleton, time: f32) -> KeyFrame {
+        let (t, next_keyframe) = self.frame_t(time);
+        self.keyframe_from_t(skeleton, next_keyframe, t)
+    }
 
-        let ik_joints = match skeleton.legs.as_ref() {
-            None => Vec::new(),
-            Some(ref legs) => legs.ik_bones(),
-        };
 
-        // interpolate joints new transformation
+    pub fn keyframe_from_t(&self, skeleton: &Skeleton, next_keyframe: usize, t: f32) -> KeyFrame {
+
+        let mut joints = Vec::new();
+
         for i in 0..skeleton.joints.len() {
 
             let current_transformation = match next_keyframe {
@@ -343,10 +494,15 @@ impl KeyframeAnimation {
 
             let translation = current_transformation.translation * (1.0 - t) + target_joint.translation * t;
 
-            if !ik_joints.contains(&i) {
-                Skeleton::update_joint_matrices(&mut skeleton.joints, i, rotation, translation)
-            }
 
+            joints.push(Transformation {
+                translation,
+                rotation
+            });
+        }
+
+        KeyFrame {
+            joints,
         }
     }
 }
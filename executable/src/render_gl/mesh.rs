@@ -1,9 +1,16 @@
 use crate::render_gl::{self, buffer};
-use crate::physics::{Triangle};
+use crate::render_gl::skinning::{VertexWeights, normalize_weights};
+use crate::physics::{Triangle, ShapeKind};
 use gl;
+use serde::Deserialize;
+use std::path::Path;
 
 pub struct SkinnedMesh {
     mesh: Mesh,
+    // bone matrices for the shader, as a texture buffer so a skeleton can have any number of joints
+    bone_buffer: gl::types::GLuint,
+    bone_texture: gl::types::GLuint,
+    gl: gl::Gl,
 }
 
 pub struct Mesh {
@@ -58,6 +65,9 @@ impl Mesh {
         unsafe {
             shader.set_model(gl, model);
 
+            // also for meshes without bones, two sampler types can not share a unit
+            set_bone_unit(gl, shader);
+
             self.vao.bind();
 
             gl.DrawElements(
@@ -70,13 +80,6 @@ impl Mesh {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct VertexWeights {
-    // maybe keep the actual vertex index instead of having it just as the index in the vec this is stored in
-    joints: [usize; 2],
-    weights: [f32; 2],
-}
-
 impl SkinnedMesh {
     pub fn new(gl: &gl::Gl, gltf_mesh: &GltfMesh) -> Self {
 
@@ -92,8 +95,19 @@ impl SkinnedMesh {
             Some(&gltf_mesh.vertex_weights)
         );
 
+        let mut bone_buffer: gl::types::GLuint = 0;
+        let mut bone_texture: gl::types::GLuint = 0;
+
+        unsafe {
+            gl.GenBuffers(1, &mut bone_buffer);
+            gl.GenTextures(1, &mut bone_texture);
+        }
+
         SkinnedMesh {
             mesh,
+            bone_buffer,
+            bone_texture,
+            gl: gl.clone(),
         }
 
     }
@@ -105,16 +119,20 @@ impl SkinnedMesh {
         model: na::Matrix4<f32>,
         bones: &[na::Matrix4<f32>],
     ) {
-        let bones_str = std::ffi::CString::new("uBones").unwrap();
-        let len: i32 = bones.len() as i32;
-
         unsafe {
-            let bones_loc = gl.GetUniformLocation(
-                shader.program_id(),
-                bones_str.as_ptr() as *mut gl::types::GLchar,
+            // each bone is 4 rgba texels, one pr column
+            gl.BindBuffer(gl::TEXTURE_BUFFER, self.bone_buffer);
+            gl.BufferData(
+                gl::TEXTURE_BUFFER,
+                (bones.len() * std::mem::size_of::<na::Matrix4<f32>>()) as gl::types::GLsizeiptr,
+                bones.as_ptr() as *const gl::types::GLvoid,
+                gl::DYNAMIC_DRAW,
             );
 
-            gl.UniformMatrix4fv(bones_loc, len, gl::FALSE, bones.as_ptr() as *const f32);
+            gl.ActiveTexture(gl::TEXTURE0 + BONE_TEXTURE_UNIT as u32);
+            gl.BindTexture(gl::TEXTURE_BUFFER, self.bone_texture);
+            gl.TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, self.bone_buffer);
+            gl.ActiveTexture(gl::TEXTURE0);
 
             self.mesh.render(gl, shader, model);
         }
@@ -122,9 +140,35 @@ impl SkinnedMesh {
 }
 
 
+// the model texture is on unit 0
+const BONE_TEXTURE_UNIT: i32 = 1;
 
-// alternative just load the data. and then we can instanciate it if needed
 
+unsafe fn set_bone_unit(gl: &gl::Gl, shader: &render_gl::Shader) {
+    let bones_str = std::ffi::CString::new("uBones").unwrap();
+
+    let bones_loc = gl.GetUniformLocation(
+        shader.program_id(),
+        bones_str.as_ptr() as *mut gl::types::GLchar,
+    );
+
+    gl.Uniform1i(bones_loc, BONE_TEXTURE_UNIT);
+}
+
+
+impl Drop for SkinnedMesh {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.bone_texture);
+            self.gl.DeleteBuffers(1, &self.bone_buffer);
+        }
+    }
+}
+
+
+
+// alternative just load the data. and then we can instanciate it if needed
+#[derive(Clone)]
 pub struct GltfMesh {
     pub name: String,
     pub pos_data: Vec<na::Vector3::<f32>>,
@@ -165,13 +209,15 @@ impl GltfMesh {
 
 
 pub struct GltfMeshes {
-    pub meshes: std::collections::HashMap::<String, GltfMesh>
+    pub meshes: std::collections::HashMap::<String, GltfMesh>,
+    // node name to the shape set in its extras, hitboxes without one are boxes
+    pub hitbox_shapes: std::collections::HashMap::<String, ShapeKind>,
 }
 
 
 impl GltfMeshes {
 
-    pub fn hitboxes(&self, base_name: &str) -> Vec::<(String, Vec<na::Vector3::<f32>>)>{
+    pub fn hitboxes(&self, base_name: &str) -> Vec::<(String, Vec<na::Vector3::<f32>>, ShapeKind)>{
 
         //println!("HITBOXES FOR {}", base_name);
         let mut res = Vec::new();
@@ -197,7 +243,9 @@ impl GltfMeshes {
             final_hitbox.push(hitbox[3]);
 
 
-            res.push((mesh_data.name.clone(), final_hitbox));
+            let shape = self.hitbox_shapes.get(&mesh_data.name).cloned().unwrap_or(ShapeKind::Box);
+
+            res.push((mesh_data.name.clone(), final_hitbox, shape));
 
         }
 
@@ -219,7 +267,7 @@ impl GltfMeshes {
 
 
 
-pub fn meshes_from_gltf(file_path: &str, _gl: &gl::Gl, index_map: &std::collections::HashMap<u16,usize>) -> Result<GltfMeshes, failure::Error> {
+pub fn meshes_from_gltf(file_path: &Path, index_map: &std::collections::HashMap<u16,usize>) -> Result<GltfMeshes, failure::Error> {
 
     let (gltf, buffers, _) = gltf::import(file_path)?;
 
@@ -237,16 +285,26 @@ pub fn meshes_from_gltf(file_path: &str, _gl: &gl::Gl, index_map: &std::collecti
     }
 
     let mut res = GltfMeshes {
-        meshes: std::collections::HashMap::new()
+        meshes: std::collections::HashMap::new(),
+        hitbox_shapes: std::collections::HashMap::new(),
     };
 
 
     for node in gltf.nodes() {
         match node.mesh() {
             Some(m) => {
-                //println!("EXTRAS FOR {} {:?}", node.name().unwrap(), node.extras());
+                let name = node.name().map(|n| n.to_string()).unwrap_or_else(|| format!("node_{}", node.index()));
 
-                res.meshes.insert(node.name().unwrap().to_string(), load_gltf_mesh_data(&m, &buffers, &index_map, &inter_joint_index)?);
+                let shape = match hitbox_shape(node.extras())? {
+                    Some(shape) => Some(shape),
+                    None => hitbox_shape(m.extras())?,
+                };
+
+                if let Some(shape) = shape {
+                    res.hitbox_shapes.insert(name.clone(), shape);
+                }
+
+                res.meshes.insert(name, load_gltf_mesh_data(&m, &buffers, &index_map, &inter_joint_index)?);
             },
             _ => {}
         };
@@ -259,9 +317,156 @@ pub fn meshes_from_gltf(file_path: &str, _gl: &gl::Gl, index_map: &std::collecti
 }
 
 
+// How a level mesh is used, set with a custom property "usage" on the blender object,
+// "render_only" or "collision_only". Without it the mesh is both rendered and collided with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeshUsage {
+    RenderAndCollision,
+    RenderOnly,
+    CollisionOnly,
+}
+
+impl MeshUsage {
+    pub fn render(&self) -> bool {
+        *self != MeshUsage::CollisionOnly
+    }
+
+    pub fn collision(&self) -> bool {
+        *self != MeshUsage::RenderOnly
+    }
+}
+
+
+#[derive(Deserialize)]
+struct LevelExtras {
+    usage: Option<String>,
+}
+
+
+// A static mesh from a level file, with the node transform applied so it is in world space
+pub struct LevelMesh {
+    pub name: String,
+    pub mesh: GltfMesh,
+    pub usage: MeshUsage,
+}
+
+
+// Load every mesh node in the default scene of a level file, fx walls, ramps and bridges
+pub fn level_meshes_from_gltf(file_path: &Path) -> Result<Vec<LevelMesh>, failure::Error> {
+
+    let (gltf, buffers, _) = gltf::import(file_path)?;
+
+    let mut res = Vec::new();
+
+    let scene = gltf.default_scene().or_else(|| gltf.scenes().next());
+
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            load_level_node(&node, &na::Matrix4::identity(), &buffers, &mut res)?;
+        }
+    }
+
+    println!("Level meshes loaded {:?}", res.iter().map(|m| &m.name).collect::<Vec<_>>());
+
+    Ok(res)
+}
+
+
+fn load_level_node(node: &gltf::Node, parent_transform: &na::Matrix4::<f32>, buffers: &Vec<gltf::buffer::Data>, res: &mut Vec<LevelMesh>) -> Result<(), failure::Error> {
+
+    let transform = parent_transform * na::Matrix4::from(node.transform().matrix());
+
+    if let Some(m) = node.mesh() {
+        let mut mesh = load_gltf_mesh_data(&m, buffers, &std::collections::HashMap::new(), &Vec::new())?;
+        transform_mesh(&mut mesh, &transform);
+
+        // blender exports object custom properties on the node, and mesh data properties on the mesh
+        let usage = match mesh_usage(node.extras())? {
+            Some(usage) => usage,
+            None => mesh_usage(m.extras())?.unwrap_or(MeshUsage::RenderAndCollision),
+        };
+
+        res.push(LevelMesh {
+            name: node.name().map(|n| n.to_string()).unwrap_or_else(|| format!("node_{}", node.index())),
+            mesh,
+            usage
+        });
+    }
+
+    for child in node.children() {
+        load_level_node(&child, &transform, buffers, res)?;
+    }
+
+    Ok(())
+}
+
+
+fn mesh_usage(extras: &gltf::json::Extras) -> Result<Option<MeshUsage>, failure::Error> {
+
+    let raw = match extras {
+        Some(raw) => raw,
+        None => return Ok(None)
+    };
+
+    let extras: LevelExtras = serde_json::from_str(raw.get())?;
+
+    match extras.usage.as_deref() {
+        None => Ok(None),
+        Some("render_only") => Ok(Some(MeshUsage::RenderOnly)),
+        Some("collision_only") => Ok(Some(MeshUsage::CollisionOnly)),
+        Some(other) => Err(format_err!("Unknown mesh usage '{}', use render_only or collision_only", other)),
+    }
+}
+
+
+#[derive(Deserialize)]
+struct HitboxExtras {
+    shape: Option<String>,
+}
+
+
+// Collision shape of a hitbox, set with a custom property "shape" on the blender object, "box", "sphere" or "capsule"
+fn hitbox_shape(extras: &gltf::json::Extras) -> Result<Option<ShapeKind>, failure::Error> {
+
+    let raw = match extras {
+        Some(raw) => raw,
+        None => return Ok(None)
+    };
+
+    let extras: HitboxExtras = serde_json::from_str(raw.get())?;
+
+    match extras.shape.as_deref() {
+        None => Ok(None),
+        Some("box") => Ok(Some(ShapeKind::Box)),
+        Some("sphere") => Ok(Some(ShapeKind::Sphere)),
+        Some("capsule") => Ok(Some(ShapeKind::Capsule)),
+        Some(other) => Err(format_err!("Unknown hitbox shape '{}', use box, sphere or capsule", other)),
+    }
+}
+
+
+// Move positions and normals from node space into world space
+fn transform_mesh(mesh: &mut GltfMesh, transform: &na::Matrix4::<f32>) {
+
+    for pos in &mut mesh.pos_data {
+        *pos = transform.transform_point(&na::Point3::from(*pos)).coords;
+    }
+
+    // normals use the inverse transpose, so they stay correct with non uniform scale
+    let linear = na::Matrix3::from_fn(|r, c| transform[(r, c)]);
+    let normal_transform = linear.try_inverse().map(|m| m.transpose()).unwrap_or(linear);
+
+    for normal in &mut mesh.normal_data {
+        let n = (normal_transform * na::Vector3::new(normal[0], normal[1], normal[2])).normalize();
+        *normal = [n.x, n.y, n.z];
+    }
+}
+
+
 fn load_gltf_mesh_data(mesh: &gltf::mesh::Mesh, buffers: &Vec<gltf::buffer::Data>, index_map: &std::collections::HashMap<u16,usize>, inter_joint_index: &Vec::<u16>) -> Result<GltfMesh, failure::Error> {
 
-    let name = mesh.name().unwrap().to_string();
+    // meshes do not need a name in glTF
+    let name = mesh.name().map(|n| n.to_string()).unwrap_or_else(|| format!("mesh_{}", mesh.index()));
 
     let mut pos_data = Vec::new();
 
@@ -282,6 +487,9 @@ fn load_gltf_mesh_data(mesh: &gltf::mesh::Mesh, buffers: &Vec<gltf::buffer::Data
 
         let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
+        // indices in each primitive start from its own first vertex
+        let index_offset = pos_data.len() as u32;
+
         if let Some(iter) = reader.read_positions() {
             for pos in iter {
                 let p1 = na::Vector3::new(pos[0], pos[1], pos[2]);
@@ -303,7 +511,7 @@ fn load_gltf_mesh_data(mesh: &gltf::mesh::Mesh, buffers: &Vec<gltf::buffer::Data
 
         if let Some(reader) = reader.read_indices() {
             for tex in reader.into_u32() {
-                indices_data.push(tex);
+                indices_data.push(tex + index_offset);
             }
         }
 
@@ -340,7 +548,7 @@ fn load_gltf_mesh_data(mesh: &gltf::mesh::Mesh, buffers: &Vec<gltf::buffer::Data
 
     }
 
-    let vertex_weights = reduce_to_2_joints(&joints_data, &weights_data);
+    let vertex_weights = normalize_weights(&joints_data, &weights_data);
 
     Ok(GltfMesh {
         name,
@@ -393,23 +601,19 @@ fn bind_mesh_data(
             Some(s_data) => {
 
                 // BONE WEIGHTS
-                vertex_data.push(s_data[i].weights[0]);
-                vertex_data.push(s_data[i].weights[1]);
+                vertex_data.extend(s_data[i].weights.iter());
 
                 // BONE INDICES
 
-                vertex_data.push(s_data[i].joints[0] as f32);
-                vertex_data.push(s_data[i].joints[1] as f32);
+                vertex_data.extend(s_data[i].joints.iter().map(|joint| *joint as f32));
             },
             _ => {
                 // BONE WEIGHTS
-                vertex_data.push(0.0);
-                vertex_data.push(0.0);
+                vertex_data.extend(&[0.0; 4]);
 
                 // BONE INDICES
 
-                vertex_data.push(-1.0);
-                vertex_data.push(-1.0);
+                vertex_data.extend(&[-1.0; 4]);
 
             }
         }
@@ -421,7 +625,7 @@ fn bind_mesh_data(
         vertex_data.push(tex_data[i][1]);
     }
 
-    let stride = ((3 + 3 + 2 + 2 + 2) * std::mem::size_of::<f32>()) as gl::types::GLint;
+    let stride = ((3 + 3 + 4 + 4 + 2) * std::mem::size_of::<f32>()) as gl::types::GLint;
     unsafe {
         // 1
         mesh.vao.bind();
@@ -467,7 +671,7 @@ fn bind_mesh_data(
 
         gl.VertexAttribPointer(
             2,
-            2,
+            4,
             gl::FLOAT,
             gl::FALSE,
             stride,
@@ -479,22 +683,22 @@ fn bind_mesh_data(
         // bone indices
         gl.VertexAttribPointer(
             3,
-            2,
+            4,
             gl::FLOAT,
             gl::FALSE,
             stride,
-            (8 * std::mem::size_of::<f32>()) as *const gl::types::GLvoid,
+            (10 * std::mem::size_of::<f32>()) as *const gl::types::GLvoid,
         );
         gl.EnableVertexAttribArray(3);
 
-        // bone indices
+        // texture coordinates
         gl.VertexAttribPointer(
             4,
             2,
             gl::FLOAT,
             gl::FALSE,
             stride,
-            (10 * std::mem::size_of::<f32>()) as *const gl::types::GLvoid,
+            (14 * std::mem::size_of::<f32>()) as *const gl::types::GLvoid,
         );
 
         gl.EnableVertexAttribArray(4);
@@ -503,54 +707,94 @@ fn bind_mesh_data(
     mesh.indices_count = indices_count as i32;
 }
 
-fn reduce_to_2_joints(joints_data: &Vec<[usize; 4]>, weights_data: &Vec<[f32; 4]>) -> Vec<VertexWeights> {
-    // find the two largest weights and use them also normalize the two weights sum to 1
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    // one triangle (0,0,0) (1,0,0) (0,1,0) used by 3 nodes, a child node with scale and
+    // a rotated node, with usage set in extras
+    const LEVEL_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [ { "nodes": [0, 2] } ],
+        "nodes": [
+            { "name": "bridge", "mesh": 0, "translation": [10.0, 0.0, 0.0], "children": [1] },
+            { "name": "bridge_rail", "mesh": 0, "scale": [2.0, 2.0, 2.0], "extras": { "usage": "render_only" } },
+            { "name": "wall_blocker", "mesh": 0, "rotation": [0.0, 0.0, 0.70710678, 0.70710678], "extras": { "usage": "collision_only" } }
+        ],
+        "meshes": [ { "name": "triangle", "primitives": [ { "attributes": { "POSITION": 0, "NORMAL": 1 }, "indices": 2 } ] } ],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+            { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 72, "byteLength": 6 }
+        ],
+        "buffers": [ { "byteLength": 80, "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/AAABAAIAAAA=" } ]
+    }"#;
+
+
+    fn assert_positions(mesh: &GltfMesh, expected: &[[f32; 3]]) {
+        assert_eq!(mesh.pos_data.len(), expected.len());
+        for (p, e) in mesh.pos_data.iter().zip(expected) {
+            assert!((p - na::Vector3::new(e[0], e[1], e[2])).magnitude() < 1e-4, "{:?} != {:?}", p, e);
+        }
+    }
 
-    let mut res = Vec::new();
 
-    for j_index in 0..joints_data.len() {
-        let mut max1 = 0.0;
-        let mut max2 = 0.0;
-        let mut max1_i = 0;
-        let mut max2_i = 0;
-        for w_index in 0..4 {
+    #[test]
+    fn level_node_transforms_and_usage() {
+        let path = std::env::temp_dir().join(format!("level_test_{}.gltf", std::process::id()));
+        std::fs::write(&path, LEVEL_GLTF).unwrap();
 
-            let w = weights_data[j_index][w_index];
+        let level = level_meshes_from_gltf(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
 
-            if w >= max1 {
-                max2 = max1;
-                max2_i = max1_i;
-                max1 = w;
-                max1_i = w_index;
-            }
-            else if w >= max2 {
-                max2 = w;
-                max2_i = w_index;
-            }
-        }
+        assert_eq!(level.len(), 3);
 
-        if max1_i == max2_i {
-            max2 = 0.0;
-        }
+        let bridge = level.iter().find(|m| m.name == "bridge").unwrap();
+        assert_eq!(bridge.usage, MeshUsage::RenderAndCollision);
+        assert_positions(&bridge.mesh, &[[10.0, 0.0, 0.0], [11.0, 0.0, 0.0], [10.0, 1.0, 0.0]]);
 
-        if max2 == 0.0 {
-            max2_i = max1_i;
-        }
+        // child transforms are on top of the parent
+        let rail = level.iter().find(|m| m.name == "bridge_rail").unwrap();
+        assert_eq!(rail.usage, MeshUsage::RenderOnly);
+        assert!(!rail.usage.collision());
+        assert_positions(&rail.mesh, &[[10.0, 0.0, 0.0], [12.0, 0.0, 0.0], [10.0, 2.0, 0.0]]);
 
+        let wall = level.iter().find(|m| m.name == "wall_blocker").unwrap();
+        assert_eq!(wall.usage, MeshUsage::CollisionOnly);
+        assert!(!wall.usage.render());
+        assert_positions(&wall.mesh, &[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0]]);
 
-        let sum = max1 + max2;
-        let max1 = max1 / sum;
-        let max2 = max2 / sum;
+        let normal = wall.mesh.normal_data[0];
+        assert!((normal[2] - 1.0).abs() < 1e-4);
 
-        let joint_1 = joints_data[j_index][max1_i];
-        let joint_2 = joints_data[j_index][max2_i];
+        assert_eq!(wall.mesh.triangles().len(), 1);
+    }
 
 
-        res.push(VertexWeights {
-            joints: [joint_1, joint_2],
-            weights: [ max1, max2]
-        });
-    }
+    #[test]
+    fn hitbox_shapes_from_extras() {
+        let gltf = LEVEL_GLTF.replace(r#""nodes": [
+            { "name": "bridge","#, r#""nodes": [
+            { "name": "enemy_hitbox_head", "mesh": 0, "extras": { "shape": "sphere" } },
+            { "name": "enemy_hitbox_body", "mesh": 0, "extras": { "shape": "capsule" } },
+            { "name": "enemy_hitbox_legs", "mesh": 0 },
+            { "name": "bridge","#);
 
-    res
+        let path = std::env::temp_dir().join(format!("hitbox_test_{}.gltf", std::process::id()));
+        std::fs::write(&path, gltf).unwrap();
+
+        let meshes = meshes_from_gltf(&path, &std::collections::HashMap::new()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(meshes.hitbox_shapes.get("enemy_hitbox_head"), Some(&ShapeKind::Sphere));
+        assert_eq!(meshes.hitbox_shapes.get("enemy_hitbox_body"), Some(&ShapeKind::Capsule));
+        assert_eq!(meshes.hitbox_shapes.get("enemy_hitbox_legs"), None);
+    }
 }
@@ -31,17 +31,26 @@ pub use self::render_functions::*;
 
 
 mod keyframe_animation;
-pub use self::keyframe_animation::{KeyframeAnimation, PlayerAnimations, Transformation, KeyFrame, load_animations};
+pub use self::keyframe_animation::{KeyframeAnimation, AnimationEvent, AnimationSet, Transformation, KeyFrame, load_animations};
 
 
 mod animation_player;
 pub use self::animation_player::{AnimationPlayer, Animation};
 
+pub mod animation_layer;
+pub use self::animation_layer::{AnimationLayer, JointMask, LayerMode};
+pub mod blend_space;
+pub use self::blend_space::{BlendSpace, BlendSample, BlendSpacePlayback};
+pub mod animation_graph;
+pub use self::animation_graph::{AnimationGraph, AnimationParameters, AnimationStateMachine};
+
 mod mesh;
-pub use self::mesh::{Mesh, SkinnedMesh, GltfMeshes, GltfMesh, meshes_from_gltf};
+pub use self::mesh::{Mesh, SkinnedMesh, GltfMeshes, GltfMesh, meshes_from_gltf, level_meshes_from_gltf, LevelMesh, MeshUsage};
+pub mod skinning;
+pub use self::skinning::{VertexWeights, skin_positions};
 
 mod generated_mesh;
-pub use self::generated_mesh::{perlin_field};
+pub use self::generated_mesh::{perlin_chunk, write_noise_image, heightmap_field, TerrainParams, HeightmapParams, DomainWarp, ChunkCoord, NeighbourLods};
 
 mod skeleton;
 pub use self::skeleton::{Skeleton, Joint};
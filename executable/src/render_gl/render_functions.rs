@@ -5,8 +5,9 @@ use crate::types::*;
 use crate::text_render;
 use crate::types::*;
 
-pub fn render_entity(entity: &entity::Entity, model: &entity::Model, gl: &gl::Gl, shader: &render_gl::Shader) {
-    model.render_from_model_mat(gl, shader, entity.base_entity.physics.calculate_model_mat(), &entity.bones);
+// alpha is how far between the previous and current simulation tick to render the entity
+pub fn render_entity(entity: &entity::Entity, model: &entity::Model, gl: &gl::Gl, shader: &render_gl::Shader, alpha: f32) {
+    model.render_from_model_mat(gl, shader, entity.render_physics(alpha).calculate_model_mat(), &entity.bones);
 }
 
 
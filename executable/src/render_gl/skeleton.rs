@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use crate::render_gl::{Transformation};
 use crate::render_gl::{Ik, IkLegs};
 
@@ -91,7 +93,7 @@ impl Skeleton {
 
 
 
-    pub fn from_gltf(file_path: &str) -> Result<(Skeleton, std::collections::HashMap<u16,usize>), failure::Error> {
+    pub fn from_gltf(file_path: &Path) -> Result<(Skeleton, std::collections::HashMap<u16,usize>), failure::Error> {
         let (gltf, _, _) = gltf::import(file_path)?;
 
         for skin in gltf.skins() {
@@ -0,0 +1,146 @@
+// Joints moving a vertex, and how much. The same as the mesh shaders do, so tests can check skinning without a gpu
+
+// joints pr vertex, like glTF JOINTS_0 and WEIGHTS_0
+pub const MAX_INFLUENCES: usize = 4;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VertexWeights {
+    // maybe keep the actual vertex index instead of having it just as the index in the vec this is stored in
+    pub joints: [usize; MAX_INFLUENCES],
+    pub weights: [f32; MAX_INFLUENCES],
+}
+
+
+impl VertexWeights {
+
+    // Weights summing to 1. Negative weights count as 0, and a vertex without weight follows its first joint
+    pub fn normalized(joints: [usize; MAX_INFLUENCES], weights: [f32; MAX_INFLUENCES]) -> VertexWeights {
+
+        let mut weights = weights;
+        for weight in &mut weights {
+            if weight.is_nan() || *weight < 0.0 {
+                *weight = 0.0;
+            }
+        }
+
+        let sum: f32 = weights.iter().sum();
+
+        if sum <= 0.0 {
+            return VertexWeights {
+                joints,
+                weights: [1.0, 0.0, 0.0, 0.0]
+            };
+        }
+
+        for weight in &mut weights {
+            *weight /= sum;
+        }
+
+        VertexWeights {
+            joints,
+            weights
+        }
+    }
+}
+
+
+pub fn normalize_weights(joints_data: &[[usize; MAX_INFLUENCES]], weights_data: &[[f32; MAX_INFLUENCES]]) -> Vec<VertexWeights> {
+    joints_data.iter().zip(weights_data)
+        .map(|(joints, weights)| VertexWeights::normalized(*joints, *weights))
+        .collect()
+}
+
+
+// Bones weighted together, bones are joint world matrix * inverse bind pose, see Skeleton::set_bones_from_skeleton
+pub fn skin_matrix(weights: &VertexWeights, bones: &[na::Matrix4::<f32>]) -> na::Matrix4::<f32> {
+
+    let mut matrix = na::Matrix4::zeros();
+
+    for (joint, weight) in weights.joints.iter().zip(&weights.weights) {
+        if *weight > 0.0 {
+            matrix += bones[*joint] * *weight;
+        }
+    }
+
+    matrix
+}
+
+
+pub fn skin_position(position: &na::Vector3::<f32>, weights: &VertexWeights, bones: &[na::Matrix4::<f32>]) -> na::Vector3::<f32> {
+    skin_matrix(weights, bones).transform_point(&na::Point3::from(*position)).coords
+}
+
+
+pub fn skin_normal(normal: &na::Vector3::<f32>, weights: &VertexWeights, bones: &[na::Matrix4::<f32>]) -> na::Vector3::<f32> {
+
+    let skin = skin_matrix(weights, bones);
+    let matrix = na::Matrix3::from_fn(|row, column| skin[(row, column)]);
+
+    // inverse transpose keeps normals right under non uniform scale
+    let normal_matrix = matrix.try_inverse().map(|inverse| inverse.transpose()).unwrap_or(matrix);
+
+    (normal_matrix * normal).normalize()
+}
+
+
+// Every position moved by the bones, a cpu reference of the mesh shader
+pub fn skin_positions(positions: &[na::Vector3::<f32>], weights: &[VertexWeights], bones: &[na::Matrix4::<f32>]) -> Vec::<na::Vector3::<f32>> {
+    positions.iter().zip(weights)
+        .map(|(position, weights)| skin_position(position, weights, bones))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn assert_close(a: na::Vector3::<f32>, b: na::Vector3::<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+
+    #[test]
+    fn weights_sum_to_one() {
+        let weights = VertexWeights::normalized([3, 1, 7, 0], [0.2, 0.2, 0.4, 0.0]);
+        assert_eq!(weights.weights, [0.25, 0.25, 0.5, 0.0]);
+
+        // all four are kept, not only the two largest
+        let weights = VertexWeights::normalized([0, 1, 2, 3], [0.4, 0.3, 0.2, 0.1]);
+        assert!(weights.weights.iter().all(|weight| *weight > 0.0));
+
+        let unweighted = VertexWeights::normalized([5, 0, 0, 0], [0.0, -1.0, 0.0, 0.0]);
+        assert_eq!(unweighted.weights, [1.0, 0.0, 0.0, 0.0]);
+    }
+
+
+    #[test]
+    fn four_influences_and_many_bones() {
+        // 40 bones, more than the old limit of 32, each moving along x by its index
+        let bones: Vec::<na::Matrix4::<f32>> = (0..40)
+            .map(|i| na::Matrix4::new_translation(&na::Vector3::new(i as f32, 0.0, 0.0)))
+            .collect();
+
+        let weights = VertexWeights::normalized([39, 33, 2, 0], [1.0, 1.0, 1.0, 1.0]);
+
+        let moved = skin_positions(&[na::Vector3::new(0.0, 1.0, 0.0)], &[weights], &bones);
+
+        assert_close(moved[0], na::Vector3::new((39.0 + 33.0 + 2.0) / 4.0, 1.0, 0.0));
+    }
+
+
+    #[test]
+    fn normals_follow_rotation() {
+        let bones = vec![
+            na::Matrix4::identity(),
+            na::Matrix4::from_euler_angles(0.0, 0.0, std::f32::consts::FRAC_PI_2),
+        ];
+
+        let weights = VertexWeights::normalized([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+
+        assert_close(skin_normal(&na::Vector3::new(1.0, 0.0, 0.0), &weights, &bones), na::Vector3::new(0.0, 1.0, 0.0));
+        assert_close(skin_position(&na::Vector3::new(2.0, 0.0, 0.0), &weights, &bones), na::Vector3::new(0.0, 2.0, 0.0));
+    }
+}
@@ -66,19 +66,35 @@ impl Resources {
     }
 
 
+    pub fn from_path(root_path: &Path) -> Resources {
+        println!("resource path {:?}", root_path);
+        Resources {
+            root_path: root_path.into()
+        }
+    }
+
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        resource_name_to_path(&self.root_path, name)
+    }
+
+
     pub fn copy_and_load_lib(&self, name: &str) -> libloading::Library {
 
+        // name is the platform specific file name, fx libai.so or ai.dll
+        let loaded_name = name.replace(std::env::consts::DLL_SUFFIX, &format!("_loaded{}", std::env::consts::DLL_SUFFIX));
+
         println!("{:?}", self.root_path);
         let path_from = resource_name_to_path(&self.root_path, name);
 
-        let path_to = resource_name_to_path(&self.root_path, &name.replace(".dll", "_loaded.dll"));
+        let path_to = resource_name_to_path(&self.root_path, &loaded_name);
 
         let res = fs::copy(path_from, path_to);
 
         let path_from = resource_name_to_path(&self.root_path, name);
         println!("\n\ncopy res = {:?}, \n\nfrom path={:?}\n\n", res, path_from);
 
-        let path_to = resource_name_to_path(&self.root_path, &name.replace(".dll", "_loaded.dll"));
+        let path_to = resource_name_to_path(&self.root_path, &loaded_name);
         unsafe {
             libloading::Library::new(path_to)
                 .expect("load library")
@@ -119,6 +135,17 @@ impl Resources {
     }
 
 
+    // 8 bit images are scaled up to 16 bit, colors are converted to gray
+    pub fn load_image_luma16(&self, resource_name: &str) -> Result<image::ImageBuffer<image::Luma<u16>, Vec<u16>>, Error> {
+
+        let path = resource_name_to_path(&self.root_path, resource_name);
+
+        let image = ImageReader::open(path)?.decode()?.into_luma16();
+
+        Ok(image)
+    }
+
+
     pub fn load_string(&self, resource_name: &str) -> Result<String, Error> {
 
         let content = std::fs::read_to_string(resource_name_to_path(&self.root_path, resource_name))?;
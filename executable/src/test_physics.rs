@@ -108,7 +108,7 @@ impl PhysicsTest {
     }
 
 
-    pub fn render(&self, gl: &gl::Gl, scene: &game::Scene, shader: &render_gl::Shader) {
+    pub fn render(&self, gl: &gl::Gl, scene: &game::Scene, cube_shader: &render_gl::Shader, shader: &render_gl::Shader) {
 
         shader.set_used();
         shader.set_projection_and_view(gl, scene.camera().projection(), scene.camera().view());
@@ -121,7 +121,7 @@ impl PhysicsTest {
         // static cube
         let static_color = na::Vector3::new(1.0, 1.0, 1.0);
         shader.set_vec3(gl, "color", static_color);
-        self.static_cube.render(gl, &scene.cube_shader, model_static);
+        self.static_cube.render(gl, cube_shader, model_static);
 
 
         // dynamic cube
@@ -53,10 +53,6 @@ impl<T> FreeList<T> {
         self.first_free = -1;
 
     }
-
-    pub fn range(&self) -> i32 {
-        (self.data.len() - 1)as i32
-    }
 }
 
 
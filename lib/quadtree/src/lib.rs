@@ -1,4 +1,5 @@
 use std::fmt;
+use nalgebra as na;
 mod free_list;
 use free_list::FreeList;
 
@@ -8,11 +9,23 @@ use free_list::FreeList;
 
 //QuadElt is stored once, and is referred to by QuadEltNode
 #[derive(Debug)]
-struct QuadElt {
+struct QuadElt<T> {
 
-    pub id: i32,
+    pub data: T,
 
-    pub rect: QuadRect
+    // rect is aabb rounded out to integers, and is used to find the nodes the element is in
+    pub rect: QuadRect,
+
+    pub aabb: Aabb,
+}
+
+
+// Returned by insert, used to remove or move the element again.
+// The generation is bumped when the element is removed, so a handle to it does not find a later element in the same slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QuadHandle {
+    index: i32,
+    generation: u32
 }
 
 #[derive(Debug)]
@@ -114,6 +127,109 @@ pub struct QuadPoint {
     pub y: i32
 }
 
+
+// Float bounding box, y up like QuadRect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+
+impl Aabb {
+
+    pub fn new(p1: na::Vector2::<f32>, p2: na::Vector2::<f32>) -> Self {
+        Aabb {
+            min_x: f32::min(p1.x, p2.x),
+            min_y: f32::min(p1.y, p2.y),
+            max_x: f32::max(p1.x, p2.x),
+            max_y: f32::max(p1.y, p2.y),
+        }
+    }
+
+    pub fn point(x: f32, y: f32) -> Self {
+        Aabb {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        }
+    }
+
+    // touching counts as intersecting
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min_x <= other.max_x && self.max_x >= other.min_x
+            && self.min_y <= other.max_y && self.max_y >= other.min_y
+    }
+
+    pub fn intersects_circle(&self, center: na::Vector2::<f32>, radius: f32) -> bool {
+        let closest_x = f32::max(self.min_x, f32::min(center.x, self.max_x));
+        let closest_y = f32::max(self.min_y, f32::min(center.y, self.max_y));
+
+        let dx = center.x - closest_x;
+        let dy = center.y - closest_y;
+
+        dx * dx + dy * dy <= radius * radius
+    }
+
+    // Distance along the ray to where it enters the box, 0 if it starts inside.
+    // dir should be normalized, None when the box is not hit within max_dist
+    pub fn ray_entry(&self, origin: na::Vector2::<f32>, dir: na::Vector2::<f32>, max_dist: f32) -> Option<f32> {
+
+        let mut t_min = 0.0;
+        let mut t_max = max_dist;
+
+        for (o, d, min, max) in &[(origin.x, dir.x, self.min_x, self.max_x), (origin.y, dir.y, self.min_y, self.max_y)] {
+            if *d == 0.0 {
+                // parallel to this axis, so it has to start between the sides
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let mut t1 = (min - o) / d;
+            let mut t2 = (max - o) / d;
+            if t1 > t2 {
+                std::mem::swap(&mut t1, &mut t2);
+            }
+
+            t_min = f32::max(t_min, t1);
+            t_max = f32::min(t_max, t2);
+
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+
+    // smallest integer rect containing the box
+    fn to_rect(&self) -> QuadRect {
+        QuadRect {
+            left: self.min_x.floor() as i32,
+            right: self.max_x.ceil() as i32,
+            top: self.max_y.ceil() as i32,
+            bottom: self.min_y.floor() as i32,
+        }
+    }
+}
+
+
+impl From<QuadRect> for Aabb {
+    fn from(rect: QuadRect) -> Aabb {
+        Aabb {
+            min_x: rect.left as f32,
+            min_y: rect.bottom as f32,
+            max_x: rect.right as f32,
+            max_y: rect.top as f32,
+        }
+    }
+}
+
 impl QuadRect {
 
     pub fn new(p1: QuadPoint, p2: QuadPoint) -> Self {
@@ -223,8 +339,11 @@ impl QuadRect {
 
 pub struct QuadTree<T> {
 
-    // All elements in quadTree
-    elements: FreeList<QuadElt>,
+    // All elements in quadTree, None when removed
+    elements: FreeList<Option<QuadElt<T>>>,
+
+    // generation of each element slot, kept when the elements are cleared
+    generations: Vec::<u32>,
 
     // All elementNodes in quadTree
     element_nodes: FreeList<QuadEltNode>,
@@ -234,14 +353,10 @@ pub struct QuadTree<T> {
     // First node is the root
     nodes: Vec::<QuadNode>,
 
-    data: Vec::<T>,
-
     // Rect for the root
     // All sub rects are computed on the fly in integers
     root_rect: QuadRect,
 
-    free_node: i32,
-
     max_depth: i32,
     nodes_per_cell: i32
 
@@ -261,34 +376,155 @@ impl<'a, T> QuadTree<T> {
 
         QuadTree {
             elements: FreeList::new(),
+            generations: Vec::new(),
             element_nodes: FreeList::new(),
             nodes,
-            data: Vec::new(),
             root_rect: rect,
-            free_node: 0,
             max_depth: 10,
             nodes_per_cell: 6
         }
     }
 
-    pub fn insert(&mut self, element: T, element_rect: QuadRect) {
+    // Elements outside the root rect are not found by any query
+    pub fn insert<R: Into<Aabb>>(&mut self, element: T, element_rect: R) -> QuadHandle {
+
+        let aabb = element_rect.into();
+        let rect = aabb.to_rect();
 
         //println!("inserting {:?}", element_rect);
-        // check if we can insert into root
+        let element_id = self.elements.insert(Some(QuadElt {
+            data: element,
+            rect: rect.clone(),
+            aabb
+        }));
 
-        self.data.push(element);
-        let data_id = (self.data.len() - 1) as i32;
+        //println!("Inserting node for element with id: {:?}", element_id);
+        let root_rect = self.root_rect.clone();
+        self.insert_elm(element_id, 0, &rect, &root_rect, 0);
 
+        if element_id as usize >= self.generations.len() {
+            self.generations.push(0);
+        }
 
+        QuadHandle {
+            index: element_id,
+            generation: self.generations[element_id as usize]
+        }
+    }
 
-        let element_id = self.elements.insert(QuadElt {
-            id: data_id,
-            rect: element_rect.clone()
-        });
 
-        //println!("Inserting node for element with id: {:?}", element_id);
-        let rect = self.root_rect.clone();
-        self.insert_elm(element_id, 0, &element_rect, &rect, 0);
+    pub fn get(&self, handle: QuadHandle) -> Option<&T> {
+        self.element(handle).map(|elt| &elt.data)
+    }
+
+
+    // None when the handle is removed, also when its slot has been reused
+    fn element(&self, handle: QuadHandle) -> Option<&QuadElt<T>> {
+        if handle.index < 0 || handle.index as usize >= self.elements.data.len() {
+            return None;
+        }
+
+        if self.generations[handle.index as usize] != handle.generation {
+            return None;
+        }
+
+        self.elements[handle.index].element.as_ref()
+    }
+
+
+    // Remove the element, returns its data or None if the handle is already removed
+    pub fn remove(&mut self, handle: QuadHandle) -> Option<T> {
+
+        let rect = self.element(handle)?.rect.clone();
+
+        let root_rect = self.root_rect.clone();
+        self.remove_elm(handle.index, 0, &rect, &root_rect);
+
+        let elt = self.elements[handle.index].element.take();
+        self.elements.erase(handle.index);
+        self.generations[handle.index as usize] += 1;
+
+        elt.map(|elt| elt.data)
+    }
+
+
+    // Move the element to a new rect, returns false if the handle is removed
+    pub fn update<R: Into<Aabb>>(&mut self, handle: QuadHandle, new_rect: R) -> bool {
+
+        let old_rect = match self.element(handle) {
+            Some(elt) => elt.rect.clone(),
+            None => return false
+        };
+
+        let root_rect = self.root_rect.clone();
+        self.remove_elm(handle.index, 0, &old_rect, &root_rect);
+
+        let aabb = new_rect.into();
+        let rect = aabb.to_rect();
+
+        if let Some(elt) = self.elements[handle.index].element.as_mut() {
+            elt.rect = rect.clone();
+            elt.aabb = aabb;
+        }
+
+        self.insert_elm(handle.index, 0, &rect, &root_rect, 0);
+
+        true
+    }
+
+
+    // Remove all elements, keeps the root rect
+    pub fn clear(&mut self) {
+        // every handle from before is stale
+        for generation in &mut self.generations {
+            *generation += 1;
+        }
+
+        self.elements.clear();
+        self.element_nodes.clear();
+        self.nodes.clear();
+        self.nodes.push(QuadNode::leaf());
+    }
+
+
+    // Unlink the element from all the leafs it is in, elements are in every leaf their rect touches
+    fn remove_elm(&mut self, element_id: i32, node_index: usize, element_rect: &QuadRect, node_rect: &QuadRect) {
+
+        if self.nodes[node_index].count > -1 {
+
+            let mut prev = -1;
+            let mut child_index = self.nodes[node_index].first_child;
+
+            while child_index != -1 {
+                let next = self.element_nodes[child_index].element.next;
+
+                if self.element_nodes[child_index].element.element == element_id {
+                    if prev == -1 {
+                        self.nodes[node_index].first_child = next;
+                    }
+                    else {
+                        self.element_nodes[prev].element.next = next;
+                    }
+
+                    self.element_nodes.erase(child_index);
+                    self.nodes[node_index].count -= 1;
+                    return;
+                }
+
+                prev = child_index;
+                child_index = next;
+            }
+        }
+        else {
+            let locations = QuadRect::element_quad_locations(node_rect, element_rect);
+
+            for i in 0..4 {
+                if locations[i] {
+                    let child_index = (self.nodes[node_index].first_child as usize) + i;
+                    self.remove_elm(element_id, child_index, element_rect, &node_rect.location_quad(i));
+                }
+            }
+        }
     }
 
 
@@ -368,7 +604,7 @@ impl<'a, T> QuadTree<T> {
 
             self.element_nodes.erase(next_child);
 
-            let child_rect = &self.elements[reallocated_id].element.rect;
+            let child_rect = &self.elements[reallocated_id].element.as_ref().unwrap().rect;
             let locations = QuadRect::element_quad_locations(node_rect, child_rect);
 
             for i in 0..4 {
@@ -402,13 +638,91 @@ impl<'a, T> QuadTree<T> {
         let mut res = Vec::new();
 
         for index in element_ids.into_iter() {
-            res.push(&self.data[index as usize]);
+            res.push(&self.elements[index].element.as_ref().unwrap().data);
         }
         res
 
     }
 
 
+    // Elements whose aabb intersects aabb, in handle order
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec::<&T> {
+        self.query_shape(&|b| b.intersects(aabb))
+    }
+
+
+    // Elements whose aabb is within radius of center, in handle order
+    pub fn query_circle(&self, center: na::Vector2::<f32>, radius: f32) -> Vec::<&T> {
+        self.query_shape(&|b| b.intersects_circle(center, radius))
+    }
+
+
+    // Elements whose aabb the ray passes through within max_dist, closest first.
+    // Returns the distance to where the ray enters each aabb
+    pub fn query_ray(&self, origin: na::Vector2::<f32>, dir: na::Vector2::<f32>, max_dist: f32) -> Vec::<(&T, f32)> {
+
+        let dir = match dir.try_normalize(0.0) {
+            Some(dir) => dir,
+            None => return Vec::new()
+        };
+
+        let mut element_ids = std::collections::BTreeSet::new();
+        self.query_shape_node(0, &self.root_rect, &|b| b.ray_entry(origin, dir, max_dist).is_some(), &mut element_ids);
+
+        let mut res: Vec::<(&T, f32)> = element_ids.into_iter()
+            .filter_map(|index| {
+                let elt = self.elements[index].element.as_ref().unwrap();
+                elt.aabb.ray_entry(origin, dir, max_dist).map(|t| (&elt.data, t))
+            })
+            .collect();
+
+        // stable sort, so same distance is still in handle order
+        res.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        res
+    }
+
+
+    fn query_shape(&self, overlaps: &dyn Fn(&Aabb) -> bool) -> Vec::<&T> {
+
+        let mut element_ids = std::collections::BTreeSet::new();
+        self.query_shape_node(0, &self.root_rect, overlaps, &mut element_ids);
+
+        element_ids.into_iter()
+            .map(|index| &self.elements[index].element.as_ref().unwrap().data)
+            .collect()
+    }
+
+
+    // Look in the nodes the shape overlaps, and keep the elements it overlaps
+    fn query_shape_node(&self, node_index: usize, node_rect: &QuadRect, overlaps: &dyn Fn(&Aabb) -> bool, element_ids: &mut std::collections::BTreeSet::<i32>) {
+
+        if !overlaps(&Aabb::from(node_rect.clone())) {
+            return;
+        }
+
+        if self.nodes[node_index].count > -1 {
+            let mut child_index = self.nodes[node_index].first_child;
+
+            while child_index != -1 {
+                let element_id = self.element_nodes[child_index].element.element;
+
+                if overlaps(&self.elements[element_id].element.as_ref().unwrap().aabb) {
+                    element_ids.insert(element_id);
+                }
+
+                child_index = self.element_nodes[child_index].element.next;
+            }
+        }
+        else {
+            for i in 0..4 {
+                let child_index = (self.nodes[node_index].first_child as usize) + i;
+                self.query_shape_node(child_index, &node_rect.location_quad(i), overlaps, element_ids);
+            }
+        }
+    }
+
+
     fn query_node_box(&self, node_index: usize, node_rect: &QuadRect, query: &Query, data_vec: &mut std::collections::HashSet::<i32>) {
         // leaf, return  all elements
         if self.nodes[node_index].count > -1 {
@@ -416,7 +730,7 @@ impl<'a, T> QuadTree<T> {
             let mut child_index = self.nodes[node_index].first_child;
 
             while child_index != -1 {
-                data_vec.insert(self.elements[self.element_nodes[child_index].element.element].element.id);
+                data_vec.insert(self.element_nodes[child_index].element.element);
 
                 child_index = self.element_nodes[child_index].element.next;
 
@@ -716,4 +1030,209 @@ mod test {
 
         assert!(points.len() == 144)
     }
+
+
+
+    // small lcg, so the tests are the same every run without a rand dependency
+    struct TestRandom(u64);
+
+    impl TestRandom {
+        fn next_f32(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 40) as f32) / ((1u64 << 24) as f32)
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + self.next_f32() * (max - min)
+        }
+
+        fn aabb(&mut self) -> Aabb {
+            let x = self.range(-120.0, 120.0);
+            let y = self.range(-120.0, 120.0);
+            let w = self.range(0.0, 8.0);
+            let h = self.range(0.0, 8.0);
+            Aabb::new(na::Vector2::new(x, y), na::Vector2::new(x + w, y + h))
+        }
+    }
+
+
+    // A tree with random elements, some removed and some moved, and the same elements in a vec
+    fn random_tree(random: &mut TestRandom) -> (QuadTree::<usize>, Vec::<(usize, Aabb)>) {
+
+        let rect = QuadRect::new(QuadPoint {x: -128, y: -128}, QuadPoint { x: 128, y: 128} );
+        let mut qt = QuadTree::<usize>::new(rect);
+
+        let mut handles = Vec::new();
+        let mut aabbs = Vec::new();
+        for i in 0..600 {
+            let aabb = random.aabb();
+            handles.push(qt.insert(i, aabb));
+            aabbs.push(Some(aabb));
+        }
+
+        for i in (0..600).step_by(3) {
+            assert_eq!(qt.remove(handles[i]), Some(i));
+            aabbs[i] = None;
+        }
+
+        for i in (1..600).step_by(4) {
+            if aabbs[i].is_some() {
+                let aabb = random.aabb();
+                assert!(qt.update(handles[i], aabb));
+                aabbs[i] = Some(aabb);
+            }
+        }
+
+        // reuses removed slots
+        for i in 600..700 {
+            let aabb = random.aabb();
+            qt.insert(i, aabb);
+            aabbs.push(Some(aabb));
+        }
+
+        let elements = aabbs.into_iter().enumerate().filter_map(|(i, aabb)| aabb.map(|a| (i, a))).collect();
+
+        (qt, elements)
+    }
+
+
+    fn sorted(res: Vec::<&usize>) -> Vec::<usize> {
+        let mut res: Vec::<usize> = res.into_iter().cloned().collect();
+        res.sort();
+        res
+    }
+
+
+    #[test]
+    fn query_aabb_matches_brute_force() {
+        let mut random = TestRandom(1);
+        let (qt, elements) = random_tree(&mut random);
+
+        for _ in 0..200 {
+            let query = random.aabb();
+
+            let expected: Vec::<usize> = elements.iter().filter(|(_, a)| a.intersects(&query)).map(|(i, _)| *i).collect();
+
+            assert_eq!(sorted(qt.query_aabb(&query)), expected);
+        }
+    }
+
+
+    #[test]
+    fn query_circle_matches_brute_force() {
+        let mut random = TestRandom(2);
+        let (qt, elements) = random_tree(&mut random);
+
+        for _ in 0..200 {
+            let center = na::Vector2::new(random.range(-130.0, 130.0), random.range(-130.0, 130.0));
+            let radius = random.range(0.0, 20.0);
+
+            let expected: Vec::<usize> = elements.iter().filter(|(_, a)| a.intersects_circle(center, radius)).map(|(i, _)| *i).collect();
+
+            assert_eq!(sorted(qt.query_circle(center, radius)), expected);
+        }
+    }
+
+
+    #[test]
+    fn query_ray_matches_brute_force() {
+        let mut random = TestRandom(3);
+        let (qt, elements) = random_tree(&mut random);
+
+        for i in 0..200 {
+            let origin = na::Vector2::new(random.range(-130.0, 130.0), random.range(-130.0, 130.0));
+            // also axis aligned rays
+            let dir = match i % 10 {
+                0 => na::Vector2::new(1.0, 0.0),
+                1 => na::Vector2::new(0.0, -1.0),
+                _ => na::Vector2::new(random.range(-1.0, 1.0), random.range(-1.0, 1.0)),
+            };
+            let max_dist = random.range(0.0, 150.0);
+
+            let res = qt.query_ray(origin, dir, max_dist);
+
+            let dir = dir.normalize();
+            let expected: Vec::<(usize, f32)> = elements.iter()
+                .filter_map(|(i, a)| a.ray_entry(origin, dir, max_dist).map(|t| (*i, t)))
+                .collect();
+
+            let mut found: Vec::<(usize, f32)> = res.iter().map(|(i, t)| (**i, *t)).collect();
+            found.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(found, expected);
+
+            // closest first
+            for pair in res.windows(2) {
+                assert!(pair[0].1 <= pair[1].1);
+            }
+        }
+    }
+
+
+    #[test]
+    fn ray_entry_distance() {
+        let aabb = Aabb::new(na::Vector2::new(2.0, -1.0), na::Vector2::new(4.0, 1.0));
+
+        assert_eq!(aabb.ray_entry(na::Vector2::new(0.0, 0.0), na::Vector2::new(1.0, 0.0), 10.0), Some(2.0));
+        assert_eq!(aabb.ray_entry(na::Vector2::new(3.0, 0.0), na::Vector2::new(1.0, 0.0), 10.0), Some(0.0));
+        assert_eq!(aabb.ray_entry(na::Vector2::new(0.0, 0.0), na::Vector2::new(1.0, 0.0), 1.5), None);
+        assert_eq!(aabb.ray_entry(na::Vector2::new(0.0, 2.0), na::Vector2::new(1.0, 0.0), 10.0), None);
+        assert_eq!(aabb.ray_entry(na::Vector2::new(5.0, 0.0), na::Vector2::new(1.0, 0.0), 10.0), None);
+    }
+
+
+    #[test]
+    fn remove_update_and_clear() {
+        let rect = QuadRect::new(QuadPoint {x: -128, y: -128}, QuadPoint { x: 128, y: 128} );
+        let mut qt = QuadTree::<usize>::new(rect);
+
+        let a = qt.insert(1, Aabb::new(na::Vector2::new(10.5, 10.5), na::Vector2::new(11.5, 11.5)));
+        let b = qt.insert(2, Aabb::new(na::Vector2::new(-20.0, -20.0), na::Vector2::new(-19.0, -19.0)));
+
+        assert_eq!(sorted(qt.query_aabb(&Aabb::point(11.0, 11.0))), vec![1]);
+
+        // moved element is only found at the new place
+        assert!(qt.update(a, Aabb::new(na::Vector2::new(-20.5, -20.5), na::Vector2::new(-19.5, -19.5))));
+        assert!(qt.query_aabb(&Aabb::point(11.0, 11.0)).is_empty());
+        assert_eq!(sorted(qt.query_aabb(&Aabb::point(-19.75, -19.75))), vec![1, 2]);
+
+        assert_eq!(qt.remove(b), Some(2));
+        assert_eq!(qt.remove(b), None);
+        assert!(!qt.update(b, Aabb::point(0.0, 0.0)));
+        assert_eq!(qt.get(b), None);
+        assert_eq!(sorted(qt.query_aabb(&Aabb::point(-19.75, -19.75))), vec![1]);
+
+        qt.clear();
+        assert!(qt.query_aabb(&Aabb::new(na::Vector2::new(-128.0, -128.0), na::Vector2::new(128.0, 128.0))).is_empty());
+        assert_eq!(qt.get(a), None);
+
+        let c = qt.insert(3, Aabb::point(0.5, 0.5));
+        assert_eq!(qt.get(c), Some(&3));
+    }
+
+
+    #[test]
+    fn stale_handle_is_rejected() {
+        let rect = QuadRect::new(QuadPoint {x: -128, y: -128}, QuadPoint { x: 128, y: 128} );
+        let mut qt = QuadTree::<usize>::new(rect);
+
+        let a = qt.insert(1, Aabb::point(0.5, 0.5));
+        assert_eq!(qt.remove(a), Some(1));
+
+        // b reuses the slot of a
+        let b = qt.insert(2, Aabb::point(10.5, 10.5));
+        assert_ne!(a, b);
+
+        assert_eq!(qt.get(a), None);
+        assert!(!qt.update(a, Aabb::point(-10.5, -10.5)));
+        assert_eq!(qt.remove(a), None);
+
+        // b is untouched by the stale handle
+        assert_eq!(qt.get(b), Some(&2));
+        assert_eq!(sorted(qt.query_aabb(&Aabb::point(10.5, 10.5))), vec![2]);
+
+        qt.clear();
+        let c = qt.insert(3, Aabb::point(0.5, 0.5));
+        assert_eq!(qt.get(b), None);
+        assert_eq!(qt.get(c), Some(&3));
+    }
 }
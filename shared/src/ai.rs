@@ -1,5 +1,6 @@
 use crate::base_entity::*;
 use crate::behaviours::*;
+use crate::random::*;
 
 
 pub struct AiRunData<'a> {
@@ -17,7 +18,8 @@ pub trait Ai<T> {
 pub struct RegularEnemyState {
 
     pub distance: f32,
-    pub current_behaviour: Behaviour
+    pub current_behaviour: Behaviour,
+    pub random: Random,
 }
 
 
@@ -29,11 +31,12 @@ pub enum EntityAi {
 
 impl EntityAi {
 
-    pub fn regular_enemy(distance: f32) -> Self {
+    pub fn regular_enemy(distance: f32, seed: u64) -> Self {
         let data =
             RegularEnemyState {
                 distance,
-                current_behaviour: Behaviour::Empty
+                current_behaviour: Behaviour::Empty,
+                random: Random::new(seed),
 
             };
         EntityAi::RegularEnemy(data)
@@ -1,22 +1,24 @@
 use crate::base_entity::*;
+use crate::weapon::*;
 
 
 #[derive(Clone)]
 pub struct BaseEntity {
     pub physics: Physics,
     pub health: Health,
+    pub stamina: Stamina,
     pub state: EntityState,
     pub is_hit: bool,
     pub queued_action: Option<EntityState>,
+    // stats of the weapon in hand, None is unarmed
+    pub weapon: Option<WeaponStats>,
 }
 
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct AttackInfo {
+    // which attack of the weapons combo
     pub combo_num: usize,
-    pub hit_start_frame: usize,
-    pub hit_end_frame: usize,
-
 }
 
 
@@ -34,10 +36,32 @@ impl BaseEntity {
         BaseEntity {
             physics: Physics::new(),
             health: Health::new(100.0),
+            stamina: Stamina::new(100.0, 25.0),
             state: EntityState::Idle,
             is_hit: false,
             queued_action: None,
+            weapon: None,
         }
     }
 
+
+    // unarmed attacks use the default stats
+    pub fn weapon_stats(&self) -> WeaponStats {
+        self.weapon.clone().unwrap_or_default()
+    }
+
+
+    // Queue attack combo_num of the weapon. False when there is not stamina for it
+    pub fn queue_attack(&mut self, combo_num: usize) -> bool {
+
+        let weapon = self.weapon_stats();
+
+        if !self.stamina.spend(weapon.stamina_cost) {
+            return false;
+        }
+
+        self.queued_action = Some(EntityState::Attack(weapon.attack_info(combo_num)));
+        true
+    }
+
 }
@@ -1,6 +1,7 @@
 #[derive(Copy, Clone)]
 pub struct Health {
-    health: f32
+    health: f32,
+    max_health: f32,
 }
 
 
@@ -8,7 +9,8 @@ impl Health {
     pub fn new(max_health: f32) -> Self {
         Health {
 
-            health: max_health
+            health: max_health,
+            max_health
         }
     }
 
@@ -17,6 +19,15 @@ impl Health {
         self.health
     }
 
+    pub fn max_health(&self) -> f32 {
+        self.max_health
+    }
+
+    // back to full health, fx on respawn
+    pub fn restore(&mut self) {
+        self.health = self.max_health;
+    }
+
     pub fn damage(&mut self, dmg: f32) -> bool {
 
         self.health -= dmg;
@@ -6,3 +6,6 @@ pub use self::physics::{Physics};
 
 mod health;
 pub use self::health::{Health};
+
+mod stamina;
+pub use self::stamina::{Stamina};
@@ -10,8 +10,16 @@ pub struct Physics {
     pub scale: f32,
     //
     pub inverse_mass: f32,
+    // bounciness in impulse collisions, 0 stops dead and 1 keeps all the speed
+    pub restitution: f32,
+    pub friction: f32,
     pub anchor_id: Option<usize>,
-    pub falling: bool,
+    // standing on walkable ground, set by the character controller
+    pub grounded: bool,
+    // normal of the ground under the entity, straight up when in the air
+    pub ground_normal: na::Vector3<f32>,
+    // seconds since last grounded, 0 while grounded
+    pub time_since_grounded: f32,
 }
 
 impl Physics {
@@ -24,9 +32,13 @@ impl Physics {
             na::Vector3::<f32>::new(0.0, 0.0, 0.0),
             max_speed: 5.0,
             inverse_mass: 1.0,
+            restitution: 0.2,
+            friction: 0.6,
             scale: 1.0,
             anchor_id: None,
-            falling: true,
+            grounded: false,
+            ground_normal: na::Vector3::<f32>::new(0.0, 0.0, 1.0),
+            time_since_grounded: 0.0,
         }
     }
 
@@ -57,6 +69,20 @@ impl Physics {
     }
 
 
+    // Physics between previous and self, alpha 0 is previous and 1 is self.
+    // Used to render between two fixed simulation ticks
+    pub fn interpolate(&self, previous: &Physics, alpha: f32) -> Physics {
+        let mut res = *self;
+
+        res.pos = previous.pos.lerp(&self.pos, alpha);
+
+        // slerp is undefined for opposite rotations, just use the current one then
+        res.rotation = previous.rotation.try_slerp(&self.rotation, alpha, 1.0e-6).unwrap_or(self.rotation);
+
+        res
+    }
+
+
     pub fn calculate_model_mat(&self) -> na::Matrix4::<f32> {
 
         let scale_mat = na::Matrix4::<f32>::new(
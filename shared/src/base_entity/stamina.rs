@@ -0,0 +1,45 @@
+#[derive(Copy, Clone)]
+pub struct Stamina {
+    stamina: f32,
+    max_stamina: f32,
+    // pr second
+    regeneration: f32,
+}
+
+
+impl Stamina {
+    pub fn new(max_stamina: f32, regeneration: f32) -> Self {
+        Stamina {
+            stamina: max_stamina,
+            max_stamina,
+            regeneration,
+        }
+    }
+
+
+    pub fn stamina(&self) -> f32 {
+        self.stamina
+    }
+
+    pub fn max_stamina(&self) -> f32 {
+        self.max_stamina
+    }
+
+    pub fn restore(&mut self) {
+        self.stamina = self.max_stamina;
+    }
+
+    // false and nothing used when there is not enough stamina
+    pub fn spend(&mut self, cost: f32) -> bool {
+        if self.stamina < cost {
+            return false;
+        }
+
+        self.stamina -= cost;
+        true
+    }
+
+    pub fn regenerate(&mut self, delta: f32) {
+        self.stamina = f32::min(self.stamina + self.regeneration * delta, self.max_stamina);
+    }
+}
@@ -12,3 +12,11 @@ pub use self::behaviours::*;
 
 
 pub mod physics_functions;
+
+mod random;
+
+pub use self::random::*;
+
+mod weapon;
+
+pub use self::weapon::*;
@@ -0,0 +1,31 @@
+// Small deterministic random generator (xorshift64*), used by the ais instead of thread_rng
+// so the same seed and inputs always give the same game
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Random {
+    state: u64
+}
+
+
+impl Random {
+
+    pub fn new(seed: u64) -> Self {
+        // state can not be 0, so mix the seed with a constant
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+
+        Random {
+            state: if state == 0 { 1 } else { state }
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+use crate::base_entity::*;
+
+
+// What a weapon does when attacking, loaded from assets/weapons/<weapon name>.ron
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WeaponStats {
+    #[serde(default = "default_damage")]
+    pub damage: f32,
+    // how far from the target an attack can start
+    #[serde(default = "default_reach")]
+    pub reach: f32,
+    #[serde(default = "default_stamina_cost")]
+    pub stamina_cost: f32,
+    // seconds the animations of attacker and target pause when a hit lands
+    #[serde(default)]
+    pub hit_stop: f32,
+    // the combo, each attack is followed by the next and the last by the first
+    #[serde(default = "default_attacks")]
+    pub attacks: Vec<AttackStats>,
+}
+
+
+// Frame numbers are in the attack animation, for weapons whose animations have no hit and combo events
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AttackStats {
+    // name of the animation clip, the first attack plays attack by default and the rest attack_follow
+    #[serde(default)]
+    pub animation: Option<String>,
+    // where the weapon can hit
+    pub hit_start_frame: usize,
+    pub hit_end_frame: usize,
+    // where attacking again queues the next attack in the combo
+    pub combo_start_frame: usize,
+    pub combo_end_frame: usize,
+}
+
+
+fn default_damage() -> f32 {
+    25.0
+}
+
+fn default_reach() -> f32 {
+    2.0
+}
+
+fn default_stamina_cost() -> f32 {
+    20.0
+}
+
+fn default_attacks() -> Vec<AttackStats> {
+    vec![AttackStats::default(), AttackStats::default()]
+}
+
+
+impl Default for AttackStats {
+    fn default() -> Self {
+        AttackStats {
+            animation: None,
+            hit_start_frame: 9,
+            hit_end_frame: 20,
+            combo_start_frame: 9,
+            combo_end_frame: 30,
+        }
+    }
+}
+
+
+impl Default for WeaponStats {
+    fn default() -> Self {
+        WeaponStats {
+            damage: default_damage(),
+            reach: default_reach(),
+            stamina_cost: default_stamina_cost(),
+            hit_stop: 0.0,
+            attacks: default_attacks(),
+        }
+    }
+}
+
+
+impl WeaponStats {
+
+    // combo_num wraps around, so the attack after the last is the first
+    pub fn attack_info(&self, combo_num: usize) -> AttackInfo {
+
+        AttackInfo {
+            combo_num: combo_num % usize::max(self.attacks.len(), 1),
+        }
+    }
+
+
+    pub fn attack_animation(&self, combo_num: usize) -> String {
+
+        let named = self.attacks.get(combo_num).and_then(|attack| attack.animation.clone());
+
+        match (named, combo_num) {
+            (Some(name), _) => name,
+            (None, 0) => "attack".to_string(),
+            (None, _) => "attack_follow".to_string(),
+        }
+    }
+}